@@ -0,0 +1,256 @@
+use crate::data::PROJECT_DIRS;
+use crate::filter::Filter;
+use crate::live::credential::Secret;
+use anyhow::{Context, Result, bail};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub rooms: Vec<RoomConfig>,
+    pub server: Option<ServerConfig>,
+    #[serde(default = "default_sinks")]
+    pub sinks: Vec<SinkConfig>,
+    #[serde(default = "default_metrics_window")]
+    pub metrics_window: u64, // 互动指标的滑动窗口（秒）
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoomConfig {
+    pub room_id: String,         // 房间号
+    pub account: Option<String>, // 优先使用的账号
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerConfig {
+    pub listen: String, // 监听地址，如 127.0.0.1:9090
+    #[serde(default)]
+    pub overlay: OverlayConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OverlayConfig {
+    #[serde(skip_serializing)]
+    pub css: Option<PathBuf>, // 自定义样式表，覆盖默认主题
+    pub show: Vec<String>,  // 显示的消息类型
+    pub max_danmaku: usize, // 同时显示的弹幕条数
+    pub danmaku_ttl: u64,   // 弹幕停留时间（秒）
+    pub gift_ttl: u64,      // 礼物停留时间（秒）
+    pub guard_ttl: u64,     // 上舰横幅停留时间（秒）
+}
+
+impl Default for OverlayConfig {
+    fn default() -> Self {
+        Self {
+            css: None,
+            show: ["danmaku", "gift", "super_chat", "guard_buy"]
+                .map(String::from)
+                .to_vec(),
+            max_danmaku: 20,
+            danmaku_ttl: 30,
+            gift_ttl: 10,
+            guard_ttl: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    #[serde(default = "default_queue")]
+    pub queue: usize, // 队列长度，满了以后归档类 sink 暂存新事件，其余丢弃
+    pub filter: Option<Filter>, // 只接收匹配的事件
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkKind {
+    RawLog,                 // 原始消息，写入 raw.jsonl
+    NormalizedLog,          // 解析后的消息，写入 normalized.jsonl
+    Sqlite,                 // 写入 live.db 的 events 表
+    Users,                  // 汇总到 live.db 的用户档案
+    Webhook(WebhookConfig), // 批量 POST 到 HTTP 接口
+    Alerts {
+        // 告警规则
+        rules: Vec<AlertRule>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,      // 推送地址
+    pub secret: Option<Secret>, // HMAC-SHA256 签名密钥
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize, // 每批最多事件数
+    #[serde(default = "default_batch_interval")]
+    pub batch_interval: u64, // 攒批最长等待时间（毫秒）
+    #[serde(default = "default_retries")]
+    pub retries: u32, // 失败后的重试次数，之后写入死信队列
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRule {
+    pub name: String,                  // 规则名
+    pub when: Filter,                  // 触发条件
+    pub actions: Vec<AlertAction>,     // 触发后的动作
+    pub rate_limit: Option<RateLimit>, // 频率限制
+    pub dedupe: Option<u64>,           // 去重窗口（秒），窗口内同一用户的相同内容只告警一次
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimit {
+    pub count: usize, // 窗口内最多告警次数
+    pub seconds: u64, // 窗口长度（秒）
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertAction {
+    Stdout, // 打印到标准输出
+    Webhook {
+        // POST 告警 JSON
+        url: String,
+    },
+    Command {
+        // 执行命令，告警 JSON 写入 stdin
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+fn default_metrics_window() -> u64 {
+    60
+}
+
+fn default_queue() -> usize {
+    1024
+}
+
+fn default_batch_size() -> usize {
+    50
+}
+
+fn default_batch_interval() -> u64 {
+    1000
+}
+
+fn default_retries() -> u32 {
+    5
+}
+
+// what used to be hard-coded before sinks were configurable
+fn default_sinks() -> Vec<SinkConfig> {
+    [SinkKind::RawLog, SinkKind::Sqlite, SinkKind::Users]
+        .map(|kind| SinkConfig {
+            kind,
+            queue: default_queue(),
+            filter: None,
+        })
+        .to_vec()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rooms: Vec::new(),
+            server: None,
+            sinks: default_sinks(),
+            metrics_window: default_metrics_window(),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> PathBuf {
+        env::var("CONFIG_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PROJECT_DIRS.config_dir().join("config.json"))
+    }
+
+    // falls back to a single room from `ROOM_ID` / `ACCOUNT` when there is no config file
+    pub fn load() -> Result<Config> {
+        let path = Self::path();
+
+        let mut config: Config = if path.exists() {
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read config {}", path.display()))?;
+
+            serde_json::from_str(&content)
+                .with_context(|| format!("failed to parse config {}", path.display()))?
+        } else {
+            Config::default()
+        };
+
+        if config.metrics_window == 0 {
+            bail!("metrics_window must be at least one second")
+        }
+
+        for room in mem::take(&mut config.rooms) {
+            config.add_room(room);
+        }
+
+        if let Ok(room_id) = env::var("ROOM_ID") {
+            config.add_room(RoomConfig {
+                room_id,
+                account: env::var("ACCOUNT").ok(),
+            });
+        }
+
+        Ok(config)
+    }
+
+    // every room is watched once, the first entry for it wins
+    fn add_room(&mut self, room: RoomConfig) {
+        if self.rooms.iter().any(|x| x.room_id == room.room_id) {
+            warn!(
+                "room {} is listed more than once, keeping the first",
+                room.room_id
+            );
+        } else {
+            self.rooms.push(room);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watches_every_room_once() {
+        let mut config: Config = serde_json::from_str(
+            r#"{"rooms": [
+                {"room_id": "21452505", "account": "main"},
+                {"room_id": "7777"},
+                {"room_id": "21452505"}
+            ]}"#,
+        )
+        .unwrap();
+
+        for room in mem::take(&mut config.rooms) {
+            config.add_room(room);
+        }
+
+        // like ROOM_ID for a room that is already configured
+        config.add_room(RoomConfig {
+            room_id: "7777".into(),
+            account: Some("alt".into()),
+        });
+
+        let rooms: Vec<_> = config
+            .rooms
+            .iter()
+            .map(|x| (x.room_id.as_str(), x.account.as_deref()))
+            .collect();
+
+        assert_eq!(rooms, [("21452505", Some("main")), ("7777", None)]);
+    }
+}
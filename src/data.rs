@@ -1,5 +1,9 @@
-mod database;
+pub mod credentials;
+pub mod database;
 pub mod logger;
+pub mod quarantine;
+pub mod search;
+pub mod users;
 
 use directories::ProjectDirs;
 use once_cell::sync::Lazy;
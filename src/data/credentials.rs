@@ -0,0 +1,146 @@
+use crate::data::PROJECT_DIRS;
+use crate::live::credential::{Credential, read_secret_file};
+use anyhow::{Context, Result, bail};
+use log::warn;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// One file per account under `<config dir>/accounts`, named after the account and holding
+// either a Cookie header or a Netscape cookies.txt. Files must only be readable by the owner.
+pub struct CredentialStore {
+    dir: PathBuf,
+}
+
+impl Default for CredentialStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CredentialStore {
+    pub fn new() -> Self {
+        Self::open(PROJECT_DIRS.config_dir().join("accounts"))
+    }
+
+    pub fn open(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn load(&self) -> Result<Vec<(String, Credential)>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        check_permissions(&self.dir)?;
+
+        let mut accounts = Vec::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+
+            if !path.is_file() {
+                continue;
+            }
+
+            let Some(name) = path.file_stem().and_then(|x| x.to_str()) else {
+                warn!(
+                    "skipping account file with invalid name: {}",
+                    path.display()
+                );
+                continue;
+            };
+
+            let credential = Self::load_account(&path)
+                .with_context(|| format!("failed to load account {name}"))?;
+
+            accounts.push((name.to_owned(), credential));
+        }
+
+        accounts.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(accounts)
+    }
+
+    fn load_account(path: &Path) -> Result<Credential> {
+        check_permissions(path)?;
+
+        let content = read_secret_file(path)?;
+
+        if content.lines().any(|line| line.split('\t').count() == 7) {
+            Credential::from_cookies_txt(&content)
+        } else {
+            Credential::from_cookie_header(content.trim())
+        }
+    }
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)?.permissions().mode();
+
+    if mode & 0o077 != 0 {
+        bail!(
+            "{} is accessible by other users (mode {:o}), restrict it to the owner",
+            path.display(),
+            mode & 0o777
+        )
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn chmod(path: &Path, mode: u32) {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn loads_accounts_only_readable_by_the_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CredentialStore::open(dir.path().into());
+        let main = dir.path().join("main.txt");
+        let alt = dir.path().join("alt.txt");
+
+        chmod(dir.path(), 0o700);
+
+        fs::write(&main, "SESSDATA=m41n; DedeUserID=42\n").unwrap();
+        fs::write(
+            &alt,
+            ".bilibili.com\tTRUE\t/\tTRUE\t0\tSESSDATA\t4lt\n.bilibili.com\tTRUE\t/\tFALSE\t0\tDedeUserID\t43\n",
+        )
+        .unwrap();
+
+        chmod(&main, 0o600);
+        chmod(&alt, 0o600);
+
+        let accounts = store.load().unwrap();
+        let names: Vec<_> = accounts
+            .iter()
+            .map(|(name, credential)| (name.as_str(), credential.uid()))
+            .collect();
+
+        assert_eq!(names, [("alt", Some(43)), ("main", Some(42))]);
+
+        chmod(&alt, 0o644);
+        assert!(store.load().is_err());
+
+        chmod(&alt, 0o600);
+        chmod(dir.path(), 0o755);
+        assert!(store.load().is_err());
+
+        // nothing to check when there is no directory
+        let missing = CredentialStore::open(dir.path().join("missing"));
+        assert!(missing.load().unwrap().is_empty());
+    }
+}
@@ -1,21 +1,287 @@
+use crate::data::PROJECT_DIRS;
+use crate::stats::metrics::MetricsSnapshot;
 use anyhow::Result;
-use rusqlite::Connection;
-use std::path::Path;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize)]
+pub struct SessionRecord {
+    pub id: i64,
+    pub room_id: String,
+    pub started_at: i64,            // 开播时间（毫秒）
+    pub ended_at: Option<i64>,      // 停播时间（毫秒）
+    pub start_reason: String,       // 开播判定依据
+    pub end_reason: Option<String>, // 停播判定依据
+}
+
+#[derive(Debug)]
+pub struct EventRecord {
+    pub id: i64,
+    pub room_id: String,
+    pub session_id: Option<i64>,
+    pub timestamp: i64, // 毫秒
+    pub kind: String,
+    pub data: Value,
+}
 
 pub struct LivePersist {
     conn: Connection,
 }
 
 impl LivePersist {
+    pub fn path() -> PathBuf {
+        PROJECT_DIRS.data_dir().join("live.db")
+    }
+
     pub fn new(file: &dyn AsRef<Path>) -> Result<LivePersist> {
+        if let Some(parent) = file.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+
         let conn = Connection::open(file)?;
 
         conn.pragma_update(None, "journal_mode", "WAL")?;
         conn.pragma_update(None, "synchronous", "NORMAL")?;
         conn.pragma_update(None, "busy_timeout", "5000")?;
 
-        // Todo: create tables
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS sessions (
+                id           INTEGER PRIMARY KEY AUTOINCREMENT,
+                room_id      TEXT    NOT NULL,
+                started_at   INTEGER NOT NULL,
+                ended_at     INTEGER,
+                start_reason TEXT    NOT NULL,
+                end_reason   TEXT
+            );
+
+            CREATE INDEX IF NOT EXISTS sessions_room ON sessions (room_id, started_at);
+
+            CREATE TABLE IF NOT EXISTS events (
+                id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                room_id    TEXT    NOT NULL,
+                session_id INTEGER REFERENCES sessions (id),
+                timestamp  INTEGER NOT NULL,
+                kind       TEXT    NOT NULL,
+                data       TEXT    NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS events_room ON events (room_id, timestamp);
+            CREATE INDEX IF NOT EXISTS events_session ON events (session_id);
+
+            CREATE TABLE IF NOT EXISTS metrics (
+                id              INTEGER PRIMARY KEY AUTOINCREMENT,
+                room_id         TEXT    NOT NULL,
+                session_id      INTEGER REFERENCES sessions (id),
+                timestamp       INTEGER NOT NULL,
+                window          INTEGER NOT NULL,
+                danmaku_rate    REAL    NOT NULL,
+                unique_chatters INTEGER NOT NULL,
+                joins           INTEGER NOT NULL,
+                follows         INTEGER NOT NULL,
+                shares          INTEGER NOT NULL,
+                likes           INTEGER NOT NULL,
+                coin_rate       REAL    NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS metrics_room ON metrics (room_id, timestamp);
+            ",
+        )?;
 
         Ok(Self { conn })
     }
+
+    pub fn open_session(&self, room_id: &str, started_at: i64, reason: &str) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO sessions (room_id, started_at, start_reason) VALUES (?1, ?2, ?3)",
+            params![room_id, started_at, reason],
+        )?;
+
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn close_session(&self, id: i64, ended_at: i64, reason: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET ended_at = ?2, end_reason = ?3 WHERE id = ?1",
+            params![id, ended_at, reason],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn current_session(&self, room_id: &str) -> Result<Option<SessionRecord>> {
+        let session = self
+            .conn
+            .query_row(
+                "SELECT * FROM sessions WHERE room_id = ?1 AND ended_at IS NULL
+                 ORDER BY started_at DESC LIMIT 1",
+                params![room_id],
+                Self::session_from_row,
+            )
+            .optional()?;
+
+        Ok(session)
+    }
+
+    pub fn session(&self, id: i64) -> Result<Option<SessionRecord>> {
+        let session = self
+            .conn
+            .query_row(
+                "SELECT * FROM sessions WHERE id = ?1",
+                params![id],
+                Self::session_from_row,
+            )
+            .optional()?;
+
+        Ok(session)
+    }
+
+    // sessions of a room overlapping with [from, to)
+    pub fn sessions_between(
+        &self,
+        room_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<SessionRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM sessions
+             WHERE room_id = ?1 AND started_at < ?3 AND (ended_at IS NULL OR ended_at >= ?2)
+             ORDER BY started_at",
+        )?;
+
+        let sessions = stmt
+            .query_map(params![room_id, from, to], Self::session_from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(sessions)
+    }
+
+    pub fn insert_event(
+        &self,
+        room_id: &str,
+        session_id: Option<i64>,
+        timestamp: i64,
+        kind: &str,
+        data: &Value,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO events (room_id, session_id, timestamp, kind, data)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![room_id, session_id, timestamp, kind, data.to_string()],
+        )?;
+
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn insert_metrics(
+        &self,
+        room_id: &str,
+        session_id: Option<i64>,
+        snapshot: &MetricsSnapshot,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO metrics (
+                room_id, session_id, timestamp, window, danmaku_rate, unique_chatters,
+                joins, follows, shares, likes, coin_rate
+             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                room_id,
+                session_id,
+                snapshot.timestamp,
+                snapshot.window,
+                snapshot.danmaku_rate,
+                snapshot.unique_chatters,
+                snapshot.joins,
+                snapshot.follows,
+                snapshot.shares,
+                snapshot.likes,
+                snapshot.coin_rate,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn last_event_time(&self, room_id: &str) -> Result<Option<i64>> {
+        let timestamp = self.conn.query_row(
+            "SELECT MAX(timestamp) FROM events WHERE room_id = ?1",
+            params![room_id],
+            |row| row.get(0),
+        )?;
+
+        Ok(timestamp)
+    }
+
+    pub fn session_events(&self, session_id: i64) -> Result<Vec<EventRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, room_id, session_id, timestamp, kind, data FROM events
+             WHERE session_id = ?1 ORDER BY timestamp, id",
+        )?;
+
+        let events = stmt
+            .query_map(params![session_id], Self::event_from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(events)
+    }
+
+    // events of a room in [from, to) in time order, an empty `kinds` means every kind
+    pub fn for_each_event<F>(
+        &self,
+        room_id: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+        kinds: &[String],
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(EventRecord) -> Result<()>,
+    {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, room_id, session_id, timestamp, kind, data FROM events
+             WHERE room_id = ?1 AND timestamp >= ?2 AND timestamp < ?3
+             ORDER BY timestamp, id",
+        )?;
+
+        let mut rows = stmt.query(params![
+            room_id,
+            from.unwrap_or(i64::MIN),
+            to.unwrap_or(i64::MAX)
+        ])?;
+
+        while let Some(row) = rows.next()? {
+            let event = Self::event_from_row(row)?;
+
+            if kinds.is_empty() || kinds.contains(&event.kind) {
+                f(event)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn event_from_row(row: &rusqlite::Row) -> rusqlite::Result<EventRecord> {
+        Ok(EventRecord {
+            id: row.get(0)?,
+            room_id: row.get(1)?,
+            session_id: row.get(2)?,
+            timestamp: row.get(3)?,
+            kind: row.get(4)?,
+            data: serde_json::from_str(&row.get::<_, String>(5)?).unwrap_or_default(),
+        })
+    }
+
+    fn session_from_row(row: &rusqlite::Row) -> rusqlite::Result<SessionRecord> {
+        Ok(SessionRecord {
+            id: row.get("id")?,
+            room_id: row.get("room_id")?,
+            started_at: row.get("started_at")?,
+            ended_at: row.get("ended_at")?,
+            start_reason: row.get("start_reason")?,
+            end_reason: row.get("end_reason")?,
+        })
+    }
 }
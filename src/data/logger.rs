@@ -11,7 +11,7 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Layer, Registry, fmt};
 
-static G_LOGGER: Lazy<Logger> = Lazy::new(|| Logger::new());
+static G_LOGGER: Lazy<Logger> = Lazy::new(Logger::new);
 
 struct Logger {
     _wgs: Vec<WorkerGuard>,
@@ -65,8 +65,9 @@ pub struct MessageLogger {
 }
 
 impl MessageLogger {
-    pub fn new(room_id: &str) -> Self {
-        let appender = rolling::daily(PROJECT_DIRS.data_dir().join(room_id), "raw.jsonl");
+    // rotated daily under the data directory of the room, e.g. `raw.jsonl.2025-01-01`
+    pub fn new(room_id: &str, name: &str) -> Self {
+        let appender = rolling::daily(PROJECT_DIRS.data_dir().join(room_id), name);
         let (non_blocking, wg) = tracing_appender::non_blocking(appender);
 
         Self {
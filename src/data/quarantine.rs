@@ -0,0 +1,111 @@
+use crate::data::PROJECT_DIRS;
+use crate::live::message::RawMessage;
+use anyhow::{Error, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::Local;
+use serde::Serialize;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    room_id: &'a str,
+    received_at: i64, // 毫秒
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<&'a serde_json::Value>, // 原始数据
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>, // 不是 JSON 的包体，base64 编码
+}
+
+// Packets that were not JSON, had no cmd or failed to parse. They are kept one JSON object
+// per line instead of being dropped, so that they can be looked into later.
+pub struct Quarantine {
+    path: PathBuf,
+}
+
+impl Quarantine {
+    pub fn new(path: &dyn AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+        }
+    }
+
+    pub fn path() -> PathBuf {
+        PROJECT_DIRS.data_dir().join("quarantine.jsonl")
+    }
+
+    pub fn put(&self, raw: &RawMessage, error: &Error) -> Result<()> {
+        self.write(&Entry {
+            room_id: raw.room_id(),
+            received_at: Local::now().timestamp_millis(),
+            error: format!("{error:#}"),
+            data: Some(raw.data()),
+            body: None,
+        })
+    }
+
+    // a message body that is not even JSON, kept byte for byte
+    pub fn put_body(&self, room_id: &str, body: &[u8], error: &Error) -> Result<()> {
+        self.write(&Entry {
+            room_id,
+            received_at: Local::now().timestamp_millis(),
+            error: format!("{error:#}"),
+            data: None,
+            body: Some(STANDARD.encode(body)),
+        })
+    }
+
+    fn write(&self, entry: &Entry) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use serde_json::{Value, json};
+
+    #[test]
+    fn keeps_bodies_that_are_not_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let quarantine = Quarantine::new(&dir.path().join("quarantine.jsonl"));
+        let raw = RawMessage::new("21452505", json!({"data": {}}));
+
+        quarantine.put(&raw, &anyhow!("missing cmd")).unwrap();
+        quarantine
+            .put_body("21452505", b"\xff{not json", &anyhow!("expected value"))
+            .unwrap();
+
+        let entries: Vec<Value> = fs::read_to_string(dir.path().join("quarantine.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect();
+
+        assert_eq!(entries[0]["data"], json!({"data": {}}));
+        assert!(entries[0].get("body").is_none());
+        assert_eq!(entries[1]["error"], "expected value");
+        assert_eq!(
+            STANDARD
+                .decode(entries[1]["body"].as_str().unwrap())
+                .unwrap(),
+            b"\xff{not json"
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use crate::data::database::LivePersist;
+use crate::live::message::{LiveMessage, RawMessage};
+use anyhow::Result;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{Connection, params, params_from_iter};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_LIMIT: usize = 50;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchQuery {
+    #[serde(rename = "q")]
+    pub text: String, // 搜索内容
+    pub room: Option<String>, // 房间号
+    pub from: Option<i64>,    // 起始时间（毫秒，含）
+    pub to: Option<i64>,      // 结束时间（毫秒，不含）
+    pub uid: Option<u64>,     // 发送者
+    pub session: Option<i64>, // 场次
+    pub limit: Option<usize>, // 最多返回条数
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub id: i64, // events 表中的 ID
+    pub room_id: String,
+    pub session_id: Option<i64>,
+    pub timestamp: i64, // 毫秒
+    pub kind: String,
+    pub uid: u64,
+    pub uname: String,
+    pub text: String,
+}
+
+// FTS5 index over the text of danmaku and super chats in the `events` table. The
+// unicode61 tokenizer keeps a run of CJK characters as one token, so CJK text is split
+// into overlapping bigrams before indexing and searched as a phrase of bigrams.
+pub struct SearchIndex {
+    conn: Connection,
+}
+
+impl SearchIndex {
+    pub fn new(file: &dyn AsRef<Path>) -> Result<SearchIndex> {
+        if let Some(parent) = file.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // the index joins the `events` table, which may not exist yet
+        LivePersist::new(file)?;
+
+        let conn = Connection::open(file)?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "busy_timeout", "5000")?;
+
+        // rowid is the id of the event
+        conn.execute_batch(
+            "
+            CREATE VIRTUAL TABLE IF NOT EXISTS events_fts USING fts5 (
+                tokens,
+                text  UNINDEXED,
+                uid   UNINDEXED,
+                uname UNINDEXED,
+                tokenize = 'unicode61'
+            );
+            ",
+        )?;
+
+        Ok(Self { conn })
+    }
+
+    // indexes the text of a stored event, other messages are ignored
+    pub fn index(&self, event_id: i64, message: &LiveMessage) -> Result<()> {
+        let (user, text) = match message {
+            LiveMessage::Danmaku { user, text, .. } => (user, text),
+            LiveMessage::SuperChat { user, text, .. } => (user, text),
+            _ => return Ok(()),
+        };
+
+        self.conn.execute(
+            "INSERT OR REPLACE INTO events_fts (rowid, tokens, text, uid, uname)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![event_id, tokens(text), text, user.uid(), user.uname()],
+        )?;
+
+        Ok(())
+    }
+
+    // rebuilds the index from every stored danmaku and super chat, returns the count
+    pub fn reindex(&self) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut count = 0;
+
+        tx.execute("DELETE FROM events_fts", [])?;
+
+        {
+            let mut stmt = tx.prepare(
+                "SELECT id, room_id, data FROM events
+                 WHERE kind IN ('danmaku', 'super_chat') ORDER BY id",
+            )?;
+
+            let mut rows = stmt.query([])?;
+
+            while let Some(row) = rows.next()? {
+                let data: Value = serde_json::from_str(&row.get::<_, String>(2)?)?;
+                let raw = RawMessage::new(&row.get::<_, String>(1)?, data);
+
+                if let Ok(message) = LiveMessage::try_from(&raw) {
+                    self.index(row.get(0)?, &message)?;
+                    count += 1;
+                }
+            }
+        }
+
+        tx.commit()?;
+
+        Ok(count)
+    }
+
+    // newest first
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>> {
+        let Some(expression) = match_expression(&query.text) else {
+            return Ok(Vec::new());
+        };
+
+        let mut sql = String::from(
+            "SELECT e.id, e.room_id, e.session_id, e.timestamp, e.kind, f.uid, f.uname, f.text
+             FROM events_fts f JOIN events e ON e.id = f.rowid
+             WHERE events_fts MATCH ?",
+        );
+        let mut args = vec![SqlValue::Text(expression)];
+
+        if let Some(room) = &query.room {
+            sql += " AND e.room_id = ?";
+            args.push(SqlValue::Text(room.clone()));
+        }
+
+        if let Some(from) = query.from {
+            sql += " AND e.timestamp >= ?";
+            args.push(SqlValue::Integer(from));
+        }
+
+        if let Some(to) = query.to {
+            sql += " AND e.timestamp < ?";
+            args.push(SqlValue::Integer(to));
+        }
+
+        if let Some(uid) = query.uid {
+            sql += " AND f.uid = ?";
+            args.push(SqlValue::Integer(uid as i64));
+        }
+
+        if let Some(session) = query.session {
+            sql += " AND e.session_id = ?";
+            args.push(SqlValue::Integer(session));
+        }
+
+        sql += " ORDER BY e.timestamp DESC, e.id DESC LIMIT ?";
+        args.push(SqlValue::Integer(
+            query.limit.unwrap_or(DEFAULT_LIMIT) as i64
+        ));
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let hits = stmt
+            .query_map(params_from_iter(args), |row| {
+                Ok(SearchHit {
+                    id: row.get(0)?,
+                    room_id: row.get(1)?,
+                    session_id: row.get(2)?,
+                    timestamp: row.get(3)?,
+                    kind: row.get(4)?,
+                    uid: row.get(5)?,
+                    uname: row.get(6)?,
+                    text: row.get(7)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(hits)
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'     // 平假名、片假名
+        | '\u{3400}'..='\u{4dbf}'   // 扩展 A
+        | '\u{4e00}'..='\u{9fff}'   // 基本汉字
+        | '\u{ac00}'..='\u{d7af}'   // 谚文
+        | '\u{f900}'..='\u{faff}'   // 兼容汉字
+        | '\u{20000}'..='\u{2ebef}' // 扩展 B ~ F
+    )
+}
+
+// splits text into CJK runs and everything else
+fn segments(text: &str) -> Vec<(bool, Vec<char>)> {
+    let mut segments: Vec<(bool, Vec<char>)> = Vec::new();
+
+    for c in text.chars() {
+        let cjk = is_cjk(c);
+
+        match segments.last_mut() {
+            Some((last, chars)) if *last == cjk => chars.push(c),
+            _ => segments.push((cjk, vec![c])),
+        }
+    }
+
+    segments
+}
+
+// bigrams of every CJK run followed by its last character, so that a single character
+// can be found as a prefix of some token
+fn tokens(text: &str) -> String {
+    let mut tokens = Vec::new();
+
+    for (cjk, chars) in segments(text) {
+        if !cjk {
+            tokens.push(chars.into_iter().collect());
+            continue;
+        }
+
+        tokens.extend(chars.windows(2).map(|x| x.iter().collect::<String>()));
+        tokens.extend(chars.last().map(|x| x.to_string()));
+    }
+
+    tokens.join(" ")
+}
+
+// an FTS5 expression matching events containing all words of the query, `None` if the
+// query has nothing to search for
+fn match_expression(query: &str) -> Option<String> {
+    let mut phrases = Vec::new();
+
+    for (cjk, chars) in query.split_whitespace().flat_map(segments) {
+        match (cjk, chars.len()) {
+            (true, 1) => phrases.push(format!("{}*", quote(&chars[0].to_string()))),
+            (true, _) => {
+                let bigrams: Vec<String> = chars.windows(2).map(|x| x.iter().collect()).collect();
+                phrases.push(quote(&bigrams.join(" ")));
+            }
+            // punctuation is dropped by the tokenizer and would leave an empty phrase
+            (false, _) if chars.iter().any(|x| x.is_alphanumeric()) => {
+                phrases.push(quote(&chars.into_iter().collect::<String>()))
+            }
+            (false, _) => (),
+        }
+    }
+
+    (!phrases.is_empty()).then(|| phrases.join(" AND "))
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn danmaku(uid: u64, text: &str, timestamp: u64) -> RawMessage {
+        RawMessage::new(
+            "1",
+            json!({
+                "cmd": "DANMU_MSG",
+                "info": [
+                    [0, 0, 0, 0, timestamp, 0, 0, "", 0, 0, 0, "", 0, "", "", {
+                        "user": {"uid": uid, "base": {"name": format!("user{uid}")}},
+                    }],
+                    text,
+                ],
+            }),
+        )
+    }
+
+    #[test]
+    fn searches_cjk_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("live.db");
+
+        let persist = LivePersist::new(&file).unwrap();
+        let index = SearchIndex::new(&file).unwrap();
+
+        let texts = [(1, "主播今天好帅"), (2, "今天吃什么 hello"), (1, "好耶")];
+
+        for (ts, (uid, text)) in texts.into_iter().enumerate() {
+            let raw = danmaku(uid, text, ts as u64 + 1_700_000_000_000);
+            let message = LiveMessage::try_from(&raw).unwrap();
+            let id = persist
+                .insert_event("1", None, ts as i64, "danmaku", raw.data())
+                .unwrap();
+
+            index.index(id, &message).unwrap();
+        }
+
+        let search = |text: &str, uid: Option<u64>| {
+            let query = SearchQuery {
+                text: text.into(),
+                uid,
+                ..Default::default()
+            };
+
+            index
+                .search(&query)
+                .unwrap()
+                .into_iter()
+                .map(|x| x.text)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(search("今天", None), ["今天吃什么 hello", "主播今天好帅"]);
+        assert_eq!(search("今天", Some(1)), ["主播今天好帅"]);
+        assert_eq!(search("好", None), ["好耶", "主播今天好帅"]);
+        assert_eq!(search("帅", None), ["主播今天好帅"]);
+        assert_eq!(search("HELLO 吃", None), ["今天吃什么 hello"]);
+        assert!(search("天今", None).is_empty());
+        assert!(search("\"", None).is_empty());
+
+        assert_eq!(index.reindex().unwrap(), 3);
+        assert_eq!(search("好帅", None), ["主播今天好帅"]);
+    }
+}
@@ -0,0 +1,582 @@
+use crate::live::message::{LiveMessage, UserInteractType};
+use crate::stats::revenue;
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Serialize)]
+pub struct UserProfile {
+    pub uid: u64,
+    pub uname: String,              // 最近使用的用户名
+    pub face: Option<String>,       // 最近使用的头像
+    pub first_seen: i64,            // 首次出现（毫秒）
+    pub last_seen: i64,             // 最近出现（毫秒）
+    pub medal_level: Option<i64>,   // 最近一次的粉丝团等级
+    pub wealth_level: Option<i64>,  // 最近一次的荣耀等级
+    pub danmaku: i64,               // 弹幕条数
+    pub gifts: i64,                 // 礼物个数
+    pub spend: i64,                 // 消费金额（1/1000 元）
+    pub visits: i64,                // 进房次数
+    pub follows: i64,               // 关注次数
+    pub names: Vec<NameRecord>,     // 用过的用户名
+    pub renames: Vec<RenameRecord>, // 改名记录
+    pub faces: Vec<FaceRecord>,     // 用过的头像
+    pub rooms: Vec<RoomRecord>,     // 出现过的房间
+    pub levels: Vec<LevelRecord>,   // 等级变化
+}
+
+#[derive(Debug, Serialize)]
+pub struct NameRecord {
+    pub uname: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RenameRecord {
+    pub from: String,
+    pub to: String,
+    pub timestamp: i64,  // 首次见到新名字的时间（毫秒）
+    pub room_id: String, // 见到新名字的房间
+}
+
+// Every name a uid is known by, used to resolve names in moderation notes
+#[derive(Debug, Serialize)]
+pub struct NameHistory {
+    pub uid: u64,
+    pub uname: String, // 最近使用的用户名
+    pub names: Vec<NameRecord>,
+    pub renames: Vec<RenameRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FaceRecord {
+    pub face: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomRecord {
+    pub room_id: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LevelRecord {
+    pub room_id: String,
+    pub timestamp: i64,
+    pub medal_level: Option<i64>,
+    pub wealth_level: Option<i64>,
+}
+
+// Per-uid profiles merged from the `UserInfo` snapshot of every event, stored next to
+// the events in `live.db`
+pub struct UserPersist {
+    conn: Connection,
+}
+
+impl UserPersist {
+    pub fn new(file: &dyn AsRef<Path>) -> Result<UserPersist> {
+        if let Some(parent) = file.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(file)?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "busy_timeout", "5000")?;
+
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS users (
+                uid          INTEGER PRIMARY KEY,
+                uname        TEXT    NOT NULL,
+                face         TEXT,
+                first_seen   INTEGER NOT NULL,
+                last_seen    INTEGER NOT NULL,
+                medal_level  INTEGER,
+                wealth_level INTEGER,
+                danmaku      INTEGER NOT NULL DEFAULT 0,
+                gifts        INTEGER NOT NULL DEFAULT 0,
+                spend        INTEGER NOT NULL DEFAULT 0,
+                visits       INTEGER NOT NULL DEFAULT 0,
+                follows      INTEGER NOT NULL DEFAULT 0
+            );
+
+            CREATE TABLE IF NOT EXISTS user_names (
+                uid        INTEGER NOT NULL,
+                uname      TEXT    NOT NULL,
+                first_seen INTEGER NOT NULL,
+                last_seen  INTEGER NOT NULL,
+                PRIMARY KEY (uid, uname)
+            );
+
+            CREATE INDEX IF NOT EXISTS user_names_uname ON user_names (uname);
+
+            CREATE TABLE IF NOT EXISTS user_renames (
+                id        INTEGER PRIMARY KEY AUTOINCREMENT,
+                uid       INTEGER NOT NULL,
+                old_name  TEXT    NOT NULL,
+                new_name  TEXT    NOT NULL,
+                timestamp INTEGER NOT NULL,
+                room_id   TEXT    NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS user_renames_uid ON user_renames (uid, timestamp);
+
+            CREATE TABLE IF NOT EXISTS user_faces (
+                uid        INTEGER NOT NULL,
+                face       TEXT    NOT NULL,
+                first_seen INTEGER NOT NULL,
+                last_seen  INTEGER NOT NULL,
+                PRIMARY KEY (uid, face)
+            );
+
+            CREATE TABLE IF NOT EXISTS user_rooms (
+                uid        INTEGER NOT NULL,
+                room_id    TEXT    NOT NULL,
+                first_seen INTEGER NOT NULL,
+                last_seen  INTEGER NOT NULL,
+                PRIMARY KEY (uid, room_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS user_levels (
+                id           INTEGER PRIMARY KEY AUTOINCREMENT,
+                uid          INTEGER NOT NULL,
+                room_id      TEXT    NOT NULL,
+                timestamp    INTEGER NOT NULL,
+                medal_level  INTEGER,
+                wealth_level INTEGER
+            );
+
+            CREATE INDEX IF NOT EXISTS user_levels_uid ON user_levels (uid, room_id, timestamp);
+            ",
+        )?;
+
+        Ok(Self { conn })
+    }
+
+    // merges the user of a message into its profile, messages without a user are ignored
+    pub fn observe(&self, room_id: &str, timestamp: i64, message: &LiveMessage) -> Result<()> {
+        let Some(user) = message.user() else {
+            return Ok(());
+        };
+
+        let uid = user.uid();
+        let (mut danmaku, mut gifts, mut visits, mut follows) = (0, 0, 0, 0);
+
+        match message {
+            LiveMessage::Danmaku { .. } => danmaku = 1,
+            LiveMessage::Gift { gift_count, .. } => gifts = *gift_count,
+            LiveMessage::UserInteract { msg_type, .. } => match msg_type {
+                UserInteractType::JoinRoom => visits = 1,
+                UserInteractType::Subscribe => follows = 1,
+                UserInteractType::Share => (),
+            },
+            _ => (),
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+
+        // a rename is only certain when the message is newer than anything seen so far
+        let current: Option<(String, i64)> = tx
+            .query_row(
+                "SELECT uname, last_seen FROM users WHERE uid = ?1",
+                params![uid],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        if let Some((uname, last_seen)) = current
+            && uname != user.uname()
+            && timestamp >= last_seen
+        {
+            tx.execute(
+                "INSERT INTO user_renames (uid, old_name, new_name, timestamp, room_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![uid, uname, user.uname(), timestamp, room_id],
+            )?;
+        }
+
+        tx.execute(
+            "INSERT INTO users (
+                uid, uname, face, first_seen, last_seen, medal_level, wealth_level,
+                danmaku, gifts, spend, visits, follows
+             ) VALUES (?1, ?2, ?3, ?4, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+             ON CONFLICT (uid) DO UPDATE SET
+                uname        = CASE WHEN ?4 >= last_seen THEN ?2 ELSE uname END,
+                face         = CASE WHEN ?4 >= last_seen THEN COALESCE(?3, face) ELSE face END,
+                medal_level  = CASE WHEN ?4 >= last_seen THEN COALESCE(?5, medal_level)
+                                    ELSE medal_level END,
+                wealth_level = CASE WHEN ?4 >= last_seen THEN COALESCE(?6, wealth_level)
+                                    ELSE wealth_level END,
+                first_seen   = MIN(first_seen, ?4),
+                last_seen    = MAX(last_seen, ?4),
+                danmaku      = danmaku + ?7,
+                gifts        = gifts + ?8,
+                spend        = spend + ?9,
+                visits       = visits + ?10,
+                follows      = follows + ?11",
+            params![
+                uid,
+                user.uname(),
+                user.face(),
+                timestamp,
+                user.medal_level(),
+                user.wealth_level(),
+                danmaku,
+                gifts,
+                revenue::value_of(message),
+                visits,
+                follows,
+            ],
+        )?;
+
+        tx.execute(
+            "INSERT INTO user_names (uid, uname, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT (uid, uname) DO UPDATE SET
+                first_seen = MIN(first_seen, ?3),
+                last_seen  = MAX(last_seen, ?3)",
+            params![uid, user.uname(), timestamp],
+        )?;
+
+        if let Some(face) = user.face() {
+            tx.execute(
+                "INSERT INTO user_faces (uid, face, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)
+                 ON CONFLICT (uid, face) DO UPDATE SET
+                    first_seen = MIN(first_seen, ?3),
+                    last_seen  = MAX(last_seen, ?3)",
+                params![uid, face, timestamp],
+            )?;
+        }
+
+        tx.execute(
+            "INSERT INTO user_rooms (uid, room_id, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT (uid, room_id) DO UPDATE SET
+                first_seen = MIN(first_seen, ?3),
+                last_seen  = MAX(last_seen, ?3)",
+            params![uid, room_id, timestamp],
+        )?;
+
+        // levels are only recorded when they change, a missing level keeps the last one
+        let last: Option<(Option<i64>, Option<i64>)> = tx
+            .query_row(
+                "SELECT medal_level, wealth_level FROM user_levels
+                 WHERE uid = ?1 AND room_id = ?2 ORDER BY timestamp DESC, id DESC LIMIT 1",
+                params![uid, room_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        let (last_medal, last_wealth) = last.unwrap_or_default();
+        let medal_level = user.medal_level().or(last_medal);
+        let wealth_level = user.wealth_level().or(last_wealth);
+
+        if (medal_level, wealth_level) != (last_medal, last_wealth) {
+            tx.execute(
+                "INSERT INTO user_levels (uid, room_id, timestamp, medal_level, wealth_level)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![uid, room_id, timestamp, medal_level, wealth_level],
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    pub fn user(&self, uid: u64) -> Result<Option<UserProfile>> {
+        let profile = self
+            .conn
+            .query_row(
+                "SELECT * FROM users WHERE uid = ?1",
+                params![uid],
+                Self::profile_from_row,
+            )
+            .optional()?;
+
+        let Some(mut profile) = profile else {
+            return Ok(None);
+        };
+
+        profile.names = self.names(uid)?;
+        profile.renames = self.renames(uid)?;
+
+        profile.faces = self.history(
+            "SELECT face, first_seen, last_seen FROM user_faces WHERE uid = ?1
+             ORDER BY first_seen",
+            uid,
+            |row| {
+                Ok(FaceRecord {
+                    face: row.get(0)?,
+                    first_seen: row.get(1)?,
+                    last_seen: row.get(2)?,
+                })
+            },
+        )?;
+
+        profile.rooms = self.history(
+            "SELECT room_id, first_seen, last_seen FROM user_rooms WHERE uid = ?1
+             ORDER BY first_seen",
+            uid,
+            |row| {
+                Ok(RoomRecord {
+                    room_id: row.get(0)?,
+                    first_seen: row.get(1)?,
+                    last_seen: row.get(2)?,
+                })
+            },
+        )?;
+
+        profile.levels = self.history(
+            "SELECT room_id, timestamp, medal_level, wealth_level FROM user_levels
+             WHERE uid = ?1 ORDER BY timestamp, id",
+            uid,
+            |row| {
+                Ok(LevelRecord {
+                    room_id: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    medal_level: row.get(2)?,
+                    wealth_level: row.get(3)?,
+                })
+            },
+        )?;
+
+        Ok(Some(profile))
+    }
+
+    // everyone who has ever used the name, most recently seen first
+    pub fn find_by_name(&self, uname: &str) -> Result<Vec<UserProfile>> {
+        let mut profiles = Vec::new();
+
+        for uid in self.resolve_name(uname)? {
+            profiles.extend(self.user(uid)?);
+        }
+
+        Ok(profiles)
+    }
+
+    // uids that have ever used the name, most recently seen with it first
+    pub fn resolve_name(&self, uname: &str) -> Result<Vec<u64>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT uid FROM user_names WHERE uname = ?1
+             GROUP BY uid ORDER BY MAX(last_seen) DESC",
+        )?;
+
+        let uids = stmt
+            .query_map(params![uname], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        Ok(uids)
+    }
+
+    pub fn name_history(&self, uid: u64) -> Result<Option<NameHistory>> {
+        let uname = self
+            .conn
+            .query_row(
+                "SELECT uname FROM users WHERE uid = ?1",
+                params![uid],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        let Some(uname) = uname else {
+            return Ok(None);
+        };
+
+        Ok(Some(NameHistory {
+            uid,
+            uname,
+            names: self.names(uid)?,
+            renames: self.renames(uid)?,
+        }))
+    }
+
+    // the names of a uid, or of everyone who has used a name, like `lookup`
+    pub fn lookup_names(&self, query: &str) -> Result<Vec<NameHistory>> {
+        if let Ok(uid) = query.parse()
+            && let Some(history) = self.name_history(uid)?
+        {
+            return Ok(vec![history]);
+        }
+
+        self.name_histories(query)
+    }
+
+    // everyone who has ever used the name with all their names
+    pub fn name_histories(&self, uname: &str) -> Result<Vec<NameHistory>> {
+        let mut histories = Vec::new();
+
+        for uid in self.resolve_name(uname)? {
+            histories.extend(self.name_history(uid)?);
+        }
+
+        Ok(histories)
+    }
+
+    fn names(&self, uid: u64) -> Result<Vec<NameRecord>> {
+        self.history(
+            "SELECT uname, first_seen, last_seen FROM user_names WHERE uid = ?1
+             ORDER BY first_seen",
+            uid,
+            |row| {
+                Ok(NameRecord {
+                    uname: row.get(0)?,
+                    first_seen: row.get(1)?,
+                    last_seen: row.get(2)?,
+                })
+            },
+        )
+    }
+
+    fn renames(&self, uid: u64) -> Result<Vec<RenameRecord>> {
+        self.history(
+            "SELECT old_name, new_name, timestamp, room_id FROM user_renames WHERE uid = ?1
+             ORDER BY timestamp, id",
+            uid,
+            |row| {
+                Ok(RenameRecord {
+                    from: row.get(0)?,
+                    to: row.get(1)?,
+                    timestamp: row.get(2)?,
+                    room_id: row.get(3)?,
+                })
+            },
+        )
+    }
+
+    // a uid if the query is numeric and known, otherwise a name
+    pub fn lookup(&self, query: &str) -> Result<Vec<UserProfile>> {
+        if let Ok(uid) = query.parse()
+            && let Some(profile) = self.user(uid)?
+        {
+            return Ok(vec![profile]);
+        }
+
+        self.find_by_name(query)
+    }
+
+    fn history<T, F>(&self, sql: &str, uid: u64, map: F) -> Result<Vec<T>>
+    where
+        F: FnMut(&rusqlite::Row) -> rusqlite::Result<T>,
+    {
+        let mut stmt = self.conn.prepare(sql)?;
+        let records = stmt
+            .query_map(params![uid], map)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(records)
+    }
+
+    fn profile_from_row(row: &rusqlite::Row) -> rusqlite::Result<UserProfile> {
+        Ok(UserProfile {
+            uid: row.get("uid")?,
+            uname: row.get("uname")?,
+            face: row.get("face")?,
+            first_seen: row.get("first_seen")?,
+            last_seen: row.get("last_seen")?,
+            medal_level: row.get("medal_level")?,
+            wealth_level: row.get("wealth_level")?,
+            danmaku: row.get("danmaku")?,
+            gifts: row.get("gifts")?,
+            spend: row.get("spend")?,
+            visits: row.get("visits")?,
+            follows: row.get("follows")?,
+            names: Vec::new(),
+            renames: Vec::new(),
+            faces: Vec::new(),
+            rooms: Vec::new(),
+            levels: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::message::RawMessage;
+    use serde_json::json;
+
+    fn gift(uid: u64, uname: &str, medal: i64, timestamp: u64) -> LiveMessage {
+        let raw = RawMessage::new(
+            "1",
+            json!({
+                "cmd": "SEND_GIFT",
+                "data": {
+                    "timestamp": timestamp,
+                    "sender_uinfo": {"uid": uid, "base": {"name": uname}, "medal": {"level": medal}},
+                    "giftName": "小花花",
+                    "num": 2,
+                    "coin_type": "gold",
+                    "total_coin": 200,
+                },
+            }),
+        );
+
+        LiveMessage::try_from(&raw).unwrap()
+    }
+
+    #[test]
+    fn merges_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let persist = UserPersist::new(&dir.path().join("live.db")).unwrap();
+
+        // out of order on purpose, the newest name still wins
+        persist.observe("1", 3000, &gift(42, "new", 2, 3)).unwrap();
+        persist.observe("1", 1000, &gift(42, "old", 1, 1)).unwrap();
+        persist.observe("2", 2000, &gift(42, "old", 1, 2)).unwrap();
+
+        let profile = persist.user(42).unwrap().unwrap();
+
+        assert_eq!(profile.uname, "new");
+        assert_eq!((profile.first_seen, profile.last_seen), (1000, 3000));
+        assert_eq!((profile.gifts, profile.spend), (6, 600));
+        assert_eq!(profile.medal_level, Some(2));
+        assert_eq!(profile.names.len(), 2);
+        assert!(profile.renames.is_empty());
+        assert_eq!(profile.rooms.len(), 2);
+        assert_eq!(profile.levels.len(), 3);
+
+        assert_eq!(persist.lookup("old").unwrap()[0].uid, 42);
+        assert_eq!(persist.lookup("42").unwrap()[0].uname, "new");
+        assert!(persist.lookup("nobody").unwrap().is_empty());
+    }
+
+    #[test]
+    fn tracks_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        let persist = UserPersist::new(&dir.path().join("live.db")).unwrap();
+
+        persist.observe("1", 1000, &gift(42, "a", 1, 1)).unwrap();
+        persist.observe("2", 2000, &gift(42, "b", 1, 2)).unwrap();
+        persist.observe("1", 1500, &gift(42, "a", 1, 1)).unwrap();
+        persist.observe("1", 3000, &gift(42, "a", 1, 3)).unwrap();
+        persist.observe("1", 3000, &gift(7, "b", 1, 3)).unwrap();
+
+        let history = persist.name_history(42).unwrap().unwrap();
+        let renames: Vec<_> = history
+            .renames
+            .iter()
+            .map(|x| {
+                (
+                    x.from.as_str(),
+                    x.to.as_str(),
+                    x.timestamp,
+                    x.room_id.as_str(),
+                )
+            })
+            .collect();
+
+        assert_eq!(renames, [("a", "b", 2000, "2"), ("b", "a", 3000, "1")]);
+        assert_eq!(history.names.len(), 2);
+
+        // both used `b`, the one seen with it last comes first
+        assert_eq!(persist.resolve_name("b").unwrap(), [7, 42]);
+        assert_eq!(persist.lookup_names("42").unwrap()[0].uname, "a");
+    }
+}
@@ -0,0 +1,449 @@
+pub mod danmaku;
+mod parquet_file;
+
+use crate::data::database::LivePersist;
+use crate::export::parquet_file::ParquetWriter;
+use crate::live::message::{LiveMessage, RawMessage};
+use crate::replay;
+use crate::stats::revenue;
+use anyhow::{Result, bail};
+use log::warn;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+    Parquet,
+}
+
+impl ExportFormat {
+    // guessed from the extension of the output file
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "csv" => Ok(ExportFormat::Csv),
+            "ndjson" | "jsonl" => Ok(ExportFormat::Ndjson),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => bail!("unknown export format {value}, expected csv, ndjson or parquet"),
+        }
+    }
+}
+
+// One row per message with the same columns in every format, fields that do not apply
+// to a message are empty. Columns are only ever appended.
+#[derive(Debug, Default, Serialize)]
+pub struct ExportRow {
+    pub room_id: String,
+    pub session_id: Option<i64>,
+    pub timestamp: i64, // 毫秒
+    pub kind: &'static str,
+    pub uid: Option<u64>,
+    pub uname: Option<String>,
+    pub medal_level: Option<i64>,
+    pub wealth_level: Option<i64>,
+    pub text: Option<String>,      // 弹幕、醒目留言内容，切断原因
+    pub gift_name: Option<String>, // 礼物或舰队名称
+    pub gift_count: Option<i64>,   // 礼物个数或上舰月数
+    pub coin_type: Option<String>, // gold / silver
+    pub total_coin: Option<i64>,   // 礼物总价（瓜子）
+    pub guard_level: Option<i64>,
+    pub value: i64,               // 价值（1/1000 元）
+    pub interact: Option<String>, // join_room / subscribe / share
+    pub watched: Option<i64>,     // 看过人数
+}
+
+impl ExportRow {
+    pub fn new(
+        room_id: &str,
+        session_id: Option<i64>,
+        timestamp: i64,
+        message: &LiveMessage,
+    ) -> Self {
+        let user = message.user();
+
+        let mut row = Self {
+            room_id: room_id.into(),
+            session_id,
+            timestamp,
+            kind: message.kind(),
+            uid: user.map(|x| x.uid()),
+            uname: user.map(|x| x.uname().into()),
+            medal_level: user.and_then(|x| x.medal_level()),
+            wealth_level: user.and_then(|x| x.wealth_level()),
+            value: revenue::value_of(message),
+            ..Default::default()
+        };
+
+        match message {
+            LiveMessage::Danmaku { text, .. } | LiveMessage::SuperChat { text, .. } => {
+                row.text = Some(text.clone());
+            }
+            LiveMessage::CutOff { reason, .. } => row.text = Some(reason.clone()),
+            LiveMessage::Gift {
+                gift_name,
+                gift_count,
+                coin_type,
+                total_coin,
+                ..
+            } => {
+                row.gift_name = Some(gift_name.clone());
+                row.gift_count = Some(*gift_count);
+                row.coin_type = Some(coin_type.clone());
+                row.total_coin = Some(*total_coin);
+            }
+            LiveMessage::GuardBuy {
+                guard_level,
+                guard_name,
+                count,
+                ..
+            } => {
+                row.gift_name = Some(guard_name.clone());
+                row.gift_count = Some(*count);
+                row.guard_level = Some(*guard_level);
+            }
+            LiveMessage::UserInteract { msg_type, .. } => {
+                row.interact = Some(msg_type.name().into());
+            }
+            LiveMessage::WatchedChange { count, .. } => row.watched = Some(*count),
+            _ => (),
+        }
+
+        row
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ExportQuery {
+    pub room_id: String,
+    pub kinds: Vec<String>, // 导出的消息类型，为空时导出全部
+    pub from: Option<i64>,  // 起始时间（毫秒，含）
+    pub to: Option<i64>,    // 结束时间（毫秒，不含）
+}
+
+impl ExportQuery {
+    fn matches(&self, timestamp: i64, message: &LiveMessage) -> bool {
+        (self.kinds.is_empty() || self.kinds.iter().any(|x| x == message.kind()))
+            && self.from.is_none_or(|from| timestamp >= from)
+            && self.to.is_none_or(|to| timestamp < to)
+    }
+}
+
+pub enum ExportSource<'a> {
+    Database(&'a LivePersist),
+    Raw(&'a [String]), // 原始消息日志，按时间顺序给出
+}
+
+trait RowWriter {
+    fn write(&mut self, row: ExportRow) -> Result<()>;
+
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+// writes the matching messages to `output`, returns the number of rows
+pub fn export(
+    source: &ExportSource,
+    query: &ExportQuery,
+    format: ExportFormat,
+    output: &dyn AsRef<Path>,
+) -> Result<usize> {
+    let mut writer: Box<dyn RowWriter> = match format {
+        ExportFormat::Csv => Box::new(CsvWriter::new(File::create(output)?)?),
+        ExportFormat::Ndjson => Box::new(NdjsonWriter(BufWriter::new(File::create(output)?))),
+        ExportFormat::Parquet => Box::new(ParquetWriter::new(File::create(output)?)?),
+    };
+
+    let mut count = 0;
+
+    for_each_message(source, query, |session_id, timestamp, message| {
+        count += 1;
+        writer.write(ExportRow::new(
+            &query.room_id,
+            session_id,
+            timestamp,
+            message,
+        ))
+    })?;
+
+    writer.finish()?;
+
+    Ok(count)
+}
+
+// calls `f` with the session, time in milliseconds and message of every matching message
+pub fn for_each_message<F>(source: &ExportSource, query: &ExportQuery, mut f: F) -> Result<()>
+where
+    F: FnMut(Option<i64>, i64, &LiveMessage) -> Result<()>,
+{
+    let mut handle = |session_id: Option<i64>, timestamp: i64, message: &LiveMessage| {
+        if query.matches(timestamp, message) {
+            f(session_id, timestamp, message)
+        } else {
+            Ok(())
+        }
+    };
+
+    match source {
+        ExportSource::Database(persist) => persist.for_each_event(
+            &query.room_id,
+            query.from,
+            query.to,
+            &query.kinds,
+            |event| {
+                let raw = RawMessage::new(&event.room_id, event.data);
+
+                match LiveMessage::try_from(&raw) {
+                    Ok(message) => handle(event.session_id, event.timestamp, &message),
+                    Err(err) => {
+                        warn!("skipping event {} that no longer parses: {err:?}", event.id);
+                        Ok(())
+                    }
+                }
+            },
+        ),
+        // a raw log line has no time of its own, messages the server did not stamp take
+        // the time of the stamped message closest before them in file order. Those before
+        // the first stamp are held back and take the time of the first stamp instead.
+        ExportSource::Raw(files) => {
+            let mut last = None;
+            let mut pending = Vec::new(); // 首个带时间的消息之前的消息，取它的时间
+
+            for file in files.iter() {
+                for raw in replay::read(&query.room_id, file)? {
+                    // unsupported messages are not stored in the database either
+                    let Ok(message) = LiveMessage::try_from(&raw) else {
+                        continue;
+                    };
+
+                    if let LiveMessage::Unsupported(_) = message {
+                        continue;
+                    }
+
+                    match message.server_timestamp() {
+                        Some(timestamp) => {
+                            let timestamp = timestamp.timestamp_millis();
+
+                            for message in pending.drain(..) {
+                                handle(None, timestamp, &message)?;
+                            }
+
+                            last = Some(timestamp);
+                            handle(None, timestamp, &message)?;
+                        }
+                        None => match last {
+                            Some(timestamp) => handle(None, timestamp, &message)?,
+                            None => pending.push(message),
+                        },
+                    }
+                }
+            }
+
+            if !pending.is_empty() {
+                warn!(
+                    "skipped {} messages, no message in the raw logs carries a time",
+                    pending.len()
+                );
+            }
+
+            Ok(())
+        }
+    }
+}
+
+struct CsvWriter(csv::Writer<File>);
+
+impl CsvWriter {
+    // the header is written up front so that an empty export still has the columns
+    fn new(file: File) -> Result<Self> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(file);
+
+        writer.write_record(parquet_file::column_names())?;
+
+        Ok(Self(writer))
+    }
+}
+
+impl RowWriter for CsvWriter {
+    fn write(&mut self, row: ExportRow) -> Result<()> {
+        self.0.serialize(row)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+struct NdjsonWriter(BufWriter<File>);
+
+impl RowWriter for NdjsonWriter {
+    fn write(&mut self, row: ExportRow) -> Result<()> {
+        serde_json::to_writer(&mut self.0, &row)?;
+        self.0.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use serde_json::{Value, json};
+    use std::fs;
+
+    #[test]
+    fn exports_every_format_with_the_same_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw = dir.path().join("raw.jsonl");
+        let lines = [
+            json!({
+                "cmd": "SEND_GIFT",
+                "data": {
+                    "timestamp": 1_700_000_000,
+                    "sender_uinfo": {"uid": 42, "base": {"name": "甲"}},
+                    "giftName": "小花花",
+                    "num": 2,
+                    "coin_type": "gold",
+                    "total_coin": 200,
+                },
+            }),
+            json!({"cmd": "WATCHED_CHANGE", "data": {"num": 7}}),
+            json!({"cmd": "UNKNOWN"}),
+        ];
+
+        fs::write(&raw, lines.map(|x| x.to_string()).join("\n")).unwrap();
+
+        let files = [raw.to_string_lossy().into_owned()];
+        let query = ExportQuery {
+            room_id: "1".into(),
+            kinds: vec!["gift".into()],
+            ..Default::default()
+        };
+
+        let export = |name: &str, format| {
+            let output = dir.path().join(name);
+            let count = export(&ExportSource::Raw(&files), &query, format, &output).unwrap();
+
+            assert_eq!(count, 1);
+            output
+        };
+
+        // the header of a serialized row is the parquet schema
+        let mut header = csv::Writer::from_writer(Vec::new());
+        header.serialize(ExportRow::default()).unwrap();
+        let header = String::from_utf8(header.into_inner().unwrap()).unwrap();
+        let columns: Vec<_> = parquet_file::column_names().collect();
+
+        assert_eq!(header.lines().next().unwrap(), columns.join(","));
+
+        let csv = fs::read_to_string(export("gift.csv", ExportFormat::Csv)).unwrap();
+        let csv: Vec<_> = csv.lines().collect();
+
+        assert_eq!(csv[0], columns.join(","));
+        assert_eq!(
+            csv[1],
+            "1,,1700000000000,gift,42,甲,,,,小花花,2,gold,200,,200,,"
+        );
+
+        let ndjson = fs::read_to_string(export("gift.ndjson", ExportFormat::Ndjson)).unwrap();
+        let row: Value = serde_json::from_str(ndjson.trim()).unwrap();
+
+        assert_eq!(row["uid"], 42);
+        assert_eq!(row["session_id"], Value::Null);
+
+        let parquet = export("gift.parquet", ExportFormat::Parquet);
+        let reader = SerializedFileReader::new(File::open(parquet).unwrap()).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr();
+        let names: Vec<_> = schema.columns().iter().map(|x| x.name()).collect();
+
+        assert_eq!(names, columns);
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 1);
+    }
+
+    #[test]
+    fn dates_raw_messages_without_a_time_by_file_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw = dir.path().join("raw.jsonl");
+        let gift = |timestamp: i64| {
+            json!({
+                "cmd": "SEND_GIFT",
+                "data": {
+                    "timestamp": timestamp,
+                    "uid": 42,
+                    "uname": "甲",
+                    "giftName": "小花花",
+                    "num": 1,
+                    "coin_type": "gold",
+                    "total_coin": 100,
+                },
+            })
+        };
+        let lines = [
+            json!({"cmd": "WATCHED_CHANGE", "data": {"num": 1}}),
+            gift(1_700_000_000),
+            json!({"cmd": "WATCHED_CHANGE", "data": {"num": 2}}),
+            gift(1_700_000_060),
+            json!({"cmd": "WATCHED_CHANGE", "data": {"num": 3}}),
+        ];
+
+        fs::write(&raw, lines.map(|x| x.to_string()).join("\n")).unwrap();
+
+        let files = [raw.to_string_lossy().into_owned()];
+        let watched = |from: Option<i64>| {
+            let query = ExportQuery {
+                room_id: "1".into(),
+                kinds: vec!["watched_change".into()],
+                from,
+                ..Default::default()
+            };
+            let mut rows = Vec::new();
+
+            for_each_message(
+                &ExportSource::Raw(&files),
+                &query,
+                |_, timestamp, message| {
+                    rows.push((
+                        timestamp,
+                        ExportRow::new("1", None, timestamp, message).watched,
+                    ));
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+            rows
+        };
+
+        assert_eq!(
+            watched(None),
+            [
+                (1_700_000_000_000, Some(1)),
+                (1_700_000_000_000, Some(2)),
+                (1_700_000_060_000, Some(3)),
+            ]
+        );
+        assert_eq!(
+            watched(Some(1_700_000_030_000)),
+            [(1_700_000_060_000, Some(3))]
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use crate::export::{ExportQuery, ExportSource, for_each_message};
+use crate::live::message::LiveMessage;
+use anyhow::{Result, bail};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const WIDTH: i64 = 1920;
+const HEIGHT: i64 = 1080;
+const FONT: &str = "Microsoft YaHei";
+const FONT_SCALE: f64 = 1.6; // 字号 25 在 1080p 下约为 40px
+const SCROLL_DURATION: i64 = 10000;
+const FIXED_DURATION: i64 = 5000;
+
+const MODE_BOTTOM: i64 = 4;
+const MODE_TOP: i64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DanmakuFormat {
+    Ass,
+    Xml, // B 站弹幕文件
+}
+
+impl DanmakuFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "ass" => Some(DanmakuFormat::Ass),
+            "xml" => Some(DanmakuFormat::Xml),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DanmakuItem {
+    pub time: i64,      // 相对录像开始的时间（毫秒）
+    pub timestamp: i64, // 发送时间（毫秒）
+    pub uid: u64,
+    pub text: String,
+    pub mode: i64,
+    pub font_size: i64,
+    pub color: i64,
+}
+
+// Writes the danmaku sent in [start, end) as a track for a recording that began at
+// `start`, returns the number written
+pub fn export(
+    source: &ExportSource,
+    room_id: &str,
+    start: i64,
+    end: Option<i64>,
+    format: DanmakuFormat,
+    output: &dyn AsRef<Path>,
+) -> Result<usize> {
+    let query = ExportQuery {
+        room_id: room_id.into(),
+        kinds: vec!["danmaku".into()],
+        from: Some(start),
+        to: end,
+    };
+
+    let mut items = Vec::new();
+
+    for_each_message(source, &query, |_, timestamp, message| {
+        if let LiveMessage::Danmaku {
+            user,
+            text,
+            mode,
+            font_size,
+            color,
+            ..
+        } = message
+        {
+            items.push(DanmakuItem {
+                time: timestamp - start,
+                timestamp,
+                uid: user.uid(),
+                text: text.clone(),
+                mode: *mode,
+                font_size: *font_size,
+                color: *color,
+            });
+        }
+
+        Ok(())
+    })?;
+
+    if items.is_empty() {
+        bail!("no danmaku in room {room_id} for the given time range")
+    }
+
+    // raw logs of several files may overlap
+    items.sort_by_key(|x| x.time);
+
+    let mut output = BufWriter::new(File::create(output)?);
+
+    match format {
+        DanmakuFormat::Ass => output.write_all(ass(&items).as_bytes())?,
+        DanmakuFormat::Xml => output.write_all(xml(&items).as_bytes())?,
+    }
+
+    output.flush()?;
+
+    Ok(items.len())
+}
+
+// Subtitles that scroll from right to left like the Bilibili player, top and bottom
+// danmaku stay centred. Each danmaku takes the first row it does not overlap in.
+pub fn ass(items: &[DanmakuItem]) -> String {
+    let base_size = (25.0 * FONT_SCALE) as i64;
+
+    let mut ass = format!(
+        "[Script Info]
+ScriptType: v4.00+
+PlayResX: {WIDTH}
+PlayResY: {HEIGHT}
+WrapStyle: 2
+ScaledBorderAndShadow: yes
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, \
+Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, \
+Shadow, Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Danmaku,{FONT},{base_size},&H33FFFFFF,&H33FFFFFF,&H33000000,&H33000000,\
+1,0,0,0,100,100,0,0,1,1,0,7,0,0,0,1
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+"
+    );
+
+    let mut scroll = Rows::new(base_size);
+    let mut top = Rows::new(base_size);
+    let mut bottom = Rows::new(base_size);
+
+    for item in items {
+        let size = (item.font_size as f64 * FONT_SCALE) as i64;
+        let width = text_width(&item.text, size);
+        let mut tags = String::new();
+
+        let end = match item.mode {
+            MODE_TOP | MODE_BOTTOM => {
+                let rows = if item.mode == MODE_TOP {
+                    &mut top
+                } else {
+                    &mut bottom
+                };
+
+                let span = rows.span(size);
+                let row = rows.place(span, |last| last.end - item.time);
+                let y = row * rows.height;
+
+                rows.occupy(row, span, item.time, item.time + FIXED_DURATION, width);
+
+                if item.mode == MODE_TOP {
+                    let _ = write!(tags, "\\an8\\pos({},{y})", WIDTH / 2);
+                } else {
+                    let _ = write!(tags, "\\an2\\pos({},{})", WIDTH / 2, HEIGHT - y);
+                }
+
+                item.time + FIXED_DURATION
+            }
+            _ => {
+                let duration = SCROLL_DURATION as f64;
+                let speed = (WIDTH + width) as f64 / duration;
+
+                // a row is free once the last danmaku has fully entered the screen and
+                // has left it before this one, which may be faster, catches up
+                let span = scroll.span(size);
+                let row = scroll.place(span, |last| {
+                    let last_speed = (WIDTH + last.width) as f64 / duration;
+                    let entered = last.start + (last.width as f64 / last_speed) as i64;
+                    let caught = last.end - (item.time + (WIDTH as f64 / speed) as i64);
+
+                    (entered - item.time).max(caught)
+                });
+
+                scroll.occupy(row, span, item.time, item.time + SCROLL_DURATION, width);
+
+                let y = row * scroll.height;
+                let _ = write!(tags, "\\move({WIDTH},{y},{},{y})", -width);
+
+                item.time + SCROLL_DURATION
+            }
+        };
+
+        if item.font_size != 25 {
+            let _ = write!(tags, "\\fs{size}");
+        }
+
+        if item.color != 0xffffff {
+            let (r, g, b) = (
+                item.color >> 16 & 0xff,
+                item.color >> 8 & 0xff,
+                item.color & 0xff,
+            );
+            let _ = write!(tags, "\\c&H{b:02X}{g:02X}{r:02X}&");
+        }
+
+        let _ = writeln!(
+            ass,
+            "Dialogue: 2,{},{},Danmaku,,0,0,0,,{{{tags}}}{}",
+            ass_time(item.time),
+            ass_time(end),
+            ass_escape(&item.text)
+        );
+    }
+
+    ass
+}
+
+// Bilibili's `<d p="...">` format, loaded by most players that take danmaku files
+pub fn xml(items: &[DanmakuItem]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<i>
+  <chatserver>chat.bilibili.com</chatserver>
+  <chatid>0</chatid>
+  <mission>0</mission>
+  <maxlimit>",
+    );
+
+    let _ = writeln!(xml, "{}</maxlimit>", items.len());
+    xml += "  <state>0</state>\n  <real_name>0</real_name>\n  <source>k-v</source>\n";
+
+    // 出现时间,模式,字号,颜色,发送时间,弹幕池,发送者（uid 的 crc32）,弹幕 ID
+    for (index, item) in items.iter().enumerate() {
+        let _ = writeln!(
+            xml,
+            "  <d p=\"{:.3},{},{},{},{},0,{:x},{index}\">{}</d>",
+            item.time as f64 / 1000.0,
+            item.mode,
+            item.font_size,
+            item.color,
+            item.timestamp / 1000,
+            crc32fast::hash(item.uid.to_string().as_bytes()),
+            xml_escape(&item.text)
+        );
+    }
+
+    xml += "</i>\n";
+    xml
+}
+
+struct Occupant {
+    start: i64,
+    end: i64,
+    width: i64,
+}
+
+struct Rows {
+    height: i64,
+    count: i64,
+    last: Vec<Option<Occupant>>,
+}
+
+impl Rows {
+    fn new(height: i64) -> Self {
+        let count = (HEIGHT / height).max(1);
+
+        Self {
+            height,
+            count,
+            last: (0..count).map(|_| None).collect(),
+        }
+    }
+
+    // how many rows a danmaku of the given font size takes
+    fn span(&self, size: i64) -> i64 {
+        ((size + self.height - 1) / self.height).clamp(1, self.count)
+    }
+
+    // the first run of `span` rows whose conflict is not positive, otherwise the one with
+    // the least, the conflict of a run being that of its worst row
+    fn place<F: Fn(&Occupant) -> i64>(&self, span: i64, conflict: F) -> i64 {
+        let mut best = (0, i64::MAX);
+
+        for row in 0..=self.count - span {
+            let conflict = self.last[row as usize..(row + span) as usize]
+                .iter()
+                .map(|last| last.as_ref().map_or(0, &conflict))
+                .max()
+                .unwrap_or(0);
+
+            if conflict <= 0 {
+                return row;
+            }
+
+            if conflict < best.1 {
+                best = (row, conflict);
+            }
+        }
+
+        best.0
+    }
+
+    fn occupy(&mut self, row: i64, span: i64, start: i64, end: i64, width: i64) {
+        for last in &mut self.last[row as usize..(row + span) as usize] {
+            *last = Some(Occupant { start, end, width });
+        }
+    }
+}
+
+// full width characters take a whole em, everything else half of one
+fn text_width(text: &str, size: i64) -> i64 {
+    let ems: f64 = text
+        .chars()
+        .map(|c| if c.is_ascii() { 0.5 } else { 1.0 })
+        .sum();
+
+    (ems * size as f64).ceil() as i64
+}
+
+fn ass_time(ms: i64) -> String {
+    let cs = ms.max(0) / 10;
+
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        cs / 360000,
+        cs / 6000 % 60,
+        cs / 100 % 60,
+        cs % 100
+    )
+}
+
+// braces start override tags and backslashes escapes, both are swapped for full width
+fn ass_escape(text: &str) -> String {
+    text.replace('\\', "＼")
+        .replace('{', "｛")
+        .replace('}', "｝")
+        .replace(['\r', '\n'], " ")
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&apos;",
+            // not allowed in XML 1.0
+            c if c < ' ' && !matches!(c, '\t' | '\n' | '\r') => (),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(time: i64, text: &str, mode: i64, color: i64) -> DanmakuItem {
+        DanmakuItem {
+            time,
+            timestamp: 1_700_000_000_000 + time,
+            uid: 255,
+            text: text.into(),
+            mode,
+            font_size: 25,
+            color,
+        }
+    }
+
+    #[test]
+    fn renders_ass_and_xml() {
+        let items = [
+            item(1500, "第一条", 1, 0xffffff),
+            item(1600, "第二条", 1, 0xff0000),
+            item(3_661_000, "{\\顶部}", MODE_TOP, 0xffffff),
+        ];
+
+        let ass = ass(&items);
+        let dialogues: Vec<_> = ass.lines().filter(|x| x.starts_with("Dialogue")).collect();
+
+        // the second one can not share the first row while the first is still entering
+        assert_eq!(
+            dialogues[0],
+            "Dialogue: 2,0:00:01.50,0:00:11.50,Danmaku,,0,0,0,,{\\move(1920,0,-120,0)}第一条"
+        );
+        assert_eq!(
+            dialogues[1],
+            "Dialogue: 2,0:00:01.60,0:00:11.60,Danmaku,,0,0,0,,\
+             {\\move(1920,40,-120,40)\\c&H0000FF&}第二条"
+        );
+        assert_eq!(
+            dialogues[2],
+            "Dialogue: 2,1:01:01.00,1:01:06.00,Danmaku,,0,0,0,,{\\an8\\pos(960,0)}｛＼顶部｝"
+        );
+
+        let xml = xml(&[item(1500, "a<b & \"c\"", 1, 16777215)]);
+
+        assert!(xml.contains("<maxlimit>1</maxlimit>"));
+        assert!(xml.contains(
+            "<d p=\"1.500,1,25,16777215,1700000001,0,2c2cee79,0\">a&lt;b &amp; &quot;c&quot;</d>"
+        ));
+    }
+
+    #[test]
+    fn reserves_rows_for_large_danmaku() {
+        let large = DanmakuItem {
+            font_size: 36,
+            ..item(1500, "大", 1, 0xffffff)
+        };
+        let items = [large, item(1600, "第二条", 1, 0xffffff)];
+
+        let ass = ass(&items);
+        let dialogues: Vec<_> = ass.lines().filter(|x| x.starts_with("Dialogue")).collect();
+
+        // 57px takes the first two rows of 40px
+        assert!(dialogues[0].contains("{\\move(1920,0,-57,0)\\fs57}"));
+        assert!(dialogues[1].contains("{\\move(1920,80,-120,80)}"));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::export::{ExportRow, RowWriter};
+use anyhow::Result;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use parquet::schema::parser::parse_message_type;
+use std::fs::File;
+use std::sync::Arc;
+
+const ROW_GROUP_SIZE: usize = 65536;
+
+enum Value {
+    Int(fn(&ExportRow) -> Option<i64>),
+    Time(fn(&ExportRow) -> i64),
+    Text(fn(&ExportRow) -> Option<&str>),
+}
+
+struct Column {
+    name: &'static str,
+    required: bool,
+    value: Value,
+}
+
+const fn column(name: &'static str, required: bool, value: Value) -> Column {
+    Column {
+        name,
+        required,
+        value,
+    }
+}
+
+// same order as the fields of `ExportRow`
+const COLUMNS: &[Column] = &[
+    column("room_id", true, Value::Text(|x| Some(&x.room_id))),
+    column("session_id", false, Value::Int(|x| x.session_id)),
+    column("timestamp", true, Value::Time(|x| x.timestamp)),
+    column("kind", true, Value::Text(|x| Some(x.kind))),
+    column("uid", false, Value::Int(|x| x.uid.map(|x| x as i64))),
+    column("uname", false, Value::Text(|x| x.uname.as_deref())),
+    column("medal_level", false, Value::Int(|x| x.medal_level)),
+    column("wealth_level", false, Value::Int(|x| x.wealth_level)),
+    column("text", false, Value::Text(|x| x.text.as_deref())),
+    column("gift_name", false, Value::Text(|x| x.gift_name.as_deref())),
+    column("gift_count", false, Value::Int(|x| x.gift_count)),
+    column("coin_type", false, Value::Text(|x| x.coin_type.as_deref())),
+    column("total_coin", false, Value::Int(|x| x.total_coin)),
+    column("guard_level", false, Value::Int(|x| x.guard_level)),
+    column("value", true, Value::Int(|x| Some(x.value))),
+    column("interact", false, Value::Text(|x| x.interact.as_deref())),
+    column("watched", false, Value::Int(|x| x.watched)),
+];
+
+pub fn column_names() -> impl Iterator<Item = &'static str> {
+    COLUMNS.iter().map(|x| x.name)
+}
+
+fn schema() -> String {
+    let fields: Vec<_> = COLUMNS
+        .iter()
+        .map(|column| {
+            let repetition = if column.required {
+                "required"
+            } else {
+                "optional"
+            };
+            let (physical, annotation) = match column.value {
+                Value::Int(_) => ("int64", ""),
+                Value::Time(_) => ("int64", " (TIMESTAMP(MILLIS,true))"),
+                Value::Text(_) => ("binary", " (UTF8)"),
+            };
+
+            format!("{repetition} {physical} {}{annotation};", column.name)
+        })
+        .collect();
+
+    format!("message export {{ {} }}", fields.join(" "))
+}
+
+// Buffers rows and writes them as snappy compressed row groups
+pub struct ParquetWriter {
+    writer: SerializedFileWriter<File>,
+    rows: Vec<ExportRow>,
+}
+
+impl ParquetWriter {
+    pub fn new(file: File) -> Result<Self> {
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+
+        let writer = SerializedFileWriter::new(
+            file,
+            Arc::new(parse_message_type(&schema())?),
+            Arc::new(properties),
+        )?;
+
+        Ok(Self {
+            writer,
+            rows: Vec::new(),
+        })
+    }
+
+    fn write_row_group(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut row_group = self.writer.next_row_group()?;
+
+        for column in COLUMNS {
+            let Some(mut writer) = row_group.next_column()? else {
+                break;
+            };
+
+            match column.value {
+                Value::Int(value) => write_column::<Int64Type, _>(
+                    &mut writer,
+                    column.required,
+                    self.rows.iter().map(value),
+                )?,
+                Value::Time(value) => write_column::<Int64Type, _>(
+                    &mut writer,
+                    column.required,
+                    self.rows.iter().map(|x| Some(value(x))),
+                )?,
+                Value::Text(value) => write_column::<ByteArrayType, _>(
+                    &mut writer,
+                    column.required,
+                    self.rows.iter().map(|x| value(x).map(ByteArray::from)),
+                )?,
+            }
+
+            writer.close()?;
+        }
+
+        row_group.close()?;
+        self.rows.clear();
+
+        Ok(())
+    }
+}
+
+fn write_column<T, I>(writer: &mut SerializedColumnWriter, required: bool, values: I) -> Result<()>
+where
+    T: parquet::data_type::DataType,
+    I: Iterator<Item = Option<T::T>>,
+{
+    let mut present = Vec::new();
+    let mut levels = Vec::new();
+
+    for value in values {
+        levels.push(value.is_some() as i16);
+        present.extend(value);
+    }
+
+    let levels = (!required).then_some(levels.as_slice());
+
+    writer.typed::<T>().write_batch(&present, levels, None)?;
+
+    Ok(())
+}
+
+impl RowWriter for ParquetWriter {
+    fn write(&mut self, row: ExportRow) -> Result<()> {
+        self.rows.push(row);
+
+        if self.rows.len() >= ROW_GROUP_SIZE {
+            self.write_row_group()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.write_row_group()?;
+        self.writer.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,519 @@
+use crate::live::message::LiveMessage;
+use crate::stats::revenue::{RevenueReport, paid_value};
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+
+// Boolean expressions over a message, compiled once and evaluated per message:
+//
+//   type == danmaku && medal >= 20
+//   type in [gift, super_chat] && price > 100
+//   uid in [1, 2, 3] || text ~ "抽奖|lottery"
+//
+// Fields: type, room, uid, uname, medal, price (CNY), text, interact (join_room, subscribe
+// or share) and cmd (of a custom message). Comparisons are `== != > >= < <=`, `~` / `!~`
+// match a regex and `in` tests a list. They combine with `&&` / `and`, `||` / `or`,
+// `!` / `not` and parentheses. A field the message does not have, such as the text of a
+// gift or the price of a danmaku, fails every comparison on it. Room ids may be written
+// as numbers or strings.
+#[derive(Clone)]
+pub struct Filter {
+    source: String,
+    root: Node,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Type,
+    Room,
+    Uid,
+    Uname,
+    Medal,
+    Price,
+    Text,
+    Interact,
+    Cmd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Number(Field, Op, f64),
+    NumberIn(Field, Vec<f64>),
+    Text(Field, Op, String),
+    TextIn(Field, HashSet<String>),
+    Matches(Field, Regex),
+}
+
+enum FieldValue<'a> {
+    Number(f64),
+    Text(&'a str),
+}
+
+impl Field {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "type" => Field::Type,
+            "room" => Field::Room,
+            "uid" => Field::Uid,
+            "uname" => Field::Uname,
+            "medal" => Field::Medal,
+            "price" => Field::Price,
+            "text" => Field::Text,
+            "interact" => Field::Interact,
+            "cmd" => Field::Cmd,
+            _ => bail!("unknown field `{name}`"),
+        })
+    }
+
+    fn is_number(self) -> bool {
+        matches!(self, Field::Uid | Field::Medal | Field::Price)
+    }
+
+    fn value<'a>(self, room_id: &'a str, message: &'a LiveMessage) -> Option<FieldValue<'a>> {
+        match self {
+            Field::Type => Some(FieldValue::Text(message.kind())),
+            Field::Room => Some(FieldValue::Text(room_id)),
+            Field::Uid => message.user().map(|x| FieldValue::Number(x.uid() as f64)),
+            Field::Uname => message.user().map(|x| FieldValue::Text(x.uname())),
+            Field::Medal => message
+                .user()
+                .and_then(|x| x.medal_level())
+                .map(|x| FieldValue::Number(x as f64)),
+            Field::Price => paid_value(message).map(|x| FieldValue::Number(RevenueReport::yuan(x))),
+            Field::Text => match message {
+                LiveMessage::Danmaku { text, .. } | LiveMessage::SuperChat { text, .. } => {
+                    Some(FieldValue::Text(text))
+                }
+                _ => None,
+            },
+            Field::Interact => match message {
+                LiveMessage::UserInteract { msg_type, .. } => {
+                    Some(FieldValue::Text(msg_type.name()))
+                }
+                _ => None,
+            },
+            Field::Cmd => match message {
+                LiveMessage::Custom { cmd, .. } => Some(FieldValue::Text(cmd)),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl Op {
+    fn test<T: PartialOrd>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            Op::Eq => lhs == rhs,
+            Op::Ne => lhs != rhs,
+            Op::Gt => lhs > rhs,
+            Op::Ge => lhs >= rhs,
+            Op::Lt => lhs < rhs,
+            Op::Le => lhs <= rhs,
+        }
+    }
+}
+
+impl Node {
+    fn eval(&self, room_id: &str, message: &LiveMessage) -> bool {
+        match self {
+            Node::And(lhs, rhs) => lhs.eval(room_id, message) && rhs.eval(room_id, message),
+            Node::Or(lhs, rhs) => lhs.eval(room_id, message) || rhs.eval(room_id, message),
+            Node::Not(node) => !node.eval(room_id, message),
+            Node::Number(field, op, value) => match field.value(room_id, message) {
+                Some(FieldValue::Number(x)) => op.test(x, *value),
+                _ => false,
+            },
+            Node::NumberIn(field, values) => match field.value(room_id, message) {
+                Some(FieldValue::Number(x)) => values.contains(&x),
+                _ => false,
+            },
+            Node::Text(field, op, value) => match field.value(room_id, message) {
+                Some(FieldValue::Text(x)) => op.test(x, value.as_str()),
+                _ => false,
+            },
+            Node::TextIn(field, values) => match field.value(room_id, message) {
+                Some(FieldValue::Text(x)) => values.contains(x),
+                _ => false,
+            },
+            Node::Matches(field, regex) => match field.value(room_id, message) {
+                Some(FieldValue::Text(x)) => regex.is_match(x),
+                Some(FieldValue::Number(x)) => regex.is_match(&x.to_string()),
+                None => false,
+            },
+        }
+    }
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+
+        let root = parser.parse_or()?;
+
+        if let Some(token) = parser.peek() {
+            bail!("unexpected {token:?} in filter `{source}`")
+        }
+
+        Ok(Self {
+            source: source.into(),
+            root,
+        })
+    }
+
+    pub fn matches(&self, room_id: &str, message: &LiveMessage) -> bool {
+        self.root.eval(room_id, message)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Filter {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+
+        Self::parse(&source).map_err(|err| serde::de::Error::custom(format!("{err:#}")))
+    }
+}
+
+impl Debug for Filter {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "Filter({:?})", self.source)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Text(String),
+    Regex(String),
+    Op(Op),
+    Match,
+    NotMatch,
+    And,
+    Or,
+    Not,
+    In,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        let (token, len) = match (ch, next) {
+            (ch, _) if ch.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('[', _) => (Token::LBracket, 1),
+            (']', _) => (Token::RBracket, 1),
+            (',', _) => (Token::Comma, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('!', Some('~')) => (Token::NotMatch, 2),
+            ('!', _) => (Token::Not, 1),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('~', _) => (Token::Match, 1),
+            ('"', _) | ('/', _) => {
+                let (text, len) = quoted(&chars[i..])?;
+                let token = if ch == '"' {
+                    Token::Text(text)
+                } else {
+                    Token::Regex(text)
+                };
+
+                (token, len)
+            }
+            (ch, _) if ch.is_ascii_digit() || ch == '-' || ch == '.' => {
+                let len = chars[i + 1..]
+                    .iter()
+                    .take_while(|x| x.is_ascii_digit() || **x == '.')
+                    .count()
+                    + 1;
+                let text: String = chars[i..i + len].iter().collect();
+                let number = text
+                    .parse()
+                    .with_context(|| format!("invalid number `{text}`"))?;
+
+                (Token::Number(number), len)
+            }
+            (ch, _) if ch.is_alphanumeric() || ch == '_' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|x| x.is_alphanumeric() || **x == '_')
+                    .count();
+                let word: String = chars[i..i + len].iter().collect();
+
+                let token = match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "in" => Token::In,
+                    _ => Token::Ident(word),
+                };
+
+                (token, len)
+            }
+            (ch, _) => bail!("unexpected `{ch}` at {i}"),
+        };
+
+        tokens.push(token);
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+// a `"string"` or `/regex/`, the delimiter is escaped with a backslash
+fn quoted(chars: &[char]) -> Result<(String, usize)> {
+    let delimiter = chars[0];
+    let mut text = String::new();
+    let mut i = 1;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if chars.get(i + 1) == Some(&delimiter) => {
+                text.push(delimiter);
+                i += 2;
+            }
+            ch if ch == delimiter => return Ok((text, i + 1)),
+            ch => {
+                text.push(ch);
+                i += 1;
+            }
+        }
+    }
+
+    bail!("unterminated {delimiter}")
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+
+        token.context("unexpected end of filter")
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => bail!("expected {expected:?}, found {token:?}"),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Node> {
+        let mut node = self.parse_and()?;
+
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            node = Node::Or(Box::new(node), Box::new(self.parse_and()?));
+        }
+
+        Ok(node)
+    }
+
+    fn parse_and(&mut self) -> Result<Node> {
+        let mut node = self.parse_not()?;
+
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            node = Node::And(Box::new(node), Box::new(self.parse_not()?));
+        }
+
+        Ok(node)
+    }
+
+    fn parse_not(&mut self) -> Result<Node> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Node::Not(Box::new(self.parse_not()?)));
+        }
+
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let node = self.parse_or()?;
+            self.expect(Token::RParen)?;
+            return Ok(node);
+        }
+
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Node> {
+        let field = match self.next()? {
+            Token::Ident(name) => Field::parse(&name)?,
+            token => bail!("expected a field, found {token:?}"),
+        };
+
+        match self.next()? {
+            Token::Op(op) => self.parse_compare(field, op),
+            Token::Match => self.parse_regex(field),
+            Token::NotMatch => Ok(Node::Not(Box::new(self.parse_regex(field)?))),
+            Token::In => self.parse_in(field),
+            token => bail!("expected an operator, found {token:?}"),
+        }
+    }
+
+    fn parse_compare(&mut self, field: Field, op: Op) -> Result<Node> {
+        match (self.next()?, field.is_number()) {
+            (Token::Number(value), true) => Ok(Node::Number(field, op, value)),
+            (Token::Number(value), false) if field == Field::Room => {
+                Ok(Node::Text(field, op, value.to_string()))
+            }
+            (Token::Text(value) | Token::Ident(value), false) => Ok(Node::Text(field, op, value)),
+            (token, true) => bail!("{field:?} compares with a number, found {token:?}"),
+            (token, false) => bail!("{field:?} compares with a string, found {token:?}"),
+        }
+    }
+
+    fn parse_regex(&mut self, field: Field) -> Result<Node> {
+        match self.next()? {
+            Token::Text(pattern) | Token::Regex(pattern) => Ok(Node::Matches(
+                field,
+                Regex::new(&pattern).with_context(|| format!("invalid regex `{pattern}`"))?,
+            )),
+            token => bail!("expected a regex, found {token:?}"),
+        }
+    }
+
+    fn parse_in(&mut self, field: Field) -> Result<Node> {
+        self.expect(Token::LBracket)?;
+
+        let mut numbers = Vec::new();
+        let mut texts = HashSet::new();
+
+        loop {
+            match (self.next()?, field.is_number()) {
+                (Token::RBracket, _) => break,
+                (Token::Number(value), true) => numbers.push(value),
+                (Token::Text(value) | Token::Ident(value), false) => {
+                    texts.insert(value);
+                }
+                (Token::Number(value), false) if field == Field::Room => {
+                    texts.insert(value.to_string());
+                }
+                (token, _) => bail!("unexpected {token:?} in list of {field:?}"),
+            }
+
+            match self.next()? {
+                Token::Comma => (),
+                Token::RBracket => break,
+                token => bail!("expected `,` or `]`, found {token:?}"),
+            }
+        }
+
+        if field.is_number() {
+            Ok(Node::NumberIn(field, numbers))
+        } else {
+            Ok(Node::TextIn(field, texts))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::message::RawMessage;
+    use serde_json::json;
+
+    fn danmaku(uid: u64, medal: i64, text: &str) -> LiveMessage {
+        let raw = RawMessage::new(
+            "100",
+            json!({
+                "cmd": "DANMU_MSG",
+                "info": [
+                    [0, 1, 25, 16777215, 1700000000000u64, 0, 0, "", 0, 0, 0, "", 0, "{}", "{}", {
+                        "extra": "{}",
+                        "user": {"uid": uid, "base": {"name": "alice", "face": ""}, "medal": {"level": medal, "score": 0}}
+                    }],
+                    text,
+                    [uid, "alice"],
+                    [], [], [], 0, 0, null, {}, 0, 0, null, null, 0, 0, [0]
+                ]
+            }),
+        );
+
+        LiveMessage::try_from(&raw).unwrap()
+    }
+
+    fn matches(filter: &str, message: &LiveMessage) -> bool {
+        Filter::parse(filter).unwrap().matches("100", message)
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let message = danmaku(42, 21, "来抽奖了");
+
+        assert!(matches("type == danmaku && medal >= 20", &message));
+        assert!(!matches("type == danmaku and medal > 21", &message));
+        assert!(matches("uid in [1, 42] || uname == \"bob\"", &message));
+        assert!(matches(
+            "text ~ /抽奖|lottery/ && room == \"100\"",
+            &message
+        ));
+        assert!(matches("room == 100 && room in [1, 100]", &message));
+        assert!(!matches("room == 10 || room in [1, 2]", &message));
+        assert!(!matches("price < 1 || price >= 0", &message));
+        assert!(matches("not (type in [gift, super_chat])", &message));
+        assert!(!matches("text !~ \"抽奖\"", &message));
+        assert!(!matches("type == gift || price > 100", &message));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for filter in [
+            "",
+            "level > 1",
+            "uid == alice",
+            "uname > 3",
+            "text ~ /(/",
+            "type == danmaku &&",
+            "(type == gift",
+            "uid in [1, 2",
+            "\"unterminated",
+        ] {
+            assert!(Filter::parse(filter).is_err(), "{filter}");
+        }
+    }
+}
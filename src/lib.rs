@@ -0,0 +1,13 @@
+// The library behind the `blivedm_rs` binary. Code embedding it can register parsers for
+// more cmds through `live::message::registry::PARSERS` before connecting.
+pub mod config;
+pub mod data;
+pub mod export;
+pub mod filter;
+pub mod live;
+pub mod redact;
+pub mod replay;
+pub mod server;
+pub mod sink;
+pub mod stats;
+pub mod watcher;
@@ -1,106 +1,677 @@
 pub mod credential;
 pub mod message;
+pub mod packet;
+pub mod pool;
+pub mod room;
+pub mod session;
+pub mod wbi;
 
-use crate::live::credential::Credential;
+use crate::live::credential::{Credential, Secret};
 use crate::live::message::RawMessage;
-use client::models::BiliMessage;
-use client::websocket::BiliLiveClient;
-use futures_channel::mpsc;
-use futures_util::StreamExt;
-use log::{debug, error};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::thread;
+use crate::live::packet::{
+    OP_AUTH, OP_AUTH_REPLY, OP_HEARTBEAT, OP_HEARTBEAT_REPLY, OP_MESSAGE, PROTOVER_ZLIB, Packet,
+};
+use crate::live::room::{DanmuInfo, Endpoints};
+use futures_channel::{mpsc, oneshot};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, error, trace, warn};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::Duration;
-use tokio::task;
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
 use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio::{task, time};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
 
-pub struct LiveClient {
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36";
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
+// every heartbeat is answered, silence for this long means the connection is dead
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExitReason {
+    Stopped,      // 调用了 close
+    AuthFailed,   // 认证失败（服务端拒绝了认证包）
+    ServerClosed, // 服务端关闭连接
+    Io(String),   // 请求或读写错误
+}
+
+impl From<WsError> for ExitReason {
+    fn from(err: WsError) -> Self {
+        match err {
+            WsError::ConnectionClosed | WsError::AlreadyClosed => Self::ServerClosed,
+            err => Self::Io(err.to_string()),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ExitReason {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Io(format!("{err:#}"))
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientEvent {
+    Connected,  // 已连接并发送认证包
+    AuthOk,     // 服务端接受了认证包
+    AuthFailed, // 服务端拒绝了认证包
+    HeartbeatReply {
+        // 心跳回复
+        popularity: u64,   // 人气值（服务端已固定为 1）
+        latency: Duration, // 心跳往返耗时
+    },
+    Message(RawMessage),                     // 业务消息
+    Undecodable(Vec<u8>, serde_json::Error), // 不是 JSON 的业务消息
+    Disconnected(ExitReason),
+    Reconnecting {
+        // 准备重连
+        attempt: u32,    // 第几次重连
+        delay: Duration, // 重连前等待时间
+    },
+}
+
+// Counts events sent but not yet taken by `next_event`
+#[derive(Clone)]
+struct EventSender {
+    tx: mpsc::Sender<ClientEvent>,
+    backlog: Arc<AtomicUsize>,
+}
+
+impl EventSender {
+    async fn send(&mut self, event: ClientEvent) {
+        self.backlog.fetch_add(1, Ordering::SeqCst);
+
+        if self.tx.send(event).await.is_err() {
+            self.backlog.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn try_send(&mut self, event: ClientEvent) {
+        self.backlog.fetch_add(1, Ordering::SeqCst);
+
+        if self.tx.try_send(event).is_err() {
+            self.backlog.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+// Everything a connection needs to get into the room, shared by every reconnect
+#[derive(Clone)]
+struct Login {
     room_id: String,
-    rx: mpsc::Receiver<BiliMessage>,
-    client: Arc<Mutex<BiliLiveClient>>,
+    uid: u64,
+    cookie: Secret,
+    buvid: String,
+    endpoints: Endpoints,
+    http: reqwest::Client,
+}
+
+pub struct LiveClient {
+    login: Login,
+    events: mpsc::Receiver<ClientEvent>,
+    events_tx: Option<EventSender>,
+    backlog: Arc<AtomicUsize>,
     stop: Arc<AtomicBool>,
+    stop_notify: Arc<Notify>,
+    done: Option<oneshot::Receiver<()>>,
 }
 
 impl LiveClient {
-    pub fn new(room_id: &str, credential: &Credential) -> LiveClient {
+    // `uid` is the one the credential is logged in as, the auth packet is rejected otherwise
+    pub fn new(room_id: &str, credential: &Credential, uid: u64) -> LiveClient {
+        Self::with_endpoints(room_id, credential, uid, Endpoints::default())
+    }
+
+    pub fn with_endpoints(
+        room_id: &str,
+        credential: &Credential,
+        uid: u64,
+        endpoints: Endpoints,
+    ) -> LiveClient {
         let (tx, rx) = mpsc::channel(16);
+        let backlog = Arc::new(AtomicUsize::new(0));
 
         Self {
-            room_id: room_id.into(),
-            rx,
-            client: Arc::new(Mutex::new(BiliLiveClient::new(
-                &credential.to_string(),
-                room_id,
+            login: Login {
+                room_id: room_id.into(),
+                uid,
+                cookie: credential.cookie_header(),
+                buvid: credential.buvid3().unwrap_or_default().into(),
+                endpoints,
+                http: reqwest::Client::builder()
+                    .user_agent(USER_AGENT)
+                    .build()
+                    .expect("failed to build http client"),
+            },
+            events: rx,
+            events_tx: Some(EventSender {
                 tx,
-            ))),
+                backlog: backlog.clone(),
+            }),
+            backlog,
             stop: Arc::new(AtomicBool::new(false)),
+            stop_notify: Arc::new(Notify::new()),
+            done: None,
         }
     }
 
-    pub fn connect(&mut self) -> JoinHandle<()> {
-        let mut proxy = self.client.lock().expect("failed to lock client");
+    pub fn connect(&mut self) -> JoinHandle<ExitReason> {
+        let mut events = self.events_tx.take().expect("client already connected");
+        let (done_tx, done_rx) = oneshot::channel();
+
+        self.done = Some(done_rx);
+
+        let login = self.login.clone();
+        let stop = self.stop.clone();
+        let stop_notify = self.stop_notify.clone();
+
+        task::spawn(async move {
+            let mut attempt = 0;
+
+            let reason = loop {
+                let connection = Connection {
+                    login: &login,
+                    events: events.clone(),
+                    stop_notify: &stop_notify,
+                    authenticated: false,
+                };
+
+                let (reason, authenticated) = connection.run().await;
+
+                if reason == ExitReason::AuthFailed {
+                    events.send(ClientEvent::AuthFailed).await;
+                }
+
+                events.send(ClientEvent::Disconnected(reason.clone())).await;
+
+                if stop.load(Ordering::SeqCst)
+                    || matches!(reason, ExitReason::Stopped | ExitReason::AuthFailed)
+                {
+                    break reason;
+                }
+
+                attempt = if authenticated { 1 } else { attempt + 1 };
+
+                if attempt > MAX_RECONNECT_ATTEMPTS {
+                    error!("giving up after {MAX_RECONNECT_ATTEMPTS} reconnect attempts");
+                    break reason;
+                }
 
-        proxy.send_auth();
+                let delay = reconnect_delay(attempt);
 
-        let heartbeat_client = self.client.clone();
-        let heartbeat_sig = self.stop.clone();
+                warn!("connection lost ({reason:?}), reconnecting in {delay:?}");
 
-        let heartbeat_task = task::spawn_blocking(move || {
+                events
+                    .send(ClientEvent::Reconnecting { attempt, delay })
+                    .await;
+
+                tokio::select! {
+                    _ = stop_notify.notified() => break ExitReason::Stopped,
+                    _ = time::sleep(delay) => (),
+                }
+            };
+
+            let _ = done_tx.send(());
+
+            reason
+        })
+    }
+
+    pub async fn next_event(&mut self) -> Option<ClientEvent> {
+        let event = self.events.next().await;
+
+        if event.is_some() {
+            self.backlog.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        event
+    }
+
+    // events waiting to be taken by `next_event`
+    pub fn backlog(&self) -> usize {
+        self.backlog.load(Ordering::SeqCst)
+    }
+
+    pub async fn close(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.stop_notify.notify_one();
+
+        if let Some(mut done) = self.done.take() {
+            // keep draining so the background task never blocks on a full channel
             loop {
-                if heartbeat_sig.load(Ordering::SeqCst) {
-                    break;
+                tokio::select! {
+                    _ = &mut done => break,
+                    _ = self.events.next() => (),
                 }
+            }
+        }
+    }
+}
 
-                match heartbeat_client.lock() {
-                    Ok(mut proxy) => {
-                        debug!("heartbeat");
-                        proxy.send_heart_beat();
-                    }
-                    Err(err) => {
-                        error!("error acquiring client heartbeat: {err:?}");
-                        break;
+// 1s, 2s, 4s ... up to `MAX_RECONNECT_DELAY`, counted from 1
+fn reconnect_delay(attempt: u32) -> Duration {
+    Duration::from_secs(1 << (attempt - 1).min(6)).min(MAX_RECONNECT_DELAY)
+}
+
+struct Connection<'a> {
+    login: &'a Login,
+    events: EventSender,
+    stop_notify: &'a Notify,
+    authenticated: bool,
+}
+
+impl Connection<'_> {
+    // runs a single websocket session, returns why it ended and whether the server accepted
+    // the auth packet
+    async fn run(mut self) -> (ExitReason, bool) {
+        let socket = tokio::select! {
+            _ = self.stop_notify.notified() => return (ExitReason::Stopped, false),
+            socket = self.open() => socket,
+        };
+
+        let reason = match socket {
+            Ok(socket) => {
+                self.events.send(ClientEvent::Connected).await;
+                self.session(socket).await
+            }
+            Err(reason) => reason,
+        };
+
+        (reason, self.authenticated)
+    }
+
+    // looks up the servers and the token, connects and sends the auth packet
+    async fn open(&self) -> Result<Socket, ExitReason> {
+        let login = self.login;
+        let info =
+            DanmuInfo::fetch(&login.http, &login.endpoints, &login.room_id, &login.cookie).await?;
+
+        let auth = json!({
+            "uid": login.uid,
+            "roomid": info.room_id,
+            "protover": PROTOVER_ZLIB,
+            "buvid": login.buvid,
+            "platform": "web",
+            "type": 2,
+            "key": info.token.expose(),
+        });
+
+        let mut reason = ExitReason::Io("no danmaku server".into());
+
+        for server in &info.servers {
+            match connect_async(server.as_str()).await {
+                Ok((mut socket, _)) => {
+                    let packet = Packet::new(OP_AUTH, auth.to_string());
+
+                    socket.send(Message::Binary(packet.encode().into())).await?;
+
+                    return Ok(socket);
+                }
+                Err(err) => {
+                    warn!("[{}] failed to connect to {server}: {err}", login.room_id);
+                    reason = err.into();
+                }
+            }
+        }
+
+        Err(reason)
+    }
+
+    async fn session(&mut self, mut socket: Socket) -> ExitReason {
+        let mut heartbeat = time::interval(HEARTBEAT_INTERVAL);
+        let mut heartbeat_sent = Instant::now();
+        let mut last_read = Instant::now();
+
+        loop {
+            tokio::select! {
+                _ = self.stop_notify.notified() => return close(socket).await,
+                _ = heartbeat.tick() => {
+                    debug!("heartbeat");
+                    heartbeat_sent = Instant::now();
+
+                    let packet = Packet::new(OP_HEARTBEAT, []);
+
+                    if let Err(err) = socket.send(Message::Binary(packet.encode().into())).await {
+                        return err.into();
                     }
                 }
+                _ = time::sleep_until(last_read + READ_TIMEOUT) => {
+                    return ExitReason::Io(format!("nothing received for {READ_TIMEOUT:?}"));
+                }
+                frame = socket.next() => {
+                    last_read = Instant::now();
 
-                thread::sleep(Duration::from_secs(20));
+                    match frame {
+                        Some(Ok(Message::Binary(data))) => match Packet::decode(&data) {
+                            Ok(packets) => {
+                                for packet in packets {
+                                    if let Some(reason) = self.handle(packet, heartbeat_sent).await {
+                                        return reason;
+                                    }
+                                }
+                            }
+                            Err(err) => warn!("[{}] dropping frame: {err:#}", self.login.room_id),
+                        },
+                        // answered by tungstenite, the stream ends right after
+                        Some(Ok(Message::Close(frame))) => debug!("server closed: {frame:?}"),
+                        Some(Ok(_)) => (),
+                        Some(Err(err)) => return err.into(),
+                        None => return ExitReason::ServerClosed,
+                    }
+                }
             }
-        });
+        }
+    }
 
-        let recvmsg_client = self.client.clone();
-        let recvmsg_sig = self.stop.clone();
+    // returns why the session ends when the packet ends it
+    async fn handle(&mut self, packet: Packet, heartbeat_sent: Instant) -> Option<ExitReason> {
+        let room_id = &self.login.room_id;
 
-        let recvmsg_task = task::spawn_blocking(move || {
-            loop {
-                if recvmsg_sig.load(Ordering::SeqCst) {
-                    break;
+        match packet.op {
+            OP_AUTH_REPLY => {
+                let reply: Option<Value> = serde_json::from_slice(&packet.body).ok();
+
+                if reply.as_ref().and_then(|x| x["code"].as_i64()) != Some(0) {
+                    warn!(
+                        "[{room_id}] auth rejected: {}",
+                        String::from_utf8_lossy(&packet.body)
+                    );
+                    return Some(ExitReason::AuthFailed);
                 }
 
-                match recvmsg_client.lock() {
-                    Ok(mut proxy) => proxy.recive(),
-                    Err(_) => {}
+                self.authenticated = true;
+                self.events.send(ClientEvent::AuthOk).await;
+            }
+            OP_HEARTBEAT_REPLY => {
+                let popularity = packet
+                    .body
+                    .first_chunk()
+                    .map_or(0, |x| u32::from_be_bytes(*x));
+
+                // heartbeat events are informational, drop them if the consumer lags behind
+                self.events.try_send(ClientEvent::HeartbeatReply {
+                    popularity: popularity.into(),
+                    latency: heartbeat_sent.elapsed(),
+                });
+            }
+            OP_MESSAGE => match serde_json::from_slice(&packet.body) {
+                Ok(data) => {
+                    self.events
+                        .send(ClientEvent::Message(RawMessage::new(room_id, data)))
+                        .await;
+                }
+                Err(err) => {
+                    self.events
+                        .send(ClientEvent::Undecodable(packet.body, err))
+                        .await;
                 }
+            },
+            op => trace!("ignored packet with op {op}"),
+        }
+
+        None
+    }
+}
+
+// sends a close frame and waits for the server to answer it
+async fn close(mut socket: Socket) -> ExitReason {
+    match socket.close(None).await {
+        Ok(()) => {
+            let drain = async { while let Some(Ok(_)) = socket.next().await {} };
 
-                thread::sleep(Duration::from_millis(50));
+            if time::timeout(CLOSE_TIMEOUT, drain).await.is_err() {
+                debug!("no close frame from server in {CLOSE_TIMEOUT:?}");
             }
-        });
+        }
+        Err(err) => debug!("failed to send close frame: {err}"),
+    }
 
-        task::spawn(async move {
-            tokio::select! {
-                _ = heartbeat_task => (),
-                _ = recvmsg_task => (),
+    ExitReason::Stopped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+    use axum::extract::{Query, State};
+    use axum::response::Response;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc as tokio_mpsc;
+
+    const ROOM_ID: u64 = 21452505;
+    const TOKEN: &str = "t0ken";
+
+    // stands in for the live APIs and the danmaku server
+    #[derive(Clone)]
+    struct StandIn {
+        port: u16,
+        auth_code: i64,
+        sessions: Arc<AtomicUsize>,
+        closed: tokio_mpsc::UnboundedSender<usize>,
+    }
+
+    async fn stand_in(auth_code: i64) -> (Endpoints, tokio_mpsc::UnboundedReceiver<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (closed, closed_rx) = tokio_mpsc::unbounded_channel();
+
+        let state = StandIn {
+            port,
+            auth_code,
+            sessions: Arc::new(AtomicUsize::new(0)),
+            closed,
+        };
+
+        let app = Router::new()
+            .route(
+                "/room_init",
+                get(|| async { Json(json!({"code": 0, "data": {"room_id": ROOM_ID}})) }),
+            )
+            .route("/nav", get(nav))
+            .route("/danmu_info", get(danmu_info))
+            .route("/sub", get(sub))
+            .with_state(state);
+
+        task::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let endpoint = |path| format!("http://127.0.0.1:{port}/{path}");
+        let endpoints = Endpoints {
+            room_init: endpoint("room_init"),
+            nav: endpoint("nav"),
+            danmu_info: endpoint("danmu_info"),
+            secure: false,
+        };
+
+        (endpoints, closed_rx)
+    }
+
+    async fn nav() -> Json<Value> {
+        Json(json!({
+            "code": -101,
+            "data": {
+                "isLogin": false,
+                "wbi_img": {
+                    "img_url": "https://i0.hdslb.com/bfs/wbi/7cd084941338484aae1ad9425b84077c.png",
+                    "sub_url": "https://i0.hdslb.com/bfs/wbi/4932caff0ff746eab6f01bf08b70ac45.png"
+                }
             }
-        })
+        }))
     }
 
-    pub async fn next_message(&mut self) -> Option<RawMessage> {
-        match self.rx.next().await {
-            Some(BiliMessage::Raw { data }) => Some(RawMessage::new(&self.room_id, data)),
-            _ => None,
+    async fn danmu_info(
+        State(state): State<StandIn>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Json<Value> {
+        if !query.contains_key("w_rid") || query["id"] != ROOM_ID.to_string() {
+            return Json(json!({"code": -352, "message": "-352"}));
         }
+
+        Json(json!({
+            "code": 0,
+            "data": {
+                "token": TOKEN,
+                "host_list": [{"host": "127.0.0.1", "ws_port": state.port, "wss_port": 443}]
+            }
+        }))
     }
 
-    pub async fn close(&mut self) {
-        self.stop.store(true, Ordering::SeqCst);
+    async fn sub(State(state): State<StandIn>, upgrade: WebSocketUpgrade) -> Response {
+        upgrade.on_upgrade(move |socket| session(state, socket))
+    }
+
+    async fn recv(socket: &mut WebSocket) -> Packet {
+        match socket.recv().await {
+            Some(Ok(WsMessage::Binary(data))) => Packet::decode(&data).unwrap().remove(0),
+            message => panic!("expected a packet, got {message:?}"),
+        }
+    }
+
+    async fn reply(socket: &mut WebSocket, packet: Packet) {
+        socket
+            .send(WsMessage::Binary(packet.encode().into()))
+            .await
+            .unwrap();
+    }
+
+    // the first session answers a heartbeat, pushes a danmaku and hangs up, the later ones
+    // stay open and report close frames from the client
+    async fn session(state: StandIn, mut socket: WebSocket) {
+        let index = state.sessions.fetch_add(1, Ordering::SeqCst);
+
+        let auth = recv(&mut socket).await;
+        let body: Value = serde_json::from_slice(&auth.body).unwrap();
+
+        assert_eq!(auth.op, OP_AUTH);
+        assert_eq!(body["roomid"], ROOM_ID);
+        assert_eq!(body["uid"], 42);
+        assert_eq!(body["key"], TOKEN);
+        assert_eq!(body["buvid"], "b3");
+
+        let code = json!({"code": state.auth_code}).to_string();
+        reply(&mut socket, Packet::new(OP_AUTH_REPLY, code)).await;
+
+        if state.auth_code != 0 {
+            return;
+        }
+
+        if index == 0 {
+            assert_eq!(recv(&mut socket).await.op, OP_HEARTBEAT);
+            reply(
+                &mut socket,
+                Packet::new(OP_HEARTBEAT_REPLY, 1u32.to_be_bytes()),
+            )
+            .await;
+
+            let danmaku = Packet {
+                op: OP_MESSAGE,
+                protover: 0,
+                body: include_str!("live/message/fixtures/danmu_msg.json").into(),
+            };
+
+            reply(&mut socket, danmaku).await;
+            socket.send(WsMessage::Close(None)).await.unwrap();
+
+            while let Some(Ok(_)) = socket.recv().await {}
+        } else {
+            while let Some(Ok(message)) = socket.recv().await {
+                if let WsMessage::Close(_) = message {
+                    state.closed.send(index).unwrap();
+                }
+            }
+        }
+    }
+
+    fn client(endpoints: Endpoints) -> LiveClient {
+        let credential = Credential::from_cookie_header("SESSDATA=s3ss; buvid3=b3").unwrap();
+        LiveClient::with_endpoints(&ROOM_ID.to_string(), &credential, 42, endpoints)
+    }
+
+    async fn events(client: &mut LiveClient, count: usize) -> Vec<ClientEvent> {
+        let mut events = Vec::new();
+
+        while events.len() < count {
+            let event = time::timeout(Duration::from_secs(10), client.next_event()).await;
+            events.push(event.unwrap().unwrap());
+        }
+
+        events
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_closes_with_a_close_frame() {
+        let (endpoints, mut closed) = stand_in(0).await;
+        let mut client = client(endpoints);
+        let handle = client.connect();
+
+        let events = events(&mut client, 8).await;
+
+        assert!(
+            matches!(
+                events.as_slice(),
+                [
+                    ClientEvent::Connected,
+                    ClientEvent::AuthOk,
+                    ClientEvent::HeartbeatReply { popularity: 1, .. },
+                    ClientEvent::Message(raw),
+                    ClientEvent::Disconnected(ExitReason::ServerClosed),
+                    ClientEvent::Reconnecting { attempt: 1, delay },
+                    ClientEvent::Connected,
+                    ClientEvent::AuthOk,
+                ] if raw.msg_type().ok() == Some("DANMU_MSG") && *delay == reconnect_delay(1)
+            ),
+            "{events:?}"
+        );
+
+        client.close().await;
+
+        assert_eq!(handle.await.unwrap(), ExitReason::Stopped);
+
+        let closed = time::timeout(CLOSE_TIMEOUT, closed.recv()).await.unwrap();
+        assert_eq!(closed, Some(1));
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_auth_is_rejected() {
+        let (endpoints, _closed) = stand_in(-101).await;
+        let mut client = client(endpoints);
+        let handle = client.connect();
+
+        let events = events(&mut client, 3).await;
+
+        assert!(
+            matches!(
+                events.as_slice(),
+                [
+                    ClientEvent::Connected,
+                    ClientEvent::AuthFailed,
+                    ClientEvent::Disconnected(ExitReason::AuthFailed),
+                ]
+            ),
+            "{events:?}"
+        );
+
+        assert_eq!(handle.await.unwrap(), ExitReason::AuthFailed);
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let delays: Vec<_> = (1..=MAX_RECONNECT_ATTEMPTS)
+            .map(|x| reconnect_delay(x).as_secs())
+            .collect();
+
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60, 60, 60]);
     }
 }
@@ -1,20 +1,501 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, TimeZone};
+use serde_json::Value;
 use std::fmt;
-use std::fmt::{Display, Formatter};
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zeroize::{Zeroize, Zeroizing};
 
+const NAV_API: &str = "https://api.bilibili.com/x/web-interface/nav";
+
+// Wiped on drop and never printed, call `expose` where the raw value is really needed
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: &str) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Secret {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self(String::deserialize(deserializer)?))
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "<redacted>")
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "<redacted>")
+    }
+}
+
+// The whole file in a buffer that is wiped on drop, sized up front so that reading does not
+// leave partial copies behind in reallocated memory
+pub fn read_secret_file(path: &Path) -> Result<Zeroizing<String>> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len() as usize;
+    let mut content = Zeroizing::new(String::with_capacity(size + 1));
+
+    file.read_to_string(&mut content)?;
+
+    Ok(content)
+}
+
+#[derive(Debug)]
 pub struct Credential {
-    sessdata: String,
+    sessdata: Secret,
+    bili_jct: Option<Secret>,
+    buvid3: Option<String>,
+    uid: Option<u64>,
+    expires: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct LoginInfo {
+    pub uid: u64,      // UID
+    pub uname: String, // 用户名
+}
+
+// Errors of `check` are failures to ask, the answer itself says whether the credential is good
+#[derive(Debug)]
+pub enum LoginCheck {
+    LoggedIn(LoginInfo),
+    Rejected(String), // 凭证失效：已过期、未登录或与 DedeUserID 不符
 }
 
 impl Credential {
     pub fn from_sessdata(sessdata: &str) -> Self {
         Self {
-            sessdata: sessdata.into(),
+            sessdata: Secret::new(sessdata),
+            bili_jct: None,
+            buvid3: None,
+            uid: None,
+            expires: None,
+        }
+    }
+
+    pub fn from_cookie_header(header: &str) -> Result<Self> {
+        let cookies = header
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| (name.trim(), value.trim(), None));
+
+        Self::from_cookies(cookies)
+    }
+
+    pub fn from_cookies_file(file: &dyn AsRef<Path>) -> Result<Self> {
+        let content = read_secret_file(file.as_ref()).context("failed to read cookies file")?;
+
+        Self::from_cookies_txt(&content)
+    }
+
+    // https://curl.se/docs/http-cookies.html
+    pub fn from_cookies_txt(content: &str) -> Result<Self> {
+        let cookies = content
+            .lines()
+            .map(|line| line.strip_prefix("#HttpOnly_").unwrap_or(line))
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let fields: Vec<_> = line.split('\t').collect();
+
+                match fields[..] {
+                    [domain, _, _, _, expires, name, value] if domain.ends_with("bilibili.com") => {
+                        Some((name, value, expires.parse().ok().filter(|&ts| ts > 0)))
+                    }
+                    _ => None,
+                }
+            });
+
+        Self::from_cookies(cookies)
+    }
+
+    fn from_cookies<'a, I: Iterator<Item = (&'a str, &'a str, Option<i64>)>>(
+        cookies: I,
+    ) -> Result<Self> {
+        let mut sessdata = None;
+        let mut credential = Self::from_sessdata("");
+
+        for (name, value, expires) in cookies {
+            match name {
+                "SESSDATA" => {
+                    sessdata = Some(value);
+                    credential.expires = expires;
+                }
+                "bili_jct" => credential.bili_jct = Some(Secret::new(value)),
+                "buvid3" => credential.buvid3 = Some(value.into()),
+                "DedeUserID" => credential.uid = value.parse().ok(),
+                _ => (),
+            }
+        }
+
+        credential.sessdata = Secret::new(sessdata.context("SESSDATA not found in cookies")?);
+
+        Ok(credential)
+    }
+
+    pub fn uid(&self) -> Option<u64> {
+        self.uid
+    }
+
+    pub fn buvid3(&self) -> Option<&str> {
+        self.buvid3.as_deref()
+    }
+
+    pub fn expires_at(&self) -> Option<DateTime<Local>> {
+        // SESSDATA looks like `<token>%2C<expires>%2C<hash>` when not given by the cookie jar,
+        // split it in place so no unzeroed copy is left behind
+        let expires = self.expires.or_else(|| {
+            self.sessdata
+                .expose()
+                .split(',')
+                .flat_map(|x| x.split("%2C"))
+                .nth(1)
+                .and_then(|ts| ts.parse().ok())
+        });
+
+        expires.and_then(|ts| Local.timestamp_opt(ts, 0).single())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at().is_some_and(|ts| ts <= Local::now())
+    }
+
+    pub fn cookie_header(&self) -> Secret {
+        let uid = self.uid.map(|x| x.to_string());
+        let cookies = [
+            ("SESSDATA", Some(self.sessdata.expose())),
+            ("bili_jct", self.bili_jct.as_ref().map(Secret::expose)),
+            ("buvid3", self.buvid3.as_deref()),
+            ("DedeUserID", uid.as_deref()),
+        ];
+
+        // sized up front, growing would leave the old buffer behind without zeroing it
+        let capacity = cookies
+            .iter()
+            .filter_map(|&(name, value)| Some("; =".len() + name.len() + value?.len()))
+            .sum();
+
+        let mut header = Secret(String::with_capacity(capacity));
+
+        for (name, value) in cookies {
+            let Some(value) = value else {
+                continue;
+            };
+
+            if !header.0.is_empty() {
+                header.0.push_str("; ");
+            }
+
+            header.0.push_str(name);
+            header.0.push('=');
+            header.0.push_str(value);
+        }
+
+        header
+    }
+
+    pub async fn check(&self) -> Result<LoginCheck> {
+        self.check_with(NAV_API).await
+    }
+
+    pub async fn check_with(&self, endpoint: &str) -> Result<LoginCheck> {
+        if self.is_expired() {
+            return Ok(LoginCheck::Rejected(format!(
+                "credential expired at {:?}",
+                self.expires_at()
+            )));
         }
+
+        let response: Value = reqwest::Client::new()
+            .get(endpoint)
+            .header(reqwest::header::COOKIE, self.cookie_header().expose())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let code = response["code"].as_i64().context("failed to parse code")?;
+
+        // anything else, like -352 or -412 from the risk control, says nothing about the cookie
+        if code == -101 || (code == 0 && response["data"]["isLogin"].as_bool() != Some(true)) {
+            return Ok(LoginCheck::Rejected(format!(
+                "credential is not logged in: {} ({code})",
+                response["message"].as_str().unwrap_or_default()
+            )));
+        }
+
+        if code != 0 {
+            bail!(
+                "nav failed: {} ({code})",
+                response["message"].as_str().unwrap_or_default()
+            )
+        }
+
+        let info = LoginInfo {
+            uid: response["data"]["mid"]
+                .as_u64()
+                .context("failed to parse mid")?,
+            uname: response["data"]["uname"]
+                .as_str()
+                .context("failed to parse uname")?
+                .into(),
+        };
+
+        if let Some(uid) = self.uid
+            && uid != info.uid
+        {
+            return Ok(LoginCheck::Rejected(format!(
+                "DedeUserID {uid} does not match logged in uid {}",
+                info.uid
+            )));
+        }
+
+        Ok(LoginCheck::LoggedIn(info))
     }
 }
 
 impl Display for Credential {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
-        write!(fmt, "SESSDATA={}", self.sessdata)
+        write!(fmt, "SESSDATA={}", self.sessdata)?;
+
+        if let Some(bili_jct) = &self.bili_jct {
+            write!(fmt, "; bili_jct={bili_jct}")?;
+        }
+
+        if let Some(buvid3) = &self.buvid3 {
+            write!(fmt, "; buvid3={buvid3}")?;
+        }
+
+        if let Some(uid) = self.uid {
+            write!(fmt, "; DedeUserID={uid}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::http::HeaderMap;
+    use axum::routing::get;
+    use log::{error, info};
+    use serde_json::json;
+    use std::io;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    const RAW_SESSDATA: &str = "d2b7a1c3%2C4102444800%2Cf00ba*11";
+    const RAW_BILI_JCT: &str = "9f8e7d6c5b4a39281706f5e4d3c2b1a0";
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_never_contain_secrets() {
+        let capture = Capture::default();
+        let writer = capture.clone();
+
+        // only for this thread, other tests may have installed their own
+        let _subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish()
+            .set_default();
+
+        let credential = Credential::from_cookie_header(&format!(
+            "SESSDATA={RAW_SESSDATA}; bili_jct={RAW_BILI_JCT}; DedeUserID=42"
+        ))
+        .unwrap();
+
+        info!("display: {credential}");
+        info!("debug: {credential:?}");
+        info!("pretty: {credential:#?}");
+        info!("header: {:?}", credential.cookie_header());
+
+        // nothing listens on port 1, the request fails with a connection error
+        if let Err(err) = credential.check_with("http://127.0.0.1:1/nav").await {
+            error!("check failed: {err:?}");
+        }
+
+        let header = credential.cookie_header();
+
+        assert!(header.expose().contains(RAW_SESSDATA));
+        assert_eq!(header.0.capacity(), header.0.len() + 2);
+
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+
+        assert!(output.contains("SESSDATA=<redacted>"));
+        assert!(output.contains("check failed"));
+        assert!(!output.contains(RAW_SESSDATA));
+        assert!(!output.contains(RAW_BILI_JCT));
+        assert!(!output.contains("d2b7a1c3"));
+    }
+
+    // answers like the nav API, depending on the SESSDATA sent
+    async fn stand_in(requests: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let app = Router::new().route(
+            "/nav",
+            get(move |headers: HeaderMap| async move {
+                requests.fetch_add(1, Ordering::SeqCst);
+
+                let cookie = headers[axum::http::header::COOKIE].to_str().unwrap();
+
+                let response = if cookie.starts_with("SESSDATA=logged_in") {
+                    json!({"code": 0, "data": {"isLogin": true, "mid": 42, "uname": "bili_42"}})
+                } else if cookie.starts_with("SESSDATA=risky") {
+                    json!({"code": -352, "message": "-352"})
+                } else {
+                    json!({"code": -101, "message": "账号未登录", "data": {"isLogin": false}})
+                };
+
+                axum::Json(response)
+            }),
+        );
+
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        format!("http://{addr}/nav")
+    }
+
+    #[tokio::test]
+    async fn checks_login_state() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let endpoint = stand_in(requests.clone()).await;
+
+        let check = |header: &str| {
+            let credential = Credential::from_cookie_header(header).unwrap();
+            let endpoint = endpoint.clone();
+
+            async move { credential.check_with(&endpoint).await }
+        };
+
+        match check("SESSDATA=logged_in%2C4102444800%2Cf00b")
+            .await
+            .unwrap()
+        {
+            LoginCheck::LoggedIn(info) => {
+                assert_eq!((info.uid, info.uname.as_str()), (42, "bili_42"))
+            }
+            check => panic!("{check:?}"),
+        }
+
+        // a cookie the server no longer accepts, and one for another account
+        for header in ["SESSDATA=logged_out", "SESSDATA=logged_in; DedeUserID=43"] {
+            assert!(
+                matches!(check(header).await, Ok(LoginCheck::Rejected(_))),
+                "{header}"
+            );
+        }
+
+        // the risk control and an unreachable server say nothing about the cookie
+        assert!(check("SESSDATA=risky").await.is_err());
+
+        let credential = Credential::from_sessdata("logged_in");
+        assert!(
+            credential
+                .check_with("http://127.0.0.1:1/nav")
+                .await
+                .is_err()
+        );
+
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // expired ones are rejected without asking
+        let expired = "SESSDATA=logged_in%2C1600000000%2Cf00b";
+
+        assert!(matches!(check(expired).await, Ok(LoginCheck::Rejected(_))));
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn parses_cookie_headers() {
+        let credential = Credential::from_cookie_header(
+            " buvid3=B3-infoc ;SESSDATA=d2b7a1c3%2C4102444800%2Cf00b; DedeUserID=42; bili_jct=9f8e; junk",
+        )
+        .unwrap();
+
+        assert_eq!(credential.uid(), Some(42));
+        assert_eq!(credential.buvid3(), Some("B3-infoc"));
+        assert_eq!(
+            credential.cookie_header().expose(),
+            "SESSDATA=d2b7a1c3%2C4102444800%2Cf00b; bili_jct=9f8e; buvid3=B3-infoc; DedeUserID=42"
+        );
+        assert_eq!(
+            credential.expires_at().map(|x| x.timestamp()),
+            Some(4102444800)
+        );
+
+        assert!(Credential::from_cookie_header("bili_jct=9f8e; DedeUserID=42").is_err());
+    }
+
+    #[test]
+    fn parses_cookies_files() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+
+        write!(
+            file,
+            "# Netscape HTTP Cookie File\n\
+             \n\
+             #HttpOnly_.bilibili.com\tTRUE\t/\tTRUE\t1767225600\tSESSDATA\td2b7a1c3\n\
+             .bilibili.com\tTRUE\t/\tFALSE\t1767225600\tDedeUserID\t42\n\
+             .bilibili.com\tTRUE\t/\tFALSE\t0\tbuvid3\tB3-infoc\n\
+             .example.com\tTRUE\t/\tFALSE\t0\tbili_jct\tnot-ours\n"
+        )
+        .unwrap();
+
+        let credential = Credential::from_cookies_file(&file.path()).unwrap();
+
+        assert_eq!(credential.uid(), Some(42));
+        assert_eq!(credential.buvid3(), Some("B3-infoc"));
+        assert!(credential.bili_jct.is_none());
+        assert_eq!(
+            credential.cookie_header().expose(),
+            "SESSDATA=d2b7a1c3; buvid3=B3-infoc; DedeUserID=42"
+        );
+
+        // the expiry of the cookie jar wins over the one in SESSDATA
+        assert_eq!(
+            credential.expires_at().map(|x| x.timestamp()),
+            Some(1767225600)
+        );
     }
 }
@@ -1,14 +1,18 @@
+use crate::live::message::registry::PARSERS;
 use anyhow::{Context, Error, Result, bail};
-use base64::Engine;
-use base64::engine::general_purpose::STANDARD;
 use chrono::{DateTime, Local, TimeZone};
-use prost::Message;
+use serde::Serialize;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::ops::Deref;
 
-mod proto {
+#[cfg(test)]
+mod golden;
+mod parsers;
+pub mod registry;
+
+pub mod proto {
     include!(concat!(env!("OUT_DIR"), "/iw2.rs"));
 }
 
@@ -26,6 +30,10 @@ impl RawMessage {
         }
     }
 
+    pub fn room_id(&self) -> &str {
+        &self.room_id
+    }
+
     pub fn data(&self) -> &Value {
         &self.data
     }
@@ -38,8 +46,25 @@ impl RawMessage {
     }
 }
 impl RawMessage {
-    pub fn msg_type(&self) -> &str {
-        self.data["cmd"].as_str().expect("wtf??")
+    // the cmd as received, may carry parameters like `DANMU_MSG:4:0:2:2:2:0`
+    pub fn cmd(&self) -> Result<&str> {
+        match self.data["cmd"].as_str() {
+            Some(cmd) if !cmd.is_empty() => Ok(cmd),
+            _ => bail!("message without a cmd"),
+        }
+    }
+
+    // the cmd without its parameters
+    pub fn msg_type(&self) -> Result<&str> {
+        Ok(self.cmd()?.split(':').next().unwrap_or_default())
+    }
+
+    // the parameters appended to the cmd, empty for most messages
+    pub fn cmd_params(&self) -> Vec<&str> {
+        match self.cmd() {
+            Ok(cmd) => cmd.split(':').skip(1).collect(),
+            Err(_) => Vec::new(),
+        }
     }
 }
 
@@ -57,9 +82,9 @@ impl Deref for RawMessage {
     }
 }
 
-impl Into<Value> for RawMessage {
-    fn into(self) -> Value {
-        self.data
+impl From<RawMessage> for Value {
+    fn from(message: RawMessage) -> Self {
+        message.data
     }
 }
 
@@ -73,30 +98,29 @@ impl<T> Required<T> for Option<T> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Timestamp {
     ts: u64,
     from_server: bool,
 }
 
 impl Timestamp {
-    fn new_server(timestamp: Option<u64>) -> Result<Self> {
+    pub fn new_server(timestamp: u64) -> Self {
         const THRESHOLD: u64 = 1_000_000_000_000; // 2001-09-09 09:46:40
 
-        let timestamp = timestamp.required("timestamp")?;
         let ts = if timestamp < THRESHOLD {
             timestamp * 1000
         } else {
             timestamp
         };
 
-        Ok(Self {
+        Self {
             ts,
             from_server: true,
-        })
+        }
     }
 
-    fn new_local() -> Self {
+    pub fn new_local() -> Self {
         Self {
             ts: Local::now().timestamp_millis() as u64,
             from_server: false,
@@ -115,7 +139,7 @@ impl Display for Timestamp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct UserInfo {
     uid: u64,                  // UID
     uname: String,             // 用户名
@@ -126,7 +150,7 @@ pub struct UserInfo {
 }
 
 impl UserInfo {
-    fn new<U: AsRef<str>, F: AsRef<str>>(
+    pub fn new<U: AsRef<str>, F: AsRef<str>>(
         uid: Option<u64>,
         uname: Option<U>,
         face: Option<F>,
@@ -144,7 +168,43 @@ impl UserInfo {
         })
     }
 
-    fn from_uinfo(uinfo: &Value, wealth_level: Option<i64>) -> Result<Self> {
+    pub fn uid(&self) -> u64 {
+        self.uid
+    }
+
+    pub fn uname(&self) -> &str {
+        &self.uname
+    }
+
+    pub fn face(&self) -> Option<&str> {
+        self.face.as_deref()
+    }
+
+    pub fn medal_level(&self) -> Option<i64> {
+        self.medal_level
+    }
+
+    pub fn wealth_level(&self) -> Option<i64> {
+        self.wealth_level
+    }
+
+    // uid 0 stands for a sender that could not be parsed at all
+    pub fn is_anonymous(&self) -> bool {
+        self.uid == 0
+    }
+
+    fn anonymous() -> Self {
+        Self {
+            uid: 0,
+            uname: String::new(),
+            face: None,
+            medal_level: None,
+            medal_score: None,
+            wealth_level: None,
+        }
+    }
+
+    pub fn from_uinfo(uinfo: &Value, wealth_level: Option<i64>) -> Result<Self> {
         Self::new(
             uinfo["uid"].as_u64(),
             uinfo["base"]["name"].as_str(),
@@ -156,26 +216,42 @@ impl UserInfo {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum BattleStatus {
     Start,
     Process,
     End,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum UserInteractType {
     JoinRoom,
     Subscribe,
     Share,
 }
 
-#[derive(Debug)]
+impl UserInteractType {
+    // same as the serialized name
+    pub fn name(&self) -> &'static str {
+        match self {
+            UserInteractType::JoinRoom => "join_room",
+            UserInteractType::Subscribe => "subscribe",
+            UserInteractType::Share => "share",
+        }
+    }
+}
+
+// Serialized with a `type` field holding the same name as `kind`
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum LiveMessage {
     StreamStart {
         // 开播
         timestamp: Timestamp,
     },
+    #[serde(rename = "stream_end")]
     SteamEnd {
         // 停播
         timestamp: Timestamp,
@@ -185,14 +261,18 @@ pub enum LiveMessage {
         timestamp: Timestamp,           // 时间戳
         user: UserInfo,                 // 用户信息
         text: String,                   // 消息内容
+        mode: i64,                      // 弹幕模式（1 滚动 / 4 底部 / 5 顶部）
+        font_size: i64,                 // 字号（25 为标准）
+        color: i64,                     // 颜色（0xRRGGBB）
         extra: HashMap<String, String>, // 附加信息
     },
     SuperChat {
         // 醒目留言
-        timestamp: Timestamp, // 时间戳
-        user: UserInfo,       // 用户信息
-        price: i64,           // 价格
-        text: String,         // 留言内容
+        timestamp: Timestamp,  // 时间戳
+        user: UserInfo,        // 用户信息
+        price: i64,            // 价格
+        text: String,          // 留言内容
+        duration: Option<i64>, // 持续时间（秒）
     },
     Gift {
         // 礼物消息
@@ -205,6 +285,15 @@ pub enum LiveMessage {
         img_basic: Option<String>, // 礼物图片
         img_webp: Option<String>,  // 礼物图片（webp）
     },
+    GuardBuy {
+        // 上舰
+        timestamp: Timestamp, // 时间戳
+        user: UserInfo,       // 用户信息
+        guard_level: i64,     // 舰队等级（1 总督 / 2 提督 / 3 舰长）
+        guard_name: String,   // 舰队名称
+        count: i64,           // 数量（月）
+        price: i64,           // 单价（金瓜子）
+    },
     Like {
         // 点赞
         timestamp: Timestamp, // 时间戳
@@ -229,192 +318,283 @@ pub enum LiveMessage {
         timestamp: Timestamp, // 时间戳
         count: i64,           // 观看数 uv
     },
+    CutOff {
+        // 超管切断直播
+        timestamp: Timestamp, // 时间戳
+        reason: String,       // 切断原因
+    },
+    Custom {
+        // 外部注册的解析器产出的消息
+        timestamp: Timestamp,   // 时间戳
+        cmd: String,            // 原始消息的 cmd
+        user: Option<UserInfo>, // 用户信息
+        data: Value,            // 解析器给出的内容
+    },
+    #[serde(skip)]
     Unsupported(String),
 }
 
 impl LiveMessage {
-    fn timestamp(&self) -> Option<DateTime<Local>> {
-        let timestamp = match self {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LiveMessage::StreamStart { .. } => "stream_start",
+            LiveMessage::SteamEnd { .. } => "stream_end",
+            LiveMessage::Danmaku { .. } => "danmaku",
+            LiveMessage::SuperChat { .. } => "super_chat",
+            LiveMessage::Gift { .. } => "gift",
+            LiveMessage::GuardBuy { .. } => "guard_buy",
+            LiveMessage::Like { .. } => "like",
+            LiveMessage::BattleInfo { .. } => "battle_info",
+            LiveMessage::UserInteract { .. } => "user_interact",
+            LiveMessage::WatchedChange { .. } => "watched_change",
+            LiveMessage::CutOff { .. } => "cut_off",
+            LiveMessage::Custom { .. } => "custom",
+            LiveMessage::Unsupported(_) => "unsupported",
+        }
+    }
+
+    pub fn timestamp(&self) -> Option<DateTime<Local>> {
+        self.stamp()
+            .and_then(|ts| Local.timestamp_millis_opt(ts.ts as i64).single())
+    }
+
+    // `None` for the messages stamped with the time they were parsed at
+    pub fn server_timestamp(&self) -> Option<DateTime<Local>> {
+        self.stamp()
+            .filter(|ts| ts.from_server)
+            .and_then(|ts| Local.timestamp_millis_opt(ts.ts as i64).single())
+    }
+
+    fn stamp(&self) -> Option<&Timestamp> {
+        match self {
             LiveMessage::StreamStart { timestamp, .. } => Some(timestamp),
             LiveMessage::SteamEnd { timestamp, .. } => Some(timestamp),
             LiveMessage::Danmaku { timestamp, .. } => Some(timestamp),
             LiveMessage::SuperChat { timestamp, .. } => Some(timestamp),
             LiveMessage::Gift { timestamp, .. } => Some(timestamp),
+            LiveMessage::GuardBuy { timestamp, .. } => Some(timestamp),
             LiveMessage::Like { timestamp, .. } => Some(timestamp),
             LiveMessage::BattleInfo { timestamp, .. } => Some(timestamp),
             LiveMessage::UserInteract { timestamp, .. } => Some(timestamp),
             LiveMessage::WatchedChange { timestamp, .. } => Some(timestamp),
+            LiveMessage::CutOff { timestamp, .. } => Some(timestamp),
+            LiveMessage::Custom { timestamp, .. } => Some(timestamp),
             LiveMessage::Unsupported(_) => None,
+        }
+    }
+
+    // the serialized message tagged with its room, `None` for unsupported messages
+    pub fn to_json(&self, room_id: &str) -> Result<Option<Value>> {
+        if let LiveMessage::Unsupported(_) = self {
+            return Ok(None);
+        }
+
+        match serde_json::to_value(self)? {
+            Value::Object(mut fields) => {
+                fields.insert("room_id".into(), room_id.into());
+                Ok(Some(Value::Object(fields)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // `None` as well when the sender could not be parsed
+    pub fn user(&self) -> Option<&UserInfo> {
+        let user = match self {
+            LiveMessage::Danmaku { user, .. } => Some(user),
+            LiveMessage::SuperChat { user, .. } => Some(user),
+            LiveMessage::Gift { user, .. } => Some(user),
+            LiveMessage::GuardBuy { user, .. } => Some(user),
+            LiveMessage::Like { user, .. } => Some(user),
+            LiveMessage::UserInteract { user, .. } => Some(user),
+            LiveMessage::Custom { user, .. } => user.as_ref(),
+            _ => None,
         };
 
-        timestamp.and_then(|ts| Local.timestamp_micros(ts.ts as i64).single())
+        user.filter(|x| !x.is_anonymous())
     }
 }
 
-macro_rules! nested_opt {
-    ($proto:expr; $target:ident) => {
-        Some(&$proto).map(|x| (&x.$target).to_owned())
-    };
-    ($proto:expr; $($field:ident),*; $target:ident) => {
-        Some(&$proto)
-            $(.and_then(|x| x.$field.as_ref()))*
-            .map(|x| (&x.$target).to_owned())
-    };
+// A field that was missing or malformed and was replaced by a fallback
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseWarning {
+    pub field: &'static str,
+    pub reason: String,
 }
 
-impl TryFrom<RawMessage> for LiveMessage {
-    type Error = Error;
+impl Display for ParseWarning {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{}: {}", self.field, self.reason)
+    }
+}
 
-    fn try_from(message: RawMessage) -> Result<Self, Self::Error> {
-        match message.msg_type() {
-            "LIVE" => Ok(LiveMessage::StreamStart {
-                timestamp: Timestamp::new_server(message["live_time"].as_u64())?,
-            }),
-            "PREPARING" => Ok(LiveMessage::SteamEnd {
-                timestamp: Timestamp::new_server(message["send_time"].as_u64())?,
-            }),
-            "DANMU_MSG" => {
-                let common_data = &message["info"][0][15];
-
-                if common_data.is_null() {
-                    bail!("failed to parse common data")
-                }
-
-                let common_data_extra: Option<Value> = common_data["extra"]
-                    .as_str()
-                    .and_then(|s| serde_json::from_str(s).ok());
-
-                let mut extra: HashMap<String, String> = HashMap::new();
-
-                if let Some(common_data_extra) = &common_data_extra {
-                    let emots = &common_data_extra["emots"];
-
-                    if !emots.is_null() {
-                        extra.insert("emots".into(), serde_json::to_string(emots)?);
-                    }
-                }
-
-                Ok(Self::Danmaku {
-                    timestamp: Timestamp::new_server(message["info"][0][4].as_u64())?,
-                    user: UserInfo::from_uinfo(
-                        &common_data["user"],
-                        message["info"][16][0].as_i64(),
-                    )?,
-                    text: message["info"][1].as_str().required("danmaku text")?.into(),
-                    extra,
-                })
+// A message parsed as far as its fields allowed, `warnings` lists what was degraded
+#[derive(Debug)]
+pub struct Parsed {
+    pub message: LiveMessage,
+    pub warnings: Vec<ParseWarning>,
+}
+
+// Collects the warnings of a parse while handing out fallback values
+#[derive(Default)]
+pub struct Fallbacks(Vec<ParseWarning>);
+
+impl Fallbacks {
+    pub fn warn(&mut self, field: &'static str, reason: impl Display) {
+        self.0.push(ParseWarning {
+            field,
+            reason: reason.to_string(),
+        });
+    }
+
+    // the server time if there is one, otherwise the time it was received
+    pub fn timestamp(&mut self, timestamp: Option<u64>) -> Timestamp {
+        match timestamp {
+            Some(timestamp) => Timestamp::new_server(timestamp),
+            None => {
+                self.warn("timestamp", "missing, using local time");
+                Timestamp::new_local()
             }
-            "SUPER_CHAT_MESSAGE" => Ok(LiveMessage::SuperChat {
-                timestamp: Timestamp::new_server(message["data"]["ts"].as_u64())?,
-                user: UserInfo::from_uinfo(&message["data"]["uinfo"], None)?,
-                price: message["data"]["price"]
-                    .as_i64()
-                    .required("super chat price")?,
-                text: message["data"]["message"]
-                    .as_str()
-                    .required("super chat message")?
-                    .into(),
+        }
+    }
+
+    pub fn or<T>(&mut self, field: &'static str, value: Option<T>, default: T) -> T {
+        value.unwrap_or_else(|| {
+            self.warn(field, "missing, using default");
+            default
+        })
+    }
+
+    // tries each source of the sender in order, anonymous when none of them parse
+    pub fn user(&mut self, sources: &[&dyn Fn() -> Result<UserInfo>]) -> UserInfo {
+        for source in sources {
+            match source() {
+                Ok(user) => return user,
+                Err(err) => self.warn("user", err),
+            }
+        }
+
+        UserInfo::anonymous()
+    }
+}
+
+impl LiveMessage {
+    // Never panics. Fails only when a message has nothing worth keeping, e.g. a danmaku
+    // without text, everything else falls back to degraded data.
+    pub fn parse(message: &RawMessage) -> Result<Parsed> {
+        PARSERS.parse(message)
+    }
+}
+
+impl TryFrom<&RawMessage> for LiveMessage {
+    type Error = Error;
+
+    // the message with its warnings dropped
+    fn try_from(message: &RawMessage) -> Result<Self, Self::Error> {
+        Ok(LiveMessage::parse(message)?.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn degrades_instead_of_failing() {
+        // no common data in info[0][15] and no timestamp in info[0][4]
+        let raw = RawMessage::new(
+            "1",
+            json!({
+                "cmd": "DANMU_MSG",
+                "info": [[0, 1, 25, 16777215], "早上好", [42, "甲"], [3]],
             }),
-            "SEND_GIFT" => Ok(Self::Gift {
-                timestamp: Timestamp::new_server(message["data"]["timestamp"].as_u64())?,
-                user: UserInfo::from_uinfo(
-                    &message["data"]["sender_uinfo"],
-                    message["data"]["wealth_level"].as_i64(),
-                )?,
-                gift_name: message["data"]["giftName"]
-                    .as_str()
-                    .required("gift name")?
-                    .into(),
-                gift_count: message["data"]["num"].as_i64().required("gift count")?,
-                coin_type: message["data"]["coin_type"]
-                    .as_str()
-                    .required("coin type")?
-                    .into(),
-                total_coin: message["data"]["total_coin"]
-                    .as_i64()
-                    .required("total coin")?,
-                img_basic: message["data"]["gift_info"]["img_basic"]
-                    .as_str()
-                    .map(|x| x.into()),
-                img_webp: message["data"]["gift_info"]["webp"]
-                    .as_str()
-                    .map(|x| x.into()),
+        );
+
+        let parsed = LiveMessage::parse(&raw).unwrap();
+        let fields: Vec<_> = parsed.warnings.iter().map(|x| x.field).collect();
+
+        assert_eq!(fields, ["user", "timestamp"]);
+
+        let LiveMessage::Danmaku {
+            timestamp,
+            user,
+            text,
+            ..
+        } = &parsed.message
+        else {
+            panic!("not a danmaku: {:?}", parsed.message);
+        };
+
+        assert!(!timestamp.from_server);
+        assert_eq!((user.uid(), user.uname()), (42, "甲"));
+        assert_eq!(user.medal_level(), Some(3));
+        assert_eq!(text, "早上好");
+
+        // a sender that can not be found anywhere leaves the message without a user
+        let raw = RawMessage::new(
+            "1",
+            json!({
+                "cmd": "SEND_GIFT",
+                "data": {
+                    "timestamp": 1_700_000_000,
+                    "giftName": "小花花",
+                    "num": 2,
+                    "price": 100,
+                    "coin_type": "gold",
+                },
             }),
-            "LIKE_INFO_V3_CLICK" => {
-                Ok(Self::Like {
-                    timestamp: Timestamp::new_local(),
-                    user: UserInfo::from_uinfo(&message["data"]["uinfo"], None)?,
-                })
-            }
-            "PK_BATTLE_START_NEW" | "PK_BATTLE_PROCESS_NEW" | "PK_BATTLE_SETTLE_NEW" => {
-                let status = match message.msg_type() {
-                    "PK_BATTLE_START_NEW" => BattleStatus::Start,
-                    "PK_BATTLE_PROCESS_NEW" => BattleStatus::Process,
-                    "PK_BATTLE_SETTLE_NEW" => BattleStatus::End,
-                    _ => unreachable!("wtf??"),
-                };
-
-                let room_a = message["data"]["init_info"]["room_id"]
-                    .as_u64()
-                    .required("init_info room")?
-                    .to_string();
-
-                let room_b = message["data"]["match_info"]["room_id"]
-                    .as_u64()
-                    .required("match_info room")?
-                    .to_string();
-
-                let vote_a = message["data"]["init_info"]["votes"].as_i64().unwrap_or(0);
-
-                let vote_b = message["data"]["match_info"]["votes"].as_i64().unwrap_or(0);
-
-                let (opponent_room, host_votes, opponent_votes) = {
-                    if room_a == message.room_id {
-                        (room_b, vote_a, vote_b)
-                    } else {
-                        (room_a, vote_b, vote_a)
-                    }
-                };
-
-                Ok(Self::BattleInfo {
-                    timestamp: Timestamp::new_server(message["timestamp"].as_u64())?,
-                    status,
-                    opponent_room,
-                    host_votes,
-                    opponent_votes,
-                })
-            }
-            "INTERACT_WORD_V2" => {
-                let pb_data = message["data"]["pb"].as_str().required("pb")?;
-                let pb_data = STANDARD.decode(pb_data.as_bytes())?;
-
-                let iw2 = proto::InteractWordV2::decode(pb_data.as_slice())?;
-
-                Ok(LiveMessage::UserInteract {
-                    timestamp: Timestamp::new_server(Some(iw2.timestamp)).expect("wtf??"),
-                    user: UserInfo::new(
-                        Some(iw2.uid),
-                        nested_opt!(iw2; uname),
-                        nested_opt!(iw2; uinfo, base; face),
-                        nested_opt!(iw2; fans_medal; medal_level),
-                        nested_opt!(iw2; fans_medal; score),
-                        nested_opt!(iw2; uinfo, wealth; level),
-                    )?,
-                    msg_type: {
-                        match iw2.msg_type {
-                            1 => UserInteractType::JoinRoom,
-                            2 => UserInteractType::Subscribe,
-                            3 => UserInteractType::Share,
-                            _ => bail!("unknown message type"),
-                        }
-                    },
-                })
+        );
+
+        let parsed = LiveMessage::parse(&raw).unwrap();
+        let fields: Vec<_> = parsed.warnings.iter().map(|x| x.field).collect();
+
+        assert_eq!(fields, ["total_coin", "user", "user"]);
+        assert!(parsed.message.user().is_none());
+        assert!(matches!(
+            parsed.message,
+            LiveMessage::Gift {
+                total_coin: 200,
+                ..
             }
-            "WATCHED_CHANGE" => Ok(Self::WatchedChange {
-                timestamp: Timestamp::new_local(),
-                count: message["data"]["num"].as_i64().required("watched count")?,
+        ));
+
+        // nothing to keep without the text
+        let raw = RawMessage::new("1", json!({"cmd": "DANMU_MSG", "info": [[0]]}));
+
+        assert!(LiveMessage::parse(&raw).is_err());
+    }
+
+    #[test]
+    fn normalises_cmd() {
+        let raw = RawMessage::new(
+            "1",
+            json!({
+                "cmd": "DANMU_MSG:4:0:2:2:2:0",
+                "info": [[0, 1, 25, 16777215, 1_700_000_000_000_u64], "hi", [42, "甲"]],
             }),
-            _ => Ok(Self::Unsupported(message.msg_type().into())),
+        );
+
+        assert_eq!(raw.msg_type().unwrap(), "DANMU_MSG");
+        assert_eq!(raw.cmd_params(), ["4", "0", "2", "2", "2", "0"]);
+
+        let LiveMessage::Danmaku { extra, .. } = LiveMessage::try_from(&raw).unwrap() else {
+            panic!("not a danmaku");
+        };
+
+        assert_eq!(extra["cmd_params"], "4:0:2:2:2:0");
+
+        for data in [
+            json!({}),
+            json!({"cmd": 1}),
+            json!({"cmd": ""}),
+            json!([1, 2]),
+        ] {
+            let raw = RawMessage::new("1", data);
+
+            assert!(raw.msg_type().is_err());
+            assert!(raw.cmd_params().is_empty());
+            assert!(LiveMessage::parse(&raw).is_err());
         }
     }
 }
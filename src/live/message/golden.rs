@@ -0,0 +1,107 @@
+// Runs every fixture in `fixtures/` through the parsers and compares the result with
+// the snapshot next to it. `UPDATE_SNAPSHOTS=1 cargo test golden` rewrites the snapshots
+// instead, review the diff before committing them.
+//
+// A fixture is one line of a raw log passed through `blivedm_rs redact`, or picked by
+// `blivedm_rs fixtures` which does both, a snapshot is the normalised output: the
+// serialized message, its warnings, or the error.
+
+use crate::live::message::registry::PARSERS;
+use crate::live::message::{LiveMessage, RawMessage};
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const ROOM_ID: &str = "21452505";
+
+fn fixtures() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/live/message/fixtures");
+
+    let mut fixtures: Vec<_> = fs::read_dir(dir)
+        .expect("failed to read fixtures")
+        .map(|x| x.expect("failed to read fixtures").path())
+        .filter(|x| x.extension().is_some_and(|x| x == "json"))
+        .collect();
+
+    fixtures.sort();
+    fixtures
+}
+
+fn normalise(raw: &RawMessage) -> Value {
+    match LiveMessage::parse(raw) {
+        Ok(parsed) => {
+            let mut message = parsed.message.to_json(ROOM_ID).unwrap().unwrap_or_default();
+
+            // the local time differs on every run
+            if message["timestamp"]["from_server"] == false {
+                message["timestamp"]["ts"] = 0.into();
+            }
+
+            let warnings: Vec<_> = parsed.warnings.iter().map(|x| x.to_string()).collect();
+
+            json!({
+                "kind": parsed.message.kind(),
+                "message": message,
+                "warnings": warnings,
+            })
+        }
+        Err(err) => json!({ "error": format!("{err:#}") }),
+    }
+}
+
+#[test]
+fn fixtures_match_snapshots() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut failures = Vec::new();
+    let mut covered = BTreeSet::new();
+
+    for fixture in fixtures() {
+        let content = fs::read_to_string(&fixture).unwrap();
+        let raw = RawMessage::new(ROOM_ID, serde_json::from_str(&content).unwrap());
+
+        if let Ok(msg_type) = raw.msg_type() {
+            covered.insert(msg_type.to_owned());
+        }
+
+        let actual = normalise(&raw);
+        let snapshot = fixture.with_extension("snap");
+
+        if update {
+            let pretty = serde_json::to_string_pretty(&actual).unwrap();
+            fs::write(&snapshot, pretty + "\n").unwrap();
+            continue;
+        }
+
+        let expected: Value = match fs::read_to_string(&snapshot) {
+            Ok(content) => serde_json::from_str(&content).unwrap(),
+            Err(_) => {
+                failures.push(format!("{}: no snapshot", fixture.display()));
+                continue;
+            }
+        };
+
+        if actual != expected {
+            failures.push(format!(
+                "{}:\nexpected {}\n  actual {}",
+                fixture.display(),
+                serde_json::to_string_pretty(&expected).unwrap(),
+                serde_json::to_string_pretty(&actual).unwrap()
+            ));
+        }
+    }
+
+    let missing: Vec<_> = PARSERS
+        .parsers()
+        .into_iter()
+        .filter(|x| !covered.contains(x.cmd))
+        .map(|x| x.cmd)
+        .collect();
+
+    assert!(missing.is_empty(), "cmds without fixtures: {missing:?}");
+    assert!(
+        failures.is_empty(),
+        "{}\n\nrun with UPDATE_SNAPSHOTS=1 if the changes are intended",
+        failures.join("\n\n")
+    );
+}
@@ -0,0 +1,374 @@
+use crate::live::message::registry::{ParseFn, ParserInfo, ParserRegistry};
+use crate::live::message::{
+    BattleStatus, Fallbacks, LiveMessage, RawMessage, Required, Timestamp, UserInfo,
+    UserInteractType, proto,
+};
+use anyhow::{Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use prost::Message;
+use serde_json::Value;
+use std::collections::HashMap;
+
+macro_rules! nested_opt {
+    ($proto:expr; $target:ident) => {
+        Some(&$proto).map(|x| (&x.$target).to_owned())
+    };
+    ($proto:expr; $($field:ident),*; $target:ident) => {
+        Some(&$proto)
+            $(.and_then(|x| x.$field.as_ref()))*
+            .map(|x| (&x.$target).to_owned())
+    };
+}
+
+// samples are the fixtures of the golden tests that parse without warnings
+macro_rules! fixture {
+    ($name:literal) => {
+        include_str!(concat!("fixtures/", $name, ".json"))
+    };
+}
+
+// the parsers that ship with the crate, bump `version` when a parser changes its output
+pub fn register_builtin(registry: &ParserRegistry) {
+    let builtin: Vec<(ParserInfo, ParseFn)> = vec![
+        (info("LIVE", 1, &[fixture!("live")]), stream_start),
+        (info("PREPARING", 1, &[fixture!("preparing")]), stream_end),
+        (
+            info(
+                "DANMU_MSG",
+                2,
+                &[
+                    fixture!("danmu_msg"),
+                    fixture!("danmu_msg-suffixed_cmd"),
+                    fixture!("danmu_msg-top_colored"),
+                    fixture!("danmu_msg-emots"),
+                ],
+            ),
+            danmaku,
+        ),
+        (
+            info("SUPER_CHAT_MESSAGE", 1, &[fixture!("super_chat_message")]),
+            super_chat,
+        ),
+        (info("SEND_GIFT", 2, &[fixture!("send_gift")]), gift),
+        (info("GUARD_BUY", 1, &[fixture!("guard_buy")]), guard_buy),
+        (
+            info("LIKE_INFO_V3_CLICK", 1, &[fixture!("like_info_v3_click")]),
+            like,
+        ),
+        (
+            info("PK_BATTLE_START_NEW", 1, &[fixture!("pk_battle_start_new")]),
+            battle,
+        ),
+        (
+            info(
+                "PK_BATTLE_PROCESS_NEW",
+                1,
+                &[fixture!("pk_battle_process_new")],
+            ),
+            battle,
+        ),
+        (
+            info(
+                "PK_BATTLE_SETTLE_NEW",
+                1,
+                &[fixture!("pk_battle_settle_new")],
+            ),
+            battle,
+        ),
+        (
+            info(
+                "INTERACT_WORD_V2",
+                1,
+                &[
+                    fixture!("interact_word_v2-join"),
+                    fixture!("interact_word_v2-follow"),
+                    fixture!("interact_word_v2-share"),
+                ],
+            ),
+            interact,
+        ),
+        (
+            info("WATCHED_CHANGE", 1, &[fixture!("watched_change")]),
+            watched_change,
+        ),
+        (info("CUT_OFF", 1, &[fixture!("cut_off")]), cut_off),
+    ];
+
+    for (info, parser) in builtin {
+        registry.register(info, parser);
+    }
+}
+
+fn info(cmd: &'static str, version: u32, samples: &'static [&'static str]) -> ParserInfo {
+    ParserInfo {
+        cmd,
+        version,
+        samples,
+    }
+}
+
+fn stream_start(message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+    Ok(LiveMessage::StreamStart {
+        timestamp: fallbacks.timestamp(message["live_time"].as_u64()),
+    })
+}
+
+fn stream_end(message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+    Ok(LiveMessage::SteamEnd {
+        timestamp: fallbacks.timestamp(message["send_time"].as_u64()),
+    })
+}
+
+fn danmaku(message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+    let info = &message["info"];
+    let common_data = &info[0][15];
+
+    let common_data_extra: Option<Value> = common_data["extra"]
+        .as_str()
+        .and_then(|s| serde_json::from_str(s).ok());
+
+    let mut extra: HashMap<String, String> = HashMap::new();
+
+    if let Some(common_data_extra) = &common_data_extra {
+        let emots = &common_data_extra["emots"];
+
+        if !emots.is_null() {
+            match serde_json::to_string(emots) {
+                Ok(emots) => {
+                    extra.insert("emots".into(), emots);
+                }
+                Err(err) => fallbacks.warn("emots", err),
+            }
+        }
+    }
+
+    let params = message.cmd_params();
+
+    if !params.is_empty() {
+        extra.insert("cmd_params".into(), params.join(":"));
+    }
+
+    // older clients leave out the common data, the sender is in info[2]
+    let user = fallbacks.user(&[
+        &|| {
+            if common_data.is_null() {
+                bail!("failed to parse common data")
+            }
+
+            UserInfo::from_uinfo(&common_data["user"], info[16][0].as_i64())
+        },
+        &|| {
+            UserInfo::new(
+                info[2][0].as_u64(),
+                info[2][1].as_str(),
+                None::<&str>,
+                info[3][0].as_i64(),
+                None,
+                info[16][0].as_i64(),
+            )
+        },
+    ]);
+
+    Ok(LiveMessage::Danmaku {
+        timestamp: fallbacks.timestamp(info[0][4].as_u64()),
+        user,
+        text: info[1].as_str().required("danmaku text")?.into(),
+        mode: info[0][1].as_i64().unwrap_or(1),
+        font_size: info[0][2].as_i64().unwrap_or(25),
+        color: info[0][3].as_i64().unwrap_or(0xffffff),
+        extra,
+    })
+}
+
+fn super_chat(message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+    let data = &message["data"];
+
+    Ok(LiveMessage::SuperChat {
+        timestamp: fallbacks.timestamp(data["ts"].as_u64()),
+        user: fallbacks.user(&[&|| UserInfo::from_uinfo(&data["uinfo"], None), &|| {
+            UserInfo::new(
+                data["uid"].as_u64(),
+                data["user_info"]["uname"].as_str(),
+                data["user_info"]["face"].as_str(),
+                data["medal_info"]["medal_level"].as_i64(),
+                None,
+                None,
+            )
+        }]),
+        price: data["price"].as_i64().required("super chat price")?,
+        text: data["message"]
+            .as_str()
+            .required("super chat message")?
+            .into(),
+        duration: data["time"].as_i64(),
+    })
+}
+
+fn gift(message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+    let data = &message["data"];
+    let gift_count = data["num"].as_i64().required("gift count")?;
+
+    // total_coin is the price times the count when it is left out
+    let total_coin = match data["total_coin"].as_i64() {
+        Some(total_coin) => total_coin,
+        None => {
+            let price = data["price"].as_i64().required("total coin")?;
+
+            match price.checked_mul(gift_count) {
+                Some(total_coin) => {
+                    fallbacks.warn("total_coin", "missing, using price * num");
+                    total_coin
+                }
+                None => {
+                    fallbacks.warn("total_coin", "missing, and price * num overflows, using 0");
+                    0
+                }
+            }
+        }
+    };
+
+    Ok(LiveMessage::Gift {
+        timestamp: fallbacks.timestamp(data["timestamp"].as_u64()),
+        user: fallbacks.user(&[
+            &|| UserInfo::from_uinfo(&data["sender_uinfo"], data["wealth_level"].as_i64()),
+            &|| {
+                UserInfo::new(
+                    data["uid"].as_u64(),
+                    data["uname"].as_str(),
+                    data["face"].as_str(),
+                    data["medal_info"]["medal_level"].as_i64(),
+                    None,
+                    data["wealth_level"].as_i64(),
+                )
+            },
+        ]),
+        gift_name: data["giftName"].as_str().required("gift name")?.into(),
+        gift_count,
+        coin_type: data["coin_type"].as_str().required("coin type")?.into(),
+        total_coin,
+        img_basic: data["gift_info"]["img_basic"].as_str().map(|x| x.into()),
+        img_webp: data["gift_info"]["webp"].as_str().map(|x| x.into()),
+    })
+}
+
+fn guard_buy(message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+    let data = &message["data"];
+
+    Ok(LiveMessage::GuardBuy {
+        timestamp: fallbacks.timestamp(data["start_time"].as_u64()),
+        user: fallbacks.user(&[&|| {
+            UserInfo::new(
+                data["uid"].as_u64(),
+                data["username"].as_str(),
+                None::<&str>,
+                None,
+                None,
+                None,
+            )
+        }]),
+        guard_level: data["guard_level"].as_i64().required("guard level")?,
+        guard_name: data["gift_name"].as_str().required("guard name")?.into(),
+        count: fallbacks.or("guard count", data["num"].as_i64(), 1),
+        price: data["price"].as_i64().required("guard price")?,
+    })
+}
+
+fn like(message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+    let data = &message["data"];
+
+    Ok(LiveMessage::Like {
+        timestamp: Timestamp::new_local(),
+        user: fallbacks.user(&[&|| UserInfo::from_uinfo(&data["uinfo"], None), &|| {
+            UserInfo::new(
+                data["uid"].as_u64(),
+                data["uname"].as_str(),
+                None::<&str>,
+                data["fans_medal"]["medal_level"].as_i64(),
+                None,
+                None,
+            )
+        }]),
+    })
+}
+
+fn battle(message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+    let status = match message.msg_type()? {
+        "PK_BATTLE_START_NEW" => BattleStatus::Start,
+        "PK_BATTLE_PROCESS_NEW" => BattleStatus::Process,
+        _ => BattleStatus::End,
+    };
+
+    let room_a = message["data"]["init_info"]["room_id"]
+        .as_u64()
+        .required("init_info room")?
+        .to_string();
+
+    let room_b = message["data"]["match_info"]["room_id"]
+        .as_u64()
+        .required("match_info room")?
+        .to_string();
+
+    let vote_a = message["data"]["init_info"]["votes"].as_i64().unwrap_or(0);
+
+    let vote_b = message["data"]["match_info"]["votes"].as_i64().unwrap_or(0);
+
+    let (opponent_room, host_votes, opponent_votes) = {
+        if room_a == message.room_id() {
+            (room_b, vote_a, vote_b)
+        } else {
+            (room_a, vote_b, vote_a)
+        }
+    };
+
+    Ok(LiveMessage::BattleInfo {
+        timestamp: fallbacks.timestamp(message["timestamp"].as_u64()),
+        status,
+        opponent_room,
+        host_votes,
+        opponent_votes,
+    })
+}
+
+fn interact(message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+    let pb_data = message["data"]["pb"].as_str().required("pb")?;
+    let pb_data = STANDARD.decode(pb_data.as_bytes())?;
+
+    let iw2 = proto::InteractWordV2::decode(pb_data.as_slice())?;
+
+    let msg_type = match iw2.msg_type {
+        1 => UserInteractType::JoinRoom,
+        2 => UserInteractType::Subscribe,
+        3 => UserInteractType::Share,
+        _ => bail!("unknown message type"),
+    };
+
+    Ok(LiveMessage::UserInteract {
+        timestamp: fallbacks.timestamp(Some(iw2.timestamp).filter(|x| *x != 0)),
+        user: fallbacks.user(&[&|| {
+            UserInfo::new(
+                Some(iw2.uid),
+                nested_opt!(iw2; uname),
+                nested_opt!(iw2; uinfo, base; face),
+                nested_opt!(iw2; fans_medal; medal_level),
+                nested_opt!(iw2; fans_medal; score),
+                nested_opt!(iw2; uinfo, wealth; level),
+            )
+        }]),
+        msg_type,
+    })
+}
+
+fn watched_change(message: &RawMessage, _: &mut Fallbacks) -> Result<LiveMessage> {
+    Ok(LiveMessage::WatchedChange {
+        timestamp: Timestamp::new_local(),
+        count: message["data"]["num"].as_i64().required("watched count")?,
+    })
+}
+
+fn cut_off(message: &RawMessage, _: &mut Fallbacks) -> Result<LiveMessage> {
+    Ok(LiveMessage::CutOff {
+        timestamp: Timestamp::new_local(),
+        reason: message["msg"].as_str().unwrap_or_default().into(),
+    })
+}
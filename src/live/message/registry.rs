@@ -0,0 +1,168 @@
+use crate::live::message::{Fallbacks, LiveMessage, Parsed, RawMessage, parsers};
+use anyhow::Result;
+use log::debug;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+pub static PARSERS: Lazy<ParserRegistry> = Lazy::new(|| {
+    let registry = ParserRegistry::default();
+    parsers::register_builtin(&registry);
+    registry
+});
+
+pub type ParseFn = fn(&RawMessage, &mut Fallbacks) -> Result<LiveMessage>;
+
+// Turns the messages of one cmd into a `LiveMessage`. Fields that had to be degraded
+// are reported through `fallbacks`, an error means there was nothing worth keeping.
+pub trait MessageParser: Send + Sync {
+    fn parse(&self, message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage>;
+}
+
+impl<F> MessageParser for F
+where
+    F: Fn(&RawMessage, &mut Fallbacks) -> Result<LiveMessage> + Send + Sync,
+{
+    fn parse(&self, message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+        self(message, fallbacks)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParserInfo {
+    pub cmd: &'static str,                // cmd（不含参数）
+    pub version: u32,                     // 解析器版本，输出变化时递增
+    pub samples: &'static [&'static str], // 样例消息（JSON），应能无警告地解析
+}
+
+struct Entry {
+    info: ParserInfo,
+    parser: Arc<dyn MessageParser>,
+}
+
+// Parsers by cmd. Registering a cmd again replaces its parser, so code embedding the
+// crate can add cmds it does not support as well as override the builtin ones.
+#[derive(Default)]
+pub struct ParserRegistry {
+    parsers: RwLock<BTreeMap<&'static str, Entry>>,
+}
+
+impl ParserRegistry {
+    pub fn register<P: MessageParser + 'static>(&self, info: ParserInfo, parser: P) {
+        let mut parsers = self.parsers.write().expect("failed to lock parsers");
+
+        if let Some(previous) = parsers.get(info.cmd) {
+            debug!(
+                "replacing parser for {} v{} with v{}",
+                info.cmd, previous.info.version, info.version
+            );
+        }
+
+        parsers.insert(
+            info.cmd,
+            Entry {
+                info,
+                parser: Arc::new(parser),
+            },
+        );
+    }
+
+    // unsupported when no parser is registered for the cmd
+    pub fn parse(&self, message: &RawMessage) -> Result<Parsed> {
+        let msg_type = message.msg_type()?;
+
+        // the lock is not held while parsing, a parser may register others
+        let parser = self
+            .parsers
+            .read()
+            .expect("failed to lock parsers")
+            .get(msg_type)
+            .map(|x| x.parser.clone());
+
+        let mut fallbacks = Fallbacks::default();
+
+        let message = match parser {
+            Some(parser) => parser.parse(message, &mut fallbacks)?,
+            None => LiveMessage::Unsupported(msg_type.into()),
+        };
+
+        Ok(Parsed {
+            message,
+            warnings: fallbacks.0,
+        })
+    }
+
+    // sorted by cmd
+    pub fn parsers(&self) -> Vec<ParserInfo> {
+        self.parsers
+            .read()
+            .expect("failed to lock parsers")
+            .values()
+            .map(|x| x.info.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::message::{Timestamp, UserInfo};
+    use serde_json::{Value, json};
+
+    #[test]
+    fn samples_parse_cleanly() {
+        for info in PARSERS.parsers() {
+            assert!(!info.samples.is_empty(), "{} has no samples", info.cmd);
+
+            for sample in info.samples {
+                let data: Value = serde_json::from_str(sample).unwrap();
+                let parsed = PARSERS.parse(&RawMessage::new("1", data)).unwrap();
+
+                assert_eq!(parsed.warnings, [], "{}", info.cmd);
+                assert!(!matches!(parsed.message, LiveMessage::Unsupported(_)));
+            }
+        }
+    }
+
+    #[test]
+    fn registers_custom_parsers() {
+        let registry = ParserRegistry::default();
+        let raw = RawMessage::new("1", json!({"cmd": "DM_INTERACTION", "data": {"uid": 7}}));
+
+        assert!(matches!(
+            registry.parse(&raw).unwrap().message,
+            LiveMessage::Unsupported(_)
+        ));
+
+        registry.register(
+            ParserInfo {
+                cmd: "DM_INTERACTION",
+                version: 1,
+                samples: &[],
+            },
+            |message: &RawMessage, fallbacks: &mut Fallbacks| {
+                Ok(LiveMessage::Custom {
+                    timestamp: Timestamp::new_local(),
+                    cmd: "DM_INTERACTION".into(),
+                    user: Some(fallbacks.user(&[&|| {
+                        UserInfo::new(
+                            message["data"]["uid"].as_u64(),
+                            message["data"]["uname"].as_str(),
+                            None::<&str>,
+                            None,
+                            None,
+                            None,
+                        )
+                    }])),
+                    data: message["data"].clone(),
+                })
+            },
+        );
+
+        let parsed = registry.parse(&raw).unwrap();
+
+        assert_eq!(parsed.message.kind(), "custom");
+        assert_eq!(parsed.warnings.len(), 1);
+        assert_eq!(registry.parsers()[0].cmd, "DM_INTERACTION");
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{Context, Result, bail};
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+// https://github.com/SocialSisterYi/bilibili-API-collect/blob/master/docs/live/message_stream.md
+pub const OP_HEARTBEAT: u32 = 2;
+pub const OP_HEARTBEAT_REPLY: u32 = 3;
+pub const OP_MESSAGE: u32 = 5;
+pub const OP_AUTH: u32 = 7;
+pub const OP_AUTH_REPLY: u32 = 8;
+
+pub const PROTOVER_INT: u16 = 1;
+pub const PROTOVER_ZLIB: u16 = 2;
+pub const PROTOVER_BROTLI: u16 = 3;
+
+// total length (u32), header length (u16), protover (u16), operation (u32), sequence (u32)
+const HEADER_LEN: usize = 16;
+// a compressed batch holds a few hundred messages at most, anything bigger is a zip bomb
+const MAX_INFLATED: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub op: u32,       // 操作码
+    pub protover: u16, // 包体格式，0 为 JSON，1 为整数，压缩的包体里是更多的包
+    pub body: Vec<u8>, // 包体
+}
+
+impl Packet {
+    pub fn new(op: u32, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            op,
+            protover: PROTOVER_INT,
+            body: body.into(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LEN + self.body.len());
+
+        data.extend_from_slice(&((HEADER_LEN + self.body.len()) as u32).to_be_bytes());
+        data.extend_from_slice(&(HEADER_LEN as u16).to_be_bytes());
+        data.extend_from_slice(&self.protover.to_be_bytes());
+        data.extend_from_slice(&self.op.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&self.body);
+
+        data
+    }
+
+    // a websocket frame holds one or more packets, compressed ones are unpacked in place
+    pub fn decode(data: &[u8]) -> Result<Vec<Packet>> {
+        let mut packets = Vec::new();
+        Self::decode_into(data, false, &mut packets)?;
+        Ok(packets)
+    }
+
+    fn decode_into(mut data: &[u8], inflated: bool, packets: &mut Vec<Packet>) -> Result<()> {
+        while !data.is_empty() {
+            if data.len() < HEADER_LEN {
+                bail!("truncated packet header of {} bytes", data.len())
+            }
+
+            let total = u32::from_be_bytes(data[0..4].try_into()?) as usize;
+            let header = u16::from_be_bytes(data[4..6].try_into()?) as usize;
+            let protover = u16::from_be_bytes(data[6..8].try_into()?);
+            let op = u32::from_be_bytes(data[8..12].try_into()?);
+
+            if header < HEADER_LEN || total < header || total > data.len() {
+                bail!(
+                    "invalid packet length {total} with header {header}, {} bytes left",
+                    data.len()
+                )
+            }
+
+            let body = &data[header..total];
+
+            match protover {
+                // the server never nests batches
+                PROTOVER_ZLIB if inflated => bail!("zlib packet inside a zlib packet"),
+                PROTOVER_ZLIB => {
+                    let mut buffer = Vec::new();
+
+                    ZlibDecoder::new(body)
+                        .take(MAX_INFLATED as u64 + 1)
+                        .read_to_end(&mut buffer)
+                        .context("failed to inflate packet")?;
+
+                    if buffer.len() > MAX_INFLATED {
+                        bail!("packet inflates to more than {MAX_INFLATED} bytes")
+                    }
+
+                    Self::decode_into(&buffer, true, packets)?;
+                }
+                // only sent when asked for in the auth packet
+                PROTOVER_BROTLI => bail!("brotli packets are not supported"),
+                _ => packets.push(Packet {
+                    op,
+                    protover,
+                    body: body.to_vec(),
+                }),
+            }
+
+            data = &data[total..];
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    fn zlib(body: &[u8]) -> Packet {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+
+        Packet {
+            op: OP_MESSAGE,
+            protover: PROTOVER_ZLIB,
+            body: encoder.finish().unwrap(),
+        }
+    }
+
+    #[test]
+    fn decodes_compressed_batches() {
+        let heartbeat = Packet::new(OP_HEARTBEAT_REPLY, 1u32.to_be_bytes());
+        let messages = [
+            r#"{"cmd":"LIKE_INFO_V3_UPDATE"}"#,
+            r#"{"cmd":"WATCHED_CHANGE"}"#,
+        ]
+        .map(|x| Packet {
+            op: OP_MESSAGE,
+            protover: 0,
+            body: x.into(),
+        });
+
+        let batch = zlib(&messages.iter().flat_map(Packet::encode).collect::<Vec<_>>());
+
+        let data = [heartbeat.encode(), batch.encode()].concat();
+
+        assert_eq!(
+            Packet::decode(&data).unwrap(),
+            [heartbeat, messages[0].clone(), messages[1].clone()]
+        );
+
+        assert!(Packet::decode(&data[..24]).is_err());
+        assert!(Packet::decode(&data[..8]).is_err());
+    }
+
+    #[test]
+    fn rejects_nested_and_oversized_batches() {
+        let message = Packet::new(OP_MESSAGE, r#"{"cmd":"WATCHED_CHANGE"}"#);
+        let nested = zlib(&zlib(&message.encode()).encode());
+
+        assert!(Packet::decode(&nested.encode()).is_err());
+
+        let bomb = zlib(&vec![0; MAX_INFLATED + 1]);
+        let err = Packet::decode(&bomb.encode()).unwrap_err();
+
+        assert!(err.to_string().contains("inflates to more than"));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::live::credential::Credential;
+use log::warn;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const AUTH_FAILURE_COOLDOWN: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Clone)]
+pub struct Account {
+    pub name: String,
+    pub credential: Arc<Credential>,
+}
+
+struct PoolState {
+    next: usize,
+    failed: HashMap<String, Instant>,
+}
+
+pub struct CredentialPool {
+    accounts: Vec<Account>,
+    state: Mutex<PoolState>,
+}
+
+impl CredentialPool {
+    pub fn new(accounts: Vec<(String, Credential)>) -> Self {
+        Self {
+            accounts: accounts
+                .into_iter()
+                .map(|(name, credential)| Account {
+                    name,
+                    credential: Arc::new(credential),
+                })
+                .collect(),
+            state: Mutex::new(PoolState {
+                next: 0,
+                failed: HashMap::new(),
+            }),
+        }
+    }
+
+    // prefers the given account, otherwise rotates through the ones that have not failed recently
+    pub fn acquire(&self, preferred: Option<&str>) -> Option<Account> {
+        let mut state = self.state.lock().expect("failed to lock credential pool");
+
+        state
+            .failed
+            .retain(|_, failed_at| failed_at.elapsed() < AUTH_FAILURE_COOLDOWN);
+
+        if let Some(preferred) = preferred {
+            match self.accounts.iter().find(|x| x.name == preferred) {
+                Some(account) if !state.failed.contains_key(&account.name) => {
+                    return Some(account.clone());
+                }
+                Some(_) => warn!("account {preferred} failed recently, rotating"),
+                None => warn!("account {preferred} not found, rotating"),
+            }
+        }
+
+        for offset in 0..self.accounts.len() {
+            let index = (state.next + offset) % self.accounts.len();
+            let account = &self.accounts[index];
+
+            if !state.failed.contains_key(&account.name) {
+                state.next = index + 1;
+                return Some(account.clone());
+            }
+        }
+
+        None
+    }
+
+    pub fn mark_failed(&self, name: &str) {
+        let mut state = self.state.lock().expect("failed to lock credential pool");
+
+        state.failed.insert(name.into(), Instant::now());
+    }
+
+    // how long until the first failed account is usable again, None when nothing is cooling down
+    pub fn next_recovery(&self) -> Option<Duration> {
+        let state = self.state.lock().expect("failed to lock credential pool");
+
+        state
+            .failed
+            .values()
+            .map(|failed_at| AUTH_FAILURE_COOLDOWN.saturating_sub(failed_at.elapsed()))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(names: &[&str]) -> CredentialPool {
+        CredentialPool::new(
+            names
+                .iter()
+                .map(|&name| (name.into(), Credential::from_sessdata(name)))
+                .collect(),
+        )
+    }
+
+    fn acquire(pool: &CredentialPool, preferred: Option<&str>) -> Option<String> {
+        pool.acquire(preferred).map(|x| x.name)
+    }
+
+    #[test]
+    fn rotates_past_failed_accounts() {
+        let pool = pool(&["a", "b", "c"]);
+
+        assert_eq!(acquire(&pool, None).as_deref(), Some("a"));
+        assert_eq!(acquire(&pool, None).as_deref(), Some("b"));
+        assert_eq!(acquire(&pool, Some("a")).as_deref(), Some("a"));
+        assert_eq!(acquire(&pool, None).as_deref(), Some("c"));
+        assert_eq!(acquire(&pool, None).as_deref(), Some("a"));
+
+        pool.mark_failed("b");
+
+        // the preferred one is skipped while it cools down, as is an unknown one
+        assert_eq!(acquire(&pool, Some("b")).as_deref(), Some("c"));
+        assert_eq!(acquire(&pool, Some("x")).as_deref(), Some("a"));
+        assert_eq!(acquire(&pool, None).as_deref(), Some("c"));
+
+        pool.mark_failed("a");
+        pool.mark_failed("c");
+
+        assert_eq!(acquire(&pool, None), None);
+        assert!(pool.next_recovery().unwrap() > AUTH_FAILURE_COOLDOWN / 2);
+
+        // until the cooldown is over
+        pool.state
+            .lock()
+            .unwrap()
+            .failed
+            .insert("b".into(), Instant::now() - AUTH_FAILURE_COOLDOWN);
+
+        assert_eq!(pool.next_recovery(), Some(Duration::ZERO));
+
+        assert_eq!(acquire(&pool, Some("c")).as_deref(), Some("b"));
+    }
+}
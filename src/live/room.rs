@@ -0,0 +1,123 @@
+use crate::live::credential::Secret;
+use crate::live::wbi::WbiKeys;
+use anyhow::{Context, Result, bail};
+use chrono::Local;
+use reqwest::header::COOKIE;
+use serde_json::Value;
+
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    pub room_init: String,  // 短号转真实房间号
+    pub nav: String,        // 取 wbi 签名密钥
+    pub danmu_info: String, // 取弹幕服务器和认证 token
+    pub secure: bool,       // 用 wss 连接弹幕服务器
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            room_init: "https://api.live.bilibili.com/room/v1/Room/room_init".into(),
+            nav: "https://api.bilibili.com/x/web-interface/nav".into(),
+            danmu_info: "https://api.live.bilibili.com/xlive/web-room/v1/index/getDanmuInfo".into(),
+            secure: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DanmuInfo {
+    pub room_id: u64,         // 真实房间号
+    pub token: Secret,        // 认证包里的 key
+    pub servers: Vec<String>, // 弹幕服务器地址，按推荐顺序
+}
+
+impl DanmuInfo {
+    pub async fn fetch(
+        client: &reqwest::Client,
+        endpoints: &Endpoints,
+        room_id: &str,
+        cookie: &Secret,
+    ) -> Result<Self> {
+        let room = get(
+            client,
+            &format!("{}?id={room_id}", endpoints.room_init),
+            cookie,
+        )
+        .await?;
+        let room_id = room["data"]["room_id"]
+            .as_u64()
+            .context("failed to parse room_id")?;
+
+        // nav answers -101 when not logged in, the keys are there regardless
+        let nav: Value = client
+            .get(&endpoints.nav)
+            .header(COOKIE, cookie.expose())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let query = WbiKeys::from_nav(&nav)?.sign(
+            &[("id", &room_id.to_string()), ("type", "0")],
+            Local::now().timestamp(),
+        );
+
+        let info = get(client, &format!("{}?{query}", endpoints.danmu_info), cookie).await?;
+
+        let (scheme, port) = if endpoints.secure {
+            ("wss", "wss_port")
+        } else {
+            ("ws", "ws_port")
+        };
+
+        let servers: Vec<_> = info["data"]["host_list"]
+            .as_array()
+            .context("failed to parse host_list")?
+            .iter()
+            .filter_map(|x| {
+                Some(format!(
+                    "{scheme}://{}:{}/sub",
+                    x["host"].as_str()?,
+                    x[port].as_u64()?
+                ))
+            })
+            .collect();
+
+        if servers.is_empty() {
+            bail!("no danmaku server for room {room_id}")
+        }
+
+        Ok(Self {
+            room_id,
+            token: Secret::new(
+                info["data"]["token"]
+                    .as_str()
+                    .context("failed to parse token")?,
+            ),
+            servers,
+        })
+    }
+}
+
+async fn get(client: &reqwest::Client, url: &str, cookie: &Secret) -> Result<Value> {
+    let response: Value = client
+        .get(url)
+        .header(COOKIE, cookie.expose())
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let code = response["code"].as_i64().context("failed to parse code")?;
+
+    if code != 0 {
+        bail!(
+            "request rejected: {} ({code})",
+            response["message"].as_str().unwrap_or_default()
+        )
+    }
+
+    Ok(response)
+}
@@ -0,0 +1,345 @@
+use crate::data::database::LivePersist;
+use crate::live::message::LiveMessage;
+use anyhow::Result;
+use chrono::Local;
+use log::info;
+
+const SESSION_GAP: i64 = 30 * 60 * 1000; // 超过该时长观看数不变视为已停播
+const RESTART_THRESHOLD: i64 = 60 * 1000; // 开播时间相差超过该值视为重新开播
+
+struct Session {
+    id: i64,
+    started_at: i64,
+}
+
+// Splits a room's events into broadcast sessions: `LIVE` opens one and `PREPARING` closes it.
+// Missed events are covered by watching WATCHED_CHANGE, which only grows while the room is live.
+// It keeps growing for a while after `PREPARING` too, so it opens no session until the room
+// has been off for `SESSION_GAP`.
+pub struct SessionTracker {
+    room_id: String,
+    current: Option<Session>,
+    last_activity: Option<i64>,
+    last_watched: Option<i64>,
+    preparing_at: Option<i64>, // 最近一次 PREPARING 的时间
+}
+
+impl SessionTracker {
+    pub fn new(room_id: &str, persist: &LivePersist) -> Result<Self> {
+        let mut tracker = Self {
+            room_id: room_id.into(),
+            current: None,
+            last_activity: None,
+            last_watched: None,
+            preparing_at: None,
+        };
+
+        // resume the session left open by the previous run, unless it has gone quiet since
+        if let Some(session) = persist.current_session(room_id)? {
+            let last_activity = persist
+                .last_event_time(room_id)?
+                .unwrap_or(session.started_at)
+                .max(session.started_at);
+
+            if Local::now().timestamp_millis() - last_activity > SESSION_GAP {
+                persist.close_session(session.id, last_activity, "gap")?;
+            } else {
+                tracker.current = Some(Session {
+                    id: session.id,
+                    started_at: session.started_at,
+                });
+                tracker.last_activity = Some(last_activity);
+            }
+        }
+
+        Ok(tracker)
+    }
+
+    pub fn current(&self) -> Option<i64> {
+        self.current.as_ref().map(|x| x.id)
+    }
+
+    // returns the session the message belongs to
+    pub fn observe(&mut self, persist: &LivePersist, message: &LiveMessage) -> Result<Option<i64>> {
+        let now = message
+            .timestamp()
+            .unwrap_or_else(Local::now)
+            .timestamp_millis();
+
+        if let Some(last_activity) = self.last_activity
+            && self.current.is_some()
+            && now - last_activity > SESSION_GAP
+        {
+            self.close(persist, last_activity, "gap")?;
+        }
+
+        match message {
+            LiveMessage::StreamStart { .. } => {
+                match &self.current {
+                    // LIVE is usually repeated a few times when the stream starts
+                    Some(session) if (now - session.started_at).abs() <= RESTART_THRESHOLD => (),
+                    Some(_) => {
+                        self.close(persist, now, "restart")?;
+                        self.open(persist, now, "live")?;
+                    }
+                    None => self.open(persist, now, "live")?,
+                }
+
+                self.last_activity = Some(now);
+            }
+            LiveMessage::SteamEnd { .. } => {
+                let id = self.current();
+
+                self.close(persist, now, "preparing")?;
+                self.preparing_at = Some(now);
+
+                return Ok(id);
+            }
+            LiveMessage::WatchedChange { count, .. } => {
+                let off_air = self.preparing_at.is_none_or(|at| now - at > SESSION_GAP);
+
+                if self.last_watched.is_some_and(|last| *count > last) {
+                    if self.current.is_none() && off_air {
+                        self.open(persist, now, "watched")?;
+                    }
+
+                    self.last_activity = Some(now);
+                }
+
+                self.last_watched = Some(*count);
+            }
+            // keeps a busy broadcast open when WATCHED_CHANGE stops coming for a while
+            _ if self.current.is_some() && message.server_timestamp().is_some() => {
+                self.last_activity = self.last_activity.max(Some(now));
+            }
+            _ => (),
+        }
+
+        Ok(self.current())
+    }
+
+    fn open(&mut self, persist: &LivePersist, at: i64, reason: &str) -> Result<()> {
+        let id = persist.open_session(&self.room_id, at, reason)?;
+
+        info!("[{}] session {id} started ({reason})", self.room_id);
+
+        self.current = Some(Session { id, started_at: at });
+        self.last_activity = Some(at);
+
+        Ok(())
+    }
+
+    fn close(&mut self, persist: &LivePersist, at: i64, reason: &str) -> Result<()> {
+        if let Some(session) = self.current.take() {
+            persist.close_session(session.id, at, reason)?;
+
+            info!("[{}] session {} ended ({reason})", self.room_id, session.id);
+        }
+
+        self.last_activity = None;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::message::RawMessage;
+    use serde_json::Value;
+
+    const MINUTE: u64 = 60 * 1000;
+    const START: u64 = 1_700_000_000_000;
+
+    fn message(fixture: &str, pointer: &str, ts: u64) -> LiveMessage {
+        let mut data: Value = serde_json::from_str(fixture).unwrap();
+        *data.pointer_mut(pointer).unwrap() = ts.into();
+
+        LiveMessage::parse(&RawMessage::new("21452505", data))
+            .unwrap()
+            .message
+    }
+
+    fn danmaku(ts: u64) -> LiveMessage {
+        message(
+            include_str!("message/fixtures/danmu_msg.json"),
+            "/info/0/4",
+            ts,
+        )
+    }
+
+    fn live(ts: u64) -> LiveMessage {
+        message(
+            include_str!("message/fixtures/live.json"),
+            "/live_time",
+            ts / 1000,
+        )
+    }
+
+    fn preparing(ts: u64) -> LiveMessage {
+        message(
+            include_str!("message/fixtures/preparing.json"),
+            "/send_time",
+            ts,
+        )
+    }
+
+    // stamped with the time it is parsed at
+    fn watched(count: u64) -> LiveMessage {
+        message(
+            include_str!("message/fixtures/watched_change.json"),
+            "/data/num",
+            count,
+        )
+    }
+
+    fn end_reasons(persist: &LivePersist) -> Vec<(i64, Option<String>)> {
+        persist
+            .sessions_between("21452505", 0, i64::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.id, x.end_reason))
+            .collect()
+    }
+
+    fn now() -> u64 {
+        Local::now().timestamp_millis() as u64
+    }
+
+    #[test]
+    fn steady_danmaku_keeps_a_session_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let persist = LivePersist::new(&dir.path().join("live.db")).unwrap();
+        let mut tracker = SessionTracker::new("21452505", &persist).unwrap();
+
+        let live = message(
+            include_str!("message/fixtures/live.json"),
+            "/live_time",
+            START / 1000,
+        );
+        let session = tracker.observe(&persist, &live).unwrap();
+
+        assert!(session.is_some());
+
+        // no WATCHED_CHANGE at all, but a danmaku every 20 minutes
+        for minutes in [20, 40, 60, 80] {
+            let current = tracker.observe(&persist, &danmaku(START + minutes * MINUTE));
+            assert_eq!(current.unwrap(), session, "{minutes}");
+        }
+
+        // then nothing for longer than the gap
+        let late = danmaku(START + 80 * MINUTE + SESSION_GAP as u64 + MINUTE);
+
+        assert_eq!(tracker.observe(&persist, &late).unwrap(), None);
+        assert_eq!(
+            persist.current_session("21452505").unwrap().map(|x| x.id),
+            None
+        );
+
+        let sessions = persist
+            .sessions_between("21452505", (START + 10 * MINUTE) as i64, i64::MAX)
+            .unwrap();
+
+        assert_eq!(
+            sessions.iter().map(|x| Some(x.id)).collect::<Vec<_>>(),
+            [session]
+        );
+        assert_eq!(sessions[0].end_reason.as_deref(), Some("gap"));
+    }
+
+    #[test]
+    fn repeated_live_keeps_the_session_and_a_late_one_restarts_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let persist = LivePersist::new(&dir.path().join("live.db")).unwrap();
+        let mut tracker = SessionTracker::new("21452505", &persist).unwrap();
+
+        let first = tracker.observe(&persist, &live(START)).unwrap();
+
+        // LIVE comes a few times as the stream starts
+        for seconds in [0, 5, 60] {
+            let current = tracker.observe(&persist, &live(START + seconds * 1000));
+            assert_eq!(current.unwrap(), first, "{seconds}");
+        }
+
+        // a LIVE well after the start means the stream was restarted
+        let second = tracker
+            .observe(&persist, &live(START + 10 * MINUTE))
+            .unwrap();
+
+        assert!(second.is_some());
+        assert_ne!(second, first);
+        assert_eq!(
+            end_reasons(&persist),
+            [
+                (first.unwrap(), Some("restart".into())),
+                (second.unwrap(), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn watched_change_after_preparing_opens_no_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let persist = LivePersist::new(&dir.path().join("live.db")).unwrap();
+        let mut tracker = SessionTracker::new("21452505", &persist).unwrap();
+
+        let session = tracker
+            .observe(&persist, &live(now() - 10 * MINUTE))
+            .unwrap();
+
+        assert_eq!(
+            tracker
+                .observe(&persist, &preparing(now() - MINUTE))
+                .unwrap(),
+            session
+        );
+
+        // the count still grows from the viewers that were watching
+        for count in [100, 200, 300] {
+            assert_eq!(tracker.observe(&persist, &watched(count)).unwrap(), None);
+        }
+
+        assert_eq!(
+            end_reasons(&persist),
+            [(session.unwrap(), Some("preparing".into()))]
+        );
+
+        // once the room has been off for a while, growth means a LIVE was missed
+        let mut tracker = SessionTracker::new("21452505", &persist).unwrap();
+        let late = now() - SESSION_GAP as u64 - MINUTE;
+
+        tracker.observe(&persist, &preparing(late)).unwrap();
+
+        assert_eq!(tracker.observe(&persist, &watched(300)).unwrap(), None);
+        assert!(tracker.observe(&persist, &watched(400)).unwrap().is_some());
+    }
+
+    #[test]
+    fn resumes_the_open_session_on_start_unless_it_went_quiet() {
+        let dir = tempfile::tempdir().unwrap();
+        let persist = LivePersist::new(&dir.path().join("live.db")).unwrap();
+
+        let recent = persist
+            .open_session("21452505", (now() - 10 * MINUTE) as i64, "live")
+            .unwrap();
+        let tracker = SessionTracker::new("21452505", &persist).unwrap();
+
+        assert_eq!(tracker.current(), Some(recent));
+
+        persist
+            .close_session(recent, (now() - 5 * MINUTE) as i64, "preparing")
+            .unwrap();
+
+        let stale = persist
+            .open_session(
+                "21452505",
+                now() as i64 - SESSION_GAP - MINUTE as i64,
+                "live",
+            )
+            .unwrap();
+        let tracker = SessionTracker::new("21452505", &persist).unwrap();
+
+        assert_eq!(tracker.current(), None);
+        assert!(end_reasons(&persist).contains(&(stale, Some("gap".into()))));
+    }
+}
@@ -1,53 +1,674 @@
-mod data;
-mod live;
-
-use crate::data::logger;
-use crate::data::logger::MessageLogger;
-use crate::live::LiveClient;
-use crate::live::credential::Credential;
-use crate::live::message::LiveMessage;
-use anyhow::Result;
-use log::{debug, error, trace};
+use anyhow::{Context, Result, bail};
+use blivedm_rs::config::{Config, RoomConfig, SinkConfig};
+use blivedm_rs::data::credentials::CredentialStore;
+use blivedm_rs::data::database::LivePersist;
+use blivedm_rs::data::logger;
+use blivedm_rs::data::search::{SearchIndex, SearchQuery};
+use blivedm_rs::data::users::UserPersist;
+use blivedm_rs::export::danmaku::DanmakuFormat;
+use blivedm_rs::export::{ExportFormat, ExportQuery, ExportSource, danmaku};
+use blivedm_rs::live::credential::{Credential, LoginCheck, LoginInfo, Secret, read_secret_file};
+use blivedm_rs::live::message::registry::PARSERS;
+use blivedm_rs::live::pool::{Account, CredentialPool};
+use blivedm_rs::live::{ClientEvent, ExitReason, LiveClient};
+use blivedm_rs::redact::Redactor;
+use blivedm_rs::server::hub::HUB;
+use blivedm_rs::stats::exporter::EXPORTER;
+use blivedm_rs::watcher::RoomWatcher;
+use blivedm_rs::{export, redact, replay, server};
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use log::{error, info, trace, warn};
+use std::collections::HashMap;
 use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Runtime;
+use tokio::time::Instant;
+use tokio::{task, time};
+use zeroize::Zeroizing;
+
+const METRICS_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+const USAGE: &str = "usage: blivedm_rs [command]
+
+commands:
+    replay <room_id> <raw.jsonl>... [--dry-run]
+                                     run the alert rules over recorded messages,
+                                     --dry-run prints the actions instead of running them
+    user <uid|name>                  show the profile of a user
+    names <uid|name>                 show every name a user has used
+    sessions <room_id> [--from <time>] [--to <time>]
+                                     list the broadcast sessions of a room
+    search <text> [--room <room_id>] [--from <time>] [--to <time>] [--uid <uid>]
+                  [--session <id>] [--limit <n>]
+                                     search archived danmaku and super chats
+    reindex                          rebuild the search index
+    export <room_id> <output> [<raw.jsonl>...] [--format <csv|ndjson|parquet>]
+           [--types <type,...>] [--from <time>] [--to <time>]
+                                     export messages from the database, or from raw logs
+                                     when given, the format defaults to the extension
+    danmaku <room_id> <output.ass|output.xml> [<raw.jsonl>...]
+            (--session <id> | --start <time>) [--end <time>] [--offset <seconds>]
+                                     export danmaku as subtitles for a recording that
+                                     began at the start of a session or at --start,
+                                     --offset is how much later the recording began
+    parsers                          list the supported cmds and their parser versions
+    redact <raw.jsonl> <output.jsonl> [--key-file <path>]
+                                     pseudonymise the users of a raw log with the key
+                                     in the file or in $BLIVEDM_REDACT_KEY, the same key
+                                     gives the same pseudonyms wherever it is used
+    fixtures <raw.jsonl> <dir> [--key-file <path>]
+                                     write the first message of every cmd in a raw log
+                                     to <dir>/<cmd>.json, redacted as above
+
+times are YYYY-MM-DD, YYYY-MM-DD HH:MM[:SS] or milliseconds since the epoch";
 
 fn main() -> Result<()> {
     logger::init();
 
-    let room_id = &env::var("ROOM_ID").expect("ROOM_ID must be set");
-    let sessdata = &env::var("SESSDATA").expect("SESSDATA must be set");
+    let config = Config::load()?;
+    let args: Vec<String> = env::args().skip(1).collect();
 
     let rt = Runtime::new().expect("failed to initialize tokio runtime");
-    let mut client = LiveClient::new(room_id, &Credential::from_sessdata(sessdata));
+
+    match args.first().map(String::as_str) {
+        None => watch(&rt, config),
+        Some("replay") => replay(&rt, &args[1..], &config),
+        Some("user") => match &args[1..] {
+            [query] => show_users(query),
+            _ => bail!(USAGE),
+        },
+        Some("names") => match &args[1..] {
+            [query] => show_names(query),
+            _ => bail!(USAGE),
+        },
+        Some("sessions") => show_sessions(&args[1..]),
+        Some("search") => search(&args[1..]),
+        Some("export") => export(&args[1..]),
+        Some("danmaku") => export_danmaku(&args[1..]),
+        Some("reindex") => {
+            let count = SearchIndex::new(&LivePersist::path())?.reindex()?;
+            println!("indexed {count} messages");
+            Ok(())
+        }
+        Some("redact") => redact(&args[1..]),
+        Some("fixtures") => fixtures(&args[1..]),
+        Some("parsers") => {
+            for parser in PARSERS.parsers() {
+                println!(
+                    "{:<24} v{:<3} {} samples",
+                    parser.cmd,
+                    parser.version,
+                    parser.samples.len()
+                );
+            }
+
+            Ok(())
+        }
+        Some(_) => bail!(USAGE),
+    }
+}
+
+fn watch(rt: &Runtime, config: Config) -> Result<()> {
+    if config.rooms.is_empty() {
+        bail!(
+            "no rooms configured, set ROOM_ID or add rooms to {}",
+            Config::path().display()
+        )
+    }
+
+    let pool = Arc::new(load_pool()?);
 
     rt.block_on(async {
-        let handle = client.connect();
-        let mut message_logger = MessageLogger::new(room_id);
+        if let Some(server) = config.server {
+            task::spawn(async move {
+                if let Err(err) = server::serve(&server).await {
+                    error!("server stopped: {err:?}");
+                }
+            });
+        }
 
-        while let Some(message) = client.next_message().await {
-            if let Err(err) = message_logger.write(&message.data()) {
-                error!("failed to write message: {}", err);
+        let tasks: Vec<_> = config
+            .rooms
+            .into_iter()
+            .map(|room| {
+                task::spawn(watch_room(
+                    room,
+                    config.sinks.clone(),
+                    Duration::from_secs(config.metrics_window),
+                    pool.clone(),
+                ))
+            })
+            .collect();
+
+        for task in tasks {
+            match task.await {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => error!("{err:?}"),
+                Err(err) => error!("failed to join room task: {err:?}"),
             }
+        }
+    });
+
+    Ok(())
+}
+
+async fn watch_room(
+    room: RoomConfig,
+    sinks: Vec<SinkConfig>,
+    metrics_window: Duration,
+    pool: Arc<CredentialPool>,
+) -> Result<()> {
+    let mut watcher = RoomWatcher::new(&room.room_id, &sinks, metrics_window)?;
+    let result = run_room(&room, &pool, &mut watcher).await;
+
+    watcher.close().await;
+
+    result
+}
+
+async fn run_room(
+    room: &RoomConfig,
+    pool: &CredentialPool,
+    watcher: &mut RoomWatcher,
+) -> Result<()> {
+    let room_id = &room.room_id;
+    let mut metrics_timer = time::interval_at(Instant::now() + METRICS_INTERVAL, METRICS_INTERVAL);
+    let mut rotate = false; // 上一个账号的连接已放弃，换下一个账号
+    let mut failures = 0;
+
+    loop {
+        let preferred = if rotate {
+            None
+        } else {
+            room.account.as_deref()
+        };
+        let Some(account) = pool.acquire(preferred) else {
+            let delay = pool
+                .next_recovery()
+                .with_context(|| format!("[{room_id}] no account to watch with"))?;
+
+            warn!("[{room_id}] every account failed recently, retrying in {delay:?}");
+
+            time::sleep(delay).await;
+            continue;
+        };
+
+        let Some(login) = check_account(room_id, &account).await else {
+            pool.mark_failed(&account.name);
+            continue;
+        };
 
-            let message = LiveMessage::try_from(message);
+        info!(
+            "[{room_id}] logged in as {} ({}) with account {}",
+            login.uname, login.uid, account.name
+        );
 
-            match message {
-                Ok(LiveMessage::Unsupported(msg_type)) => {
-                    trace!("unsupported message type: {}", msg_type);
+        HUB.set_account(room_id, &account.name);
+
+        let mut client = LiveClient::new(room_id, &account.credential, login.uid);
+        let handle = client.connect();
+
+        loop {
+            let event = tokio::select! {
+                event = client.next_event() => event,
+                _ = metrics_timer.tick() => {
+                    watcher.snapshot_metrics();
+                    watcher.flush();
+                    continue;
+                }
+            };
+
+            let Some(event) = event else {
+                break;
+            };
+
+            EXPORTER.set_backlog(room_id, client.backlog());
+
+            match event {
+                ClientEvent::Message(raw) => watcher.handle_message(raw),
+                ClientEvent::Undecodable(body, err) => watcher.handle_undecodable(&body, err),
+                ClientEvent::AuthOk => {
+                    info!("[{room_id}] authenticated");
+                    failures = 0;
+                    rotate = false;
+                    HUB.set_connected(room_id, true);
+                }
+                ClientEvent::HeartbeatReply {
+                    popularity,
+                    latency,
+                } => {
+                    trace!("[{room_id}] heartbeat, popularity: {popularity}, latency: {latency:?}");
+                    EXPORTER.set_heartbeat_latency(room_id, latency);
+                }
+                ClientEvent::AuthFailed => {
+                    error!(
+                        "[{room_id}] authentication failed with account {}",
+                        account.name
+                    );
+                }
+                ClientEvent::Disconnected(reason) => {
+                    warn!("[{room_id}] disconnected: {reason:?}");
+                    HUB.set_connected(room_id, false);
                 }
-                Ok(message) => {
-                    debug!("{message:?}");
+                ClientEvent::Reconnecting { attempt, delay } => {
+                    info!("[{room_id}] reconnecting in {delay:?} (attempt {attempt})");
+                    EXPORTER.inc_reconnect(room_id);
                 }
-                Err(msg) => {
-                    error!("failed to parse message: {:?}", msg);
+                event => {
+                    info!("[{room_id}] {event:?}");
                 }
             }
         }
 
         client.close().await;
 
-        let _ = handle.await;
-    });
+        // only a close stops watching the room, any other exit moves on to the next account
+        match handle.await {
+            Ok(ExitReason::Stopped) => {
+                info!("[{room_id}] connection closed");
+                return Ok(());
+            }
+            Ok(reason) => {
+                failures += 1;
+
+                // a rejected account cools down in the pool, otherwise rotate away from it
+                if reason == ExitReason::AuthFailed {
+                    pool.mark_failed(&account.name);
+                } else {
+                    rotate = true;
+                }
+
+                let delay = backoff(failures);
+
+                warn!(
+                    "[{room_id}] gave up on the connection with account {}, \
+                     retrying with the next account in {delay:?}: {reason:?}",
+                    account.name
+                );
+
+                time::sleep(delay).await;
+            }
+            Err(err) => bail!("[{room_id}] failed to join client: {err:?}"),
+        }
+    }
+}
+
+// retries on the same account until the server answers, returns None when it rejects the account
+async fn check_account(room_id: &str, account: &Account) -> Option<LoginInfo> {
+    let mut attempt = 0;
+
+    loop {
+        match account.credential.check().await {
+            Ok(LoginCheck::LoggedIn(login)) => return Some(login),
+            Ok(LoginCheck::Rejected(reason)) => {
+                warn!("[{room_id}] account {} is unusable: {reason}", account.name);
+                return None;
+            }
+            Err(err) => {
+                attempt += 1;
+
+                let delay = backoff(attempt);
+
+                warn!(
+                    "[{room_id}] failed to check account {}, retrying in {delay:?}: {err:?}",
+                    account.name
+                );
+
+                time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+// 5s doubling up to `MAX_RETRY_DELAY`, `attempt` counts from 1
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(5 << (attempt - 1).min(6)).min(MAX_RETRY_DELAY)
+}
+
+fn replay(rt: &Runtime, args: &[String], config: &Config) -> Result<()> {
+    let dry_run = args.iter().any(|x| x == "--dry-run");
+    let args: Vec<_> = args.iter().filter(|x| *x != "--dry-run").cloned().collect();
+
+    let [room_id, files @ ..] = args.as_slice() else {
+        bail!(USAGE)
+    };
+
+    if files.is_empty() {
+        bail!(USAGE)
+    }
+
+    rt.block_on(replay::alerts(room_id, files, &config.sinks, dry_run))?;
+
+    Ok(())
+}
+
+fn show_users(query: &str) -> Result<()> {
+    let profiles = UserPersist::new(&LivePersist::path())?.lookup(query)?;
+
+    if profiles.is_empty() {
+        bail!("no user matches {query}")
+    }
+
+    println!("{}", serde_json::to_string_pretty(&profiles)?);
+
+    Ok(())
+}
+
+// one line per uid: the current name, then every rename in order
+fn show_names(query: &str) -> Result<()> {
+    let histories = UserPersist::new(&LivePersist::path())?.lookup_names(query)?;
+
+    if histories.is_empty() {
+        bail!("no user matches {query}")
+    }
+
+    for history in histories {
+        println!("{} {}", history.uid, history.uname);
+
+        for name in &history.names {
+            println!(
+                "    {} ({} ~ {})",
+                name.uname,
+                time(name.first_seen),
+                time(name.last_seen)
+            );
+        }
+
+        for rename in &history.renames {
+            println!(
+                "    {} -> {} at {} in room {}",
+                rename.from,
+                rename.to,
+                time(rename.timestamp),
+                rename.room_id
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn show_sessions(args: &[String]) -> Result<()> {
+    let (positional, options) = parse_args(args, &["from", "to"])?;
+
+    let [room_id] = positional.as_slice() else {
+        bail!(USAGE)
+    };
+
+    let from = options.get("from").map(|x| parse_time(x)).transpose()?;
+    let to = options.get("to").map(|x| parse_time(x)).transpose()?;
+
+    let sessions = LivePersist::new(&LivePersist::path())?.sessions_between(
+        room_id,
+        from.unwrap_or(i64::MIN),
+        to.unwrap_or(i64::MAX),
+    )?;
+
+    for session in sessions {
+        println!(
+            "{} {} ~ {} ({} ~ {})",
+            session.id,
+            time(session.started_at),
+            session.ended_at.map(time).unwrap_or_else(|| "live".into()),
+            session.start_reason,
+            session.end_reason.as_deref().unwrap_or("-")
+        );
+    }
+
+    Ok(())
+}
+
+fn search(args: &[String]) -> Result<()> {
+    let (positional, options) =
+        parse_args(args, &["room", "from", "to", "uid", "session", "limit"])?;
+
+    let [text] = positional.as_slice() else {
+        bail!(USAGE)
+    };
+
+    let query = SearchQuery {
+        text: text.clone(),
+        room: options.get("room").cloned(),
+        from: options.get("from").map(|x| parse_time(x)).transpose()?,
+        to: options.get("to").map(|x| parse_time(x)).transpose()?,
+        uid: options.get("uid").map(|x| x.parse()).transpose()?,
+        session: options.get("session").map(|x| x.parse()).transpose()?,
+        limit: options.get("limit").map(|x| x.parse()).transpose()?,
+    };
+
+    for hit in SearchIndex::new(&LivePersist::path())?.search(&query)? {
+        println!(
+            "{} [{}] {} ({}): {}",
+            time(hit.timestamp),
+            hit.room_id,
+            hit.uname,
+            hit.uid,
+            hit.text
+        );
+    }
+
+    Ok(())
+}
+
+fn export(args: &[String]) -> Result<()> {
+    let (positional, options) = parse_args(args, &["format", "types", "from", "to"])?;
+
+    let [room_id, output, files @ ..] = positional.as_slice() else {
+        bail!(USAGE)
+    };
+
+    let output = Path::new(output);
+    let format = match options.get("format") {
+        Some(format) => format.parse()?,
+        None => ExportFormat::from_path(output).with_context(|| {
+            format!(
+                "can not tell the format of {}, pass --format",
+                output.display()
+            )
+        })?,
+    };
+
+    let query = ExportQuery {
+        room_id: room_id.clone(),
+        kinds: options
+            .get("types")
+            .map(|x| x.split(',').map(String::from).collect())
+            .unwrap_or_default(),
+        from: options.get("from").map(|x| parse_time(x)).transpose()?,
+        to: options.get("to").map(|x| parse_time(x)).transpose()?,
+    };
+
+    let count = if files.is_empty() {
+        let persist = LivePersist::new(&LivePersist::path())?;
+        export::export(&ExportSource::Database(&persist), &query, format, &output)?
+    } else {
+        export::export(&ExportSource::Raw(files), &query, format, &output)?
+    };
+
+    println!("exported {count} messages to {}", output.display());
+
+    Ok(())
+}
+
+fn redact(args: &[String]) -> Result<()> {
+    let (positional, options) = parse_args(args, &["key-file"])?;
+
+    let [input, output] = positional.as_slice() else {
+        bail!(USAGE)
+    };
+
+    let count = redact::redact(&redactor(&options)?, input, output)?;
+
+    println!("redacted {count} messages to {output}");
+
+    Ok(())
+}
+
+fn fixtures(args: &[String]) -> Result<()> {
+    let (positional, options) = parse_args(args, &["key-file"])?;
+
+    let [input, dir] = positional.as_slice() else {
+        bail!(USAGE)
+    };
+
+    for path in redact::extract_fixtures(&redactor(&options)?, input, dir)? {
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
+
+// never from the command line, where the key would end up in the shell history
+fn redactor(options: &HashMap<String, String>) -> Result<Redactor> {
+    let key = match options.get("key-file") {
+        Some(path) => read_secret_file(Path::new(path)).context("failed to read key file")?,
+        None => Zeroizing::new(
+            env::var("BLIVEDM_REDACT_KEY")
+                .context("pass --key-file or set BLIVEDM_REDACT_KEY to redact")?,
+        ),
+    };
+
+    Ok(Redactor::new(Secret::new(key.trim_end())))
+}
+
+fn export_danmaku(args: &[String]) -> Result<()> {
+    let (positional, options) = parse_args(args, &["session", "start", "end", "offset"])?;
+
+    let [room_id, output, files @ ..] = positional.as_slice() else {
+        bail!(USAGE)
+    };
+
+    let output = Path::new(output);
+    let format = DanmakuFormat::from_path(output)
+        .with_context(|| format!("{} is neither .ass nor .xml", output.display()))?;
+
+    let persist = LivePersist::new(&LivePersist::path())?;
+
+    let (start, end) = match (options.get("session"), options.get("start")) {
+        (Some(id), None) => {
+            let session = persist
+                .session(id.parse()?)?
+                .with_context(|| format!("no session {id}"))?;
+
+            if &session.room_id != room_id {
+                bail!("session {id} belongs to room {}", session.room_id)
+            }
+
+            (session.started_at, session.ended_at)
+        }
+        (None, Some(start)) => (parse_time(start)?, None),
+        _ => bail!("pass either --session or --start\n\n{USAGE}"),
+    };
+
+    let offset: f64 = options
+        .get("offset")
+        .map(|x| x.parse())
+        .transpose()?
+        .unwrap_or(0.0);
+    let start = start + (offset * 1000.0) as i64;
+    let end = options
+        .get("end")
+        .map(|x| parse_time(x))
+        .transpose()?
+        .or(end);
+
+    let source = if files.is_empty() {
+        ExportSource::Database(&persist)
+    } else {
+        ExportSource::Raw(files)
+    };
+
+    let count = danmaku::export(&source, room_id, start, end, format, &output)?;
+
+    println!("exported {count} danmaku to {}", output.display());
 
     Ok(())
 }
+
+// splits `--name value` options from positional arguments, unknown options are an error
+fn parse_args(args: &[String], names: &[&str]) -> Result<(Vec<String>, HashMap<String, String>)> {
+    let mut positional = Vec::new();
+    let mut options = HashMap::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let Some(name) = arg.strip_prefix("--") else {
+            positional.push(arg.clone());
+            continue;
+        };
+
+        if !names.contains(&name) {
+            bail!("unknown option --{name}\n\n{USAGE}")
+        }
+
+        let value = args
+            .next()
+            .with_context(|| format!("missing value for --{name}"))?;
+
+        options.insert(name.to_owned(), value.clone());
+    }
+
+    Ok((positional, options))
+}
+
+// local date, local date and time, or milliseconds
+fn parse_time(value: &str) -> Result<i64> {
+    if let Ok(timestamp) = value.parse() {
+        return Ok(timestamp);
+    }
+
+    let time = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|x| x.and_hms_opt(0, 0, 0))
+        })
+        .with_context(|| format!("invalid time {value}"))?;
+
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .map(|x| x.timestamp_millis())
+        .with_context(|| format!("invalid local time {value}"))
+}
+
+fn time(timestamp: i64) -> String {
+    Local
+        .timestamp_millis_opt(timestamp)
+        .single()
+        .map(|x| x.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn load_pool() -> Result<CredentialPool> {
+    let mut accounts = CredentialStore::new().load()?;
+
+    if let Some(credential) = load_env_credential()? {
+        accounts.push(("env".into(), credential));
+    }
+
+    if accounts.is_empty() {
+        bail!("no accounts configured, add one to the credential store or set COOKIE / SESSDATA")
+    }
+
+    Ok(CredentialPool::new(accounts))
+}
+
+fn load_env_credential() -> Result<Option<Credential>> {
+    if let Ok(file) = env::var("COOKIES_FILE") {
+        return Credential::from_cookies_file(&file).map(Some);
+    }
+
+    if let Ok(cookie) = env::var("COOKIE") {
+        return Credential::from_cookie_header(&cookie).map(Some);
+    }
+
+    Ok(env::var("SESSDATA")
+        .ok()
+        .map(|sessdata| Credential::from_sessdata(&sessdata)))
+}
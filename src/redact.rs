@@ -0,0 +1,529 @@
+use crate::live::credential::Secret;
+use crate::live::message::{LiveMessage, Parsed, RawMessage, UserInteractType, proto};
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hmac::{Hmac, Mac};
+use log::warn;
+use prost::Message;
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const NOFACE: &str = "https://i0.hdslb.com/bfs/face/member/noface.jpg";
+
+// keys holding the uid of a user or an anchor
+const UID_KEYS: &[&str] = &["uid", "mid", "ruid", "reply_mid", "target_id"];
+const NAME_KEYS: &[&str] = &[
+    "uname",
+    "username",
+    "anchor_uname",
+    "reply_uname",
+    "best_uname",
+];
+// objects whose `name` is a user name rather than that of a gift or medal
+const USER_OBJECTS: &[&str] = &["base", "origin_info", "risk_ctrl_info"];
+
+// Pseudonymises the users in raw messages. Uids and names are replaced by a keyed hash
+// of them, so a user keeps the same pseudonym across messages and files redacted with
+// the same key while the original can not be recovered without it. Faces are replaced
+// by the default one, everything else including the structure is left as it is.
+pub struct Redactor {
+    key: Secret,
+}
+
+impl Redactor {
+    pub fn new(key: Secret) -> Self {
+        Self { key }
+    }
+
+    fn hash(&self, kind: &str, value: &str) -> [u8; 32] {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.expose().as_bytes())
+            .expect("hmac accepts keys of any length");
+
+        mac.update(kind.as_bytes());
+        mac.update(b":");
+        mac.update(value.as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+
+    // a ten digit uid, 0 (nobody) stays 0
+    pub fn uid(&self, uid: u64) -> u64 {
+        if uid == 0 {
+            return 0;
+        }
+
+        let hash = self.hash("uid", &uid.to_string());
+        let value = u64::from_be_bytes(hash[..8].try_into().expect("hash is 32 bytes"));
+
+        1_000_000_000 + value % 9_000_000_000
+    }
+
+    pub fn uname(&self, uname: &str) -> String {
+        if uname.is_empty() {
+            return String::new();
+        }
+
+        let hash = self.hash("uname", uname);
+        let hex: String = hash[..4].iter().map(|x| format!("{x:02x}")).collect();
+
+        format!("anon_{hex}")
+    }
+
+    pub fn message(&self, data: &mut Value) -> Result<()> {
+        let mut uids = HashSet::new();
+
+        collect_uids(data, &mut uids);
+        self.value(None, data, &uids);
+
+        // the cmd without its parameters, see `RawMessage::msg_type`
+        let cmd = data["cmd"].as_str().unwrap_or_default();
+        let msg_type = cmd.split(':').next().unwrap_or_default().to_owned();
+
+        match msg_type.as_str() {
+            "DANMU_MSG" => {
+                if let Some(info) = data.get_mut("info") {
+                    self.danmaku_info(info);
+                }
+            }
+            "INTERACT_WORD_V2" => {
+                if let Some(pb) = data.pointer_mut("/data/pb")
+                    && let Some(encoded) = pb.as_str()
+                {
+                    *pb = self
+                        .interact_word(encoded)
+                        .context("failed to redact pb")?
+                        .into();
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    // `uids` are the ones in the message, they also show up inside ids like
+    // `batch:gift:combo_id:<uid>:<ruid>:<gift_id>:<time>`
+    fn value(&self, key: Option<&str>, value: &mut Value, uids: &HashSet<u64>) {
+        match value {
+            Value::Object(fields) => {
+                for (name, field) in fields.iter_mut() {
+                    match name.as_str() {
+                        name if UID_KEYS.contains(&name) => self.uid_value(field),
+                        name if NAME_KEYS.contains(&name) => self.uname_value(field),
+                        "name" if key.is_some_and(|x| USER_OBJECTS.contains(&x)) => {
+                            self.uname_value(field)
+                        }
+                        "face" if field.is_string() => *field = NOFACE.into(),
+                        // crc32 of the uid, easily reversed
+                        "user_hash" if field.is_string() => *field = "0".into(),
+                        // the common data of a danmaku holds more JSON as a string
+                        "extra" if field.is_string() => self.nested(field, uids),
+                        name => self.value(Some(name), field, uids),
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    self.value(key, item, uids);
+                }
+            }
+            Value::String(text) if text.contains(':') => {
+                let tokens: Vec<_> = text
+                    .split(':')
+                    .map(|token| match token.parse() {
+                        Ok(uid) if uids.contains(&uid) => self.uid(uid).to_string(),
+                        _ => token.to_owned(),
+                    })
+                    .collect();
+
+                *text = tokens.join(":");
+            }
+            _ => (),
+        }
+    }
+
+    fn uid_value(&self, value: &mut Value) {
+        match value {
+            Value::Number(uid) => {
+                if let Some(uid) = uid.as_u64() {
+                    *value = self.uid(uid).into();
+                }
+            }
+            Value::String(uid) => {
+                if let Ok(parsed) = uid.parse::<u64>() {
+                    *uid = self.uid(parsed).to_string();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn uname_value(&self, value: &mut Value) {
+        if let Value::String(uname) = value {
+            *uname = self.uname(uname);
+        }
+    }
+
+    fn nested(&self, value: &mut Value, uids: &HashSet<u64>) {
+        let Some(Ok(mut nested)) = value.as_str().map(serde_json::from_str::<Value>) else {
+            return;
+        };
+
+        self.value(None, &mut nested, uids);
+        *value = nested.to_string().into();
+    }
+
+    // the sender and the medal of a danmaku are arrays, see `parsers::danmaku`
+    fn danmaku_info(&self, info: &mut Value) {
+        if let Some(hash) = info.pointer_mut("/0/7")
+            && hash.is_string()
+        {
+            *hash = "0".into();
+        }
+
+        for (pointer, uid) in [
+            ("/2/0", true),
+            ("/2/1", false),
+            ("/3/2", false),
+            ("/3/12", true),
+        ] {
+            match info.pointer_mut(pointer) {
+                Some(value) if uid => self.uid_value(value),
+                Some(value) => self.uname_value(value),
+                None => (),
+            }
+        }
+    }
+
+    fn interact_word(&self, pb: &str) -> Result<String> {
+        let pb = STANDARD.decode(pb.as_bytes())?;
+        let mut iw2 = proto::InteractWordV2::decode(pb.as_slice())?;
+
+        // prost drops the fields the proto does not know, which may well hold a user
+        if iw2.encoded_len() != pb.len() {
+            bail!(
+                "INTERACT_WORD_V2 has {} bytes the proto does not cover, refusing to drop them",
+                pb.len().abs_diff(iw2.encoded_len())
+            )
+        }
+
+        iw2.uid = self.uid(iw2.uid);
+        iw2.uname = self.uname(&iw2.uname);
+
+        if let Some(medal) = &mut iw2.fans_medal {
+            medal.target_id = self.uid(medal.target_id as u64) as i64;
+        }
+
+        if let Some(uinfo) = &mut iw2.uinfo {
+            uinfo.uid = self.uid(uinfo.uid);
+
+            if let Some(base) = &mut uinfo.base {
+                base.name = self.uname(&base.name);
+                base.face = NOFACE.into();
+
+                if let Some(origin) = &mut base.origin_info {
+                    origin.name = self.uname(&origin.name);
+                    origin.face = NOFACE.into();
+                }
+
+                if let Some(risk) = &mut base.risk_ctrl_info {
+                    risk.name = self.uname(&risk.name);
+                    risk.face = NOFACE.into();
+                }
+            }
+
+            if let Some(medal) = &mut uinfo.medal {
+                medal.ruid = self.uid(medal.ruid as u64) as i64;
+            }
+        }
+
+        Ok(STANDARD.encode(iw2.encode_to_vec()))
+    }
+}
+
+fn collect_uids(value: &Value, uids: &mut HashSet<u64>) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                if UID_KEYS.contains(&name.as_str()) {
+                    uids.extend(field.as_u64().or_else(|| field.as_str()?.parse().ok()));
+                } else {
+                    collect_uids(field, uids);
+                }
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|x| collect_uids(x, uids)),
+        _ => (),
+    }
+}
+
+// Rewrites a raw log with its users pseudonymised, returns the number of messages.
+// Lines that are not JSON are dropped since they can not be redacted.
+pub fn redact(
+    redactor: &Redactor,
+    input: &dyn AsRef<Path>,
+    output: &dyn AsRef<Path>,
+) -> Result<usize> {
+    let input = input.as_ref();
+    let mut output = BufWriter::new(File::create(output)?);
+    let mut count = 0;
+
+    for_each_message(input, |line, mut data| {
+        if let Err(err) = redactor.message(&mut data) {
+            warn!("dropping {}:{line}: {err:?}", input.display());
+            return Ok(());
+        }
+
+        writeln!(output, "{data}")?;
+        count += 1;
+
+        Ok(())
+    })?;
+
+    output.flush()?;
+
+    Ok(count)
+}
+
+// Picks the first message of every supported cmd, and of every kind of interaction, from a
+// raw log and writes it redacted to `<dir>/<cmd>.json` as a golden fixture. Fixtures that
+// already exist are left alone, returns the ones written. Their snapshots are left to
+// `UPDATE_SNAPSHOTS=1 cargo test golden`.
+pub fn extract_fixtures(
+    redactor: &Redactor,
+    input: &dyn AsRef<Path>,
+    dir: &dyn AsRef<Path>,
+) -> Result<Vec<PathBuf>> {
+    let (input, dir) = (input.as_ref(), dir.as_ref());
+    let mut seen = HashSet::new();
+    let mut written = Vec::new();
+
+    fs::create_dir_all(dir)?;
+
+    for_each_message(input, |line, mut data| {
+        let raw = RawMessage::new("", data.clone());
+
+        let message = match LiveMessage::parse(&raw) {
+            Ok(Parsed { message, .. }) if message.kind() != "unsupported" => message,
+            _ => return Ok(()),
+        };
+
+        let mut name = raw.msg_type()?.to_lowercase();
+
+        if let Some(variant) = variant(&message) {
+            name = format!("{name}-{variant}");
+        }
+
+        if !seen.insert(name.clone()) {
+            return Ok(());
+        }
+
+        let path = dir.join(format!("{name}.json"));
+
+        if path.exists() {
+            return Ok(());
+        }
+
+        if let Err(err) = redactor.message(&mut data) {
+            warn!("skipping {}:{line}: {err:?}", input.display());
+            seen.remove(&name);
+            return Ok(());
+        }
+
+        fs::write(&path, format!("{data}\n"))?;
+        written.push(path);
+
+        Ok(())
+    })?;
+
+    Ok(written)
+}
+
+// the suffix that tells the fixtures of one cmd apart
+fn variant(message: &LiveMessage) -> Option<&'static str> {
+    match message {
+        LiveMessage::UserInteract { msg_type, .. } => Some(match msg_type {
+            UserInteractType::JoinRoom => "join",
+            UserInteractType::Subscribe => "follow",
+            UserInteractType::Share => "share",
+        }),
+        _ => None,
+    }
+}
+
+// calls `f` with the line number and content of every JSON line of a raw log
+fn for_each_message<F>(input: &Path, mut f: F) -> Result<()>
+where
+    F: FnMut(usize, Value) -> Result<()>,
+{
+    let reader = BufReader::new(
+        File::open(input).with_context(|| format!("failed to open {}", input.display()))?,
+    );
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<Value>(&line) {
+            Ok(data) => f(index + 1, data)?,
+            Err(err) => warn!("dropping {}:{}: {err}", input.display(), index + 1),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(data: &Value) -> (LiveMessage, usize) {
+        let parsed = LiveMessage::parse(&RawMessage::new("21452505", data.clone())).unwrap();
+        (parsed.message, parsed.warnings.len())
+    }
+
+    #[test]
+    fn pseudonymises_users_consistently() {
+        let redactor = Redactor::new(Secret::new("test"));
+        let fixtures = [
+            include_str!("live/message/fixtures/danmu_msg.json"),
+            include_str!("live/message/fixtures/danmu_msg-old_client.json"),
+            include_str!("live/message/fixtures/send_gift.json"),
+            include_str!("live/message/fixtures/super_chat_message.json"),
+            include_str!("live/message/fixtures/interact_word_v2-join.json"),
+        ];
+
+        let uid = redactor.uid(3141592653);
+        let uname = redactor.uname("anon_7f3a9c21");
+
+        assert_ne!(uid, 3141592653);
+        assert_eq!(uid, Redactor::new(Secret::new("test")).uid(3141592653));
+        assert_ne!(uid, Redactor::new(Secret::new("other")).uid(3141592653));
+        assert_eq!(redactor.uid(0), 0);
+
+        for fixture in fixtures {
+            let original: Value = serde_json::from_str(fixture).unwrap();
+            let mut redacted = original.clone();
+
+            redactor.message(&mut redacted).unwrap();
+
+            let text = redacted.to_string();
+
+            for pii in ["3141592653", "anon_7f3a9c21", "1414213562", "anon_5b91e2f0"] {
+                assert!(!text.contains(pii), "{pii} left in {text}");
+            }
+
+            // still parses the same way, with the pseudonyms
+            let (before, before_warnings) = parse(&original);
+            let (after, after_warnings) = parse(&redacted);
+            let (before_user, after_user) = (before.user().unwrap(), after.user().unwrap());
+
+            assert_eq!(before.kind(), after.kind());
+            assert_eq!(before_warnings, after_warnings);
+            assert_eq!(after_user.uid(), redactor.uid(before_user.uid()));
+            assert_eq!(after_user.uname(), redactor.uname(before_user.uname()));
+            assert_eq!(before_user.medal_level(), after_user.medal_level());
+
+            if before_user.uid() == 3141592653 {
+                assert_eq!(
+                    (after_user.uid(), after_user.uname()),
+                    (uid, uname.as_str())
+                );
+            }
+        }
+
+        // malformed messages are left alone rather than panicking
+        for mut data in [
+            json!({"cmd": "DANMU_MSG"}),
+            json!({"cmd": "DANMU_MSG", "info": [[0], "text"]}),
+            json!([1, {"uid": 1}]),
+        ] {
+            redactor.message(&mut data).unwrap();
+        }
+
+        let mut data = json!({"cmd": "INTERACT_WORD_V2", "data": {"pb": "!!"}});
+        assert!(redactor.message(&mut data).is_err());
+    }
+
+    #[test]
+    fn refuses_to_drop_unknown_protobuf_fields() {
+        let redactor = Redactor::new(Secret::new("test"));
+        let mut data: Value = serde_json::from_str(include_str!(
+            "live/message/fixtures/interact_word_v2-join.json"
+        ))
+        .unwrap();
+
+        let mut pb = STANDARD
+            .decode(data["data"]["pb"].as_str().unwrap())
+            .unwrap();
+
+        // the known fields round-trip byte for byte
+        let iw2 = proto::InteractWordV2::decode(pb.as_slice()).unwrap();
+        assert_eq!(iw2.encode_to_vec(), pb);
+
+        // a field added by a newer client, such as another uid
+        prost::encoding::uint64::encode(999, &3141592653, &mut pb);
+        data["data"]["pb"] = STANDARD.encode(&pb).into();
+
+        let err = redactor.message(&mut data).unwrap_err();
+
+        assert!(format!("{err:#}").contains("refusing to drop"));
+    }
+
+    #[test]
+    fn extracts_one_fixture_per_cmd() {
+        let redactor = Redactor::new(Secret::new("test"));
+        let dir = tempfile::tempdir().unwrap();
+        let (input, fixtures) = (dir.path().join("raw.jsonl"), dir.path().join("fixtures"));
+
+        let lines = [
+            include_str!("live/message/fixtures/danmu_msg.json"),
+            include_str!("live/message/fixtures/danmu_msg-old_client.json"),
+            include_str!("live/message/fixtures/interact_word_v2-join.json"),
+            include_str!("live/message/fixtures/interact_word_v2-follow.json"),
+            include_str!("live/message/fixtures/unsupported.json"),
+            include_str!("live/message/fixtures/live.json"),
+            "not json\n",
+        ];
+        fs::write(&input, lines.concat()).unwrap();
+
+        // a fixture that is already there is kept as it is
+        fs::create_dir_all(&fixtures).unwrap();
+        fs::write(fixtures.join("live.json"), "{}").unwrap();
+
+        let mut written = extract_fixtures(&redactor, &input, &fixtures).unwrap();
+        written.sort();
+
+        assert_eq!(
+            written,
+            [
+                "danmu_msg",
+                "interact_word_v2-follow",
+                "interact_word_v2-join"
+            ]
+            .map(|x| fixtures.join(format!("{x}.json")))
+        );
+        assert_eq!(
+            fs::read_to_string(fixtures.join("live.json")).unwrap(),
+            "{}"
+        );
+
+        // the first danmaku, redacted
+        let mut expected: Value = serde_json::from_str(lines[0]).unwrap();
+        redactor.message(&mut expected).unwrap();
+
+        let danmaku = fs::read_to_string(fixtures.join("danmu_msg.json")).unwrap();
+
+        assert_eq!(serde_json::from_str::<Value>(&danmaku).unwrap(), expected);
+        assert!(!danmaku.contains("3141592653"));
+    }
+}
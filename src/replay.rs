@@ -0,0 +1,173 @@
+use crate::config::{SinkConfig, SinkKind};
+use crate::live::message::{LiveMessage, RawMessage};
+use crate::sink::alert::{self, Alert, AlertSink};
+use crate::sink::{Sink, SinkEvent};
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+// Messages recorded by the raw log, one JSON object per line
+pub fn read(room_id: &str, file: &dyn AsRef<Path>) -> Result<impl Iterator<Item = RawMessage>> {
+    let path = file.as_ref().to_owned();
+    let reader = BufReader::new(
+        File::open(&path).with_context(|| format!("failed to open {}", path.display()))?,
+    );
+    let room_id = room_id.to_owned();
+
+    Ok(reader.lines().enumerate().filter_map(move |(index, line)| {
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => return None,
+            Ok(line) => line,
+            Err(err) => {
+                warn!("{}:{}: {err}", path.display(), index + 1);
+                return None;
+            }
+        };
+
+        match serde_json::from_str::<Value>(&line) {
+            Ok(data) => Some(RawMessage::new(&room_id, data)),
+            Err(err) => {
+                warn!("{}:{}: {err}", path.display(), index + 1);
+                None
+            }
+        }
+    }))
+}
+
+// runs the configured alert rules over recorded messages and returns the alerts that fired,
+// actions are executed for real unless `dry_run` prints them instead
+pub async fn alerts(
+    room_id: &str,
+    files: &[String],
+    sinks: &[SinkConfig],
+    dry_run: bool,
+) -> Result<Vec<Arc<Alert>>> {
+    let mut alerts: Vec<_> = sinks
+        .iter()
+        .filter_map(|config| match &config.kind {
+            SinkKind::Alerts { rules } => Some((AlertSink::new(room_id, rules), &config.filter)),
+            _ => None,
+        })
+        .collect();
+
+    if alerts.is_empty() {
+        bail!("no alert rules configured")
+    }
+
+    let mut count = 0;
+    let mut fired = Vec::new();
+
+    for file in files {
+        for raw in read(room_id, file)? {
+            let Ok(message) = LiveMessage::try_from(&raw) else {
+                continue;
+            };
+
+            let event = SinkEvent {
+                session_id: None,
+                message,
+                raw,
+            };
+
+            for (sink, filter) in &mut alerts {
+                if filter
+                    .as_ref()
+                    .is_some_and(|x| !x.matches(room_id, &event.message))
+                {
+                    continue;
+                }
+
+                for (alert, actions) in sink.evaluate(&event)? {
+                    for action in &actions {
+                        if dry_run {
+                            alert::dry_run(action, &alert);
+                        } else {
+                            sink.fire(action, &alert);
+                        }
+                    }
+
+                    fired.push(alert);
+                }
+            }
+
+            count += 1;
+        }
+    }
+
+    for (sink, _) in &mut alerts {
+        sink.close().await?;
+    }
+
+    info!("replayed {count} messages, {} alerts fired", fired.len());
+
+    Ok(fired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn replays_rules_without_running_actions() {
+        let sinks: Vec<SinkConfig> = serde_json::from_value(json!([
+            {
+                "type": "alerts",
+                "rules": [
+                    {
+                        "name": "lottery",
+                        "when": "text ~ \"抽奖\"",
+                        "dedupe": 60,
+                        "actions": [{"type": "webhook", "url": "http://127.0.0.1:9/"}],
+                    },
+                    {
+                        "name": "alice",
+                        "when": "uid == 42",
+                        "rate_limit": {"count": 2, "seconds": 60},
+                        "actions": [{"type": "command", "program": "false"}],
+                    },
+                    {
+                        "name": "gifts",
+                        "when": "type == gift",
+                        "actions": [{"type": "stdout"}],
+                    },
+                ],
+            },
+            {
+                "type": "alerts",
+                "filter": "uid == 7",
+                "rules": [{"name": "bob", "when": "type == danmaku", "actions": []}],
+            },
+        ]))
+        .unwrap();
+
+        let fixture = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/replay/fixtures/alerts.jsonl"
+        );
+        let fired = alerts("1", &[fixture.into()], &sinks, true).await.unwrap();
+
+        let fired: Vec<_> = fired
+            .iter()
+            .map(|x| (x.rule.as_str(), x.fired_at - 1_700_000_000_000))
+            .collect();
+
+        // the second lottery from alice is a duplicate and her "hi" is over the rate limit
+        assert_eq!(
+            fired,
+            [
+                ("lottery", 0),
+                ("alice", 0),
+                ("alice", 1_000),
+                ("lottery", 2_000),
+                ("bob", 2_000),
+                ("lottery", 61_000),
+                ("alice", 61_000),
+            ]
+        );
+    }
+}
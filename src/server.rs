@@ -0,0 +1,232 @@
+mod api;
+pub mod hub;
+mod overlay;
+mod ws;
+
+use crate::config::ServerConfig;
+use crate::stats::exporter::EXPORTER;
+use anyhow::Result;
+use axum::Router;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use log::info;
+use tokio::net::TcpListener;
+
+pub async fn serve(config: &ServerConfig) -> Result<()> {
+    let listener = TcpListener::bind(&config.listen).await?;
+
+    info!("listening on http://{}", listener.local_addr()?);
+
+    axum::serve(listener, router(config)).await?;
+
+    Ok(())
+}
+
+fn router(config: &ServerConfig) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .route("/ws", get(ws::subscribe))
+        .route("/rooms", get(api::rooms))
+        .route("/rooms/{room_id}", get(api::room))
+        .route("/rooms/{room_id}/session", get(api::session))
+        .route("/rooms/{room_id}/danmaku", get(api::danmaku))
+        .route("/rooms/{room_id}/stats", get(api::stats))
+        .route("/users", get(api::users))
+        .route("/users/{uid}", get(api::user))
+        .route("/users/{uid}/names", get(api::user_names))
+        .route("/names/{uname}", get(api::names))
+        .route("/search", get(api::search))
+        .merge(overlay::router(&config.overlay))
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        EXPORTER.render(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OverlayConfig;
+    use crate::live::message::{LiveMessage, RawMessage};
+    use crate::server::hub::HUB;
+    use crate::sink::SinkEvent;
+    use crate::stats::metrics::MetricsEngine;
+    use futures_util::StreamExt;
+    use serde_json::{Value, json};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::time;
+
+    async fn spawn_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = ServerConfig {
+            listen: addr.to_string(),
+            overlay: OverlayConfig::default(),
+        };
+
+        tokio::spawn(async move { axum::serve(listener, router(&config)).await.unwrap() });
+
+        addr.to_string()
+    }
+
+    fn event(room_id: &str, data: Value) -> Arc<SinkEvent> {
+        let raw = RawMessage::new(room_id, data);
+
+        Arc::new(SinkEvent {
+            session_id: Some(3),
+            message: LiveMessage::try_from(&raw).unwrap(),
+            raw,
+        })
+    }
+
+    fn danmaku(room_id: &str, text: &str) -> Arc<SinkEvent> {
+        let mut data: Value =
+            serde_json::from_str(include_str!("live/message/fixtures/danmu_msg.json")).unwrap();
+        data["info"][1] = text.into();
+
+        event(room_id, data)
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_over_http() {
+        EXPORTER.inc_message("metrics-test", "DANMU_MSG");
+        EXPORTER.set_heartbeat_latency("metrics-test", Duration::from_millis(250));
+
+        let addr = spawn_server().await;
+
+        let response = reqwest::get(format!("http://{addr}/metrics"))
+            .await
+            .unwrap();
+
+        assert!(response.status().is_success());
+        assert_eq!(
+            response.headers()[reqwest::header::CONTENT_TYPE],
+            "text/plain; version=0.0.4; charset=utf-8"
+        );
+
+        let body = response.text().await.unwrap();
+
+        assert!(
+            body.contains("blivedm_messages_total{room=\"metrics-test\",cmd=\"DANMU_MSG\"} 1\n")
+        );
+        assert!(body.contains("blivedm_heartbeat_latency_seconds{room=\"metrics-test\"} 0.25\n"));
+    }
+
+    #[tokio::test]
+    async fn serves_room_state_over_http() {
+        let addr = spawn_server().await;
+        let room_id = "api-test";
+
+        let metrics = Arc::new(Mutex::new(MetricsEngine::new(Duration::from_secs(60))));
+        HUB.set_metrics(room_id, metrics.clone());
+
+        for text in ["first", "second"] {
+            let event = danmaku(room_id, text);
+
+            HUB.observe(room_id, Some(3), &event.message);
+            metrics.lock().unwrap().observe(&event.message);
+        }
+
+        let status: Value = reqwest::get(format!("http://{addr}/rooms/{room_id}"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(status["room_id"], room_id);
+        assert_eq!(status["session_id"], 3);
+        assert_eq!(status["last_message_at"], 1700000012345i64);
+
+        let danmaku: Value = reqwest::get(format!("http://{addr}/rooms/{room_id}/danmaku?limit=1"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            danmaku,
+            json!([{
+                "timestamp": 1700000012345i64,
+                "uid": 3141592653u64,
+                "uname": "anon_7f3a9c21",
+                "text": "second",
+            }])
+        );
+
+        // computed from the window as it is now rather than at the last snapshot
+        let stats: Value = reqwest::get(format!("http://{addr}/rooms/{room_id}/stats"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(stats["metrics"]["danmaku_rate"], 2.0);
+        assert_eq!(stats["metrics"]["unique_chatters"], 1);
+
+        let missing = reqwest::get(format!("http://{addr}/rooms/unwatched/danmaku"))
+            .await
+            .unwrap();
+
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn streams_subscribed_events_over_websocket() {
+        let addr = spawn_server().await;
+        let room_id = "ws-test";
+
+        let url =
+            format!("ws://{addr}/ws?room={room_id}&types=danmaku&filter=text%20~%20%22match%22");
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        let mut next_event = async || {
+            let message = time::timeout(Duration::from_millis(100), socket.next())
+                .await
+                .ok()?;
+            let json: Value = serde_json::from_str(message?.unwrap().to_text().unwrap()).unwrap();
+
+            Some(json)
+        };
+
+        // the server subscribes to the hub after the handshake, publish until it is listening
+        loop {
+            HUB.publish(room_id, &danmaku(room_id, "match ready"));
+
+            if next_event().await.is_some() {
+                break;
+            }
+        }
+
+        HUB.publish("other-room", &danmaku("other-room", "match elsewhere"));
+        HUB.publish(
+            room_id,
+            &event(room_id, json!({"cmd": "CUT_OFF", "msg": "match"})),
+        );
+        HUB.publish(room_id, &danmaku(room_id, "ignored"));
+        HUB.publish(room_id, &danmaku(room_id, "match done"));
+
+        loop {
+            let json = next_event().await.expect("no more events");
+
+            assert_eq!(json["type"], "danmaku");
+            assert_eq!(json["room_id"], room_id);
+
+            match json["text"].as_str().unwrap() {
+                "match ready" => (),
+                "match done" => break,
+                text => panic!("unexpected event {text}"),
+            }
+        }
+    }
+}
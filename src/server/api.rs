@@ -0,0 +1,111 @@
+use crate::data::database::{LivePersist, SessionRecord};
+use crate::data::search::{SearchHit, SearchIndex, SearchQuery};
+use crate::data::users::{NameHistory, UserPersist, UserProfile};
+use crate::server::hub::{DanmakuEntry, HUB, RoomStats, RoomStatus};
+use axum::Json;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use log::error;
+use serde::Deserialize;
+use tokio::task;
+
+const DEFAULT_DANMAKU_LIMIT: usize = 50;
+
+#[derive(Deserialize)]
+pub struct DanmakuQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct UserQuery {
+    q: String,
+}
+
+pub async fn rooms() -> Json<Vec<RoomStatus>> {
+    Json(HUB.rooms())
+}
+
+pub async fn room(Path(room_id): Path<String>) -> Result<Json<RoomStatus>, StatusCode> {
+    HUB.status(&room_id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn session(
+    Path(room_id): Path<String>,
+) -> Result<Json<Option<SessionRecord>>, StatusCode> {
+    if HUB.status(&room_id).is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    query(move || LivePersist::new(&LivePersist::path())?.current_session(&room_id))
+        .await
+        .map(Json)
+}
+
+pub async fn danmaku(
+    Path(room_id): Path<String>,
+    Query(query): Query<DanmakuQuery>,
+) -> Result<Json<Vec<DanmakuEntry>>, StatusCode> {
+    let limit = query.limit.unwrap_or(DEFAULT_DANMAKU_LIMIT);
+
+    HUB.recent_danmaku(&room_id, limit)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn user(Path(uid): Path<u64>) -> Result<Json<UserProfile>, StatusCode> {
+    query(move || UserPersist::new(&LivePersist::path())?.user(uid))
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn user_names(Path(uid): Path<u64>) -> Result<Json<NameHistory>, StatusCode> {
+    query(move || UserPersist::new(&LivePersist::path())?.name_history(uid))
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+// everyone who has ever been called `uname`, with all their names
+pub async fn names(Path(uname): Path<String>) -> Result<Json<Vec<NameHistory>>, StatusCode> {
+    query(move || UserPersist::new(&LivePersist::path())?.name_histories(&uname))
+        .await
+        .map(Json)
+}
+
+// `?q=` is a uid or a current or former name
+pub async fn users(Query(lookup): Query<UserQuery>) -> Result<Json<Vec<UserProfile>>, StatusCode> {
+    query(move || UserPersist::new(&LivePersist::path())?.lookup(&lookup.q))
+        .await
+        .map(Json)
+}
+
+// `?q=&room=&from=&to=&uid=&session=&limit=`, times in milliseconds
+pub async fn search(Query(search): Query<SearchQuery>) -> Result<Json<Vec<SearchHit>>, StatusCode> {
+    query(move || SearchIndex::new(&LivePersist::path())?.search(&search))
+        .await
+        .map(Json)
+}
+
+pub async fn stats(Path(room_id): Path<String>) -> Result<Json<RoomStats>, StatusCode> {
+    HUB.stats(&room_id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+// runs a database query off the async runtime
+async fn query<T, F>(f: F) -> Result<T, StatusCode>
+where
+    T: Send + 'static,
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(err)) => {
+            error!("failed to query database: {err:?}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        Err(err) => {
+            error!("failed to join database query: {err:?}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
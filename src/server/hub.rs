@@ -0,0 +1,187 @@
+use crate::live::message::LiveMessage;
+use crate::sink::SinkEvent;
+use crate::stats::metrics::{MetricsEngine, MetricsSnapshot};
+use crate::stats::revenue::RevenueReport;
+use log::error;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+const EVENT_CAPACITY: usize = 1024;
+const RECENT_DANMAKU: usize = 200;
+
+pub static HUB: Lazy<Hub> = Lazy::new(Hub::new);
+
+// A parsed message of one room, already encoded for subscribers
+#[derive(Debug)]
+pub struct LiveEvent {
+    pub room_id: String,
+    pub event: Arc<SinkEvent>,
+    pub json: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RoomStatus {
+    pub room_id: String,
+    pub account: Option<String>,      // 当前使用的账号
+    pub connected: bool,              // 是否已连接
+    pub session_id: Option<i64>,      // 当前场次
+    pub watched: Option<i64>,         // 看过人数
+    pub last_message_at: Option<i64>, // 最近一条消息的时间（毫秒）
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DanmakuEntry {
+    pub timestamp: i64, // 毫秒
+    pub uid: u64,
+    pub uname: String,
+    pub text: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RoomStats {
+    pub metrics: Option<MetricsSnapshot>, // 请求时的滑动窗口指标
+    pub revenue: Option<RevenueReport>,   // 当前场次收入
+}
+
+#[derive(Default)]
+struct RoomState {
+    status: RoomStatus,
+    danmaku: VecDeque<DanmakuEntry>,
+    metrics: Option<Arc<Mutex<MetricsEngine>>>, // 观察者的滑动窗口
+    revenue: Option<RevenueReport>,
+}
+
+// Latest state of every watched room, shared between the watchers and the HTTP server
+pub struct Hub {
+    rooms: Mutex<BTreeMap<String, RoomState>>,
+    events: broadcast::Sender<Arc<LiveEvent>>,
+}
+
+impl Hub {
+    fn new() -> Self {
+        Self {
+            rooms: Mutex::new(BTreeMap::new()),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    fn update<F: FnOnce(&mut RoomState)>(&self, room_id: &str, f: F) {
+        let mut rooms = self.rooms.lock().expect("failed to lock hub");
+        let state = rooms.entry(room_id.into()).or_default();
+
+        state.status.room_id = room_id.into();
+
+        f(state);
+    }
+
+    pub fn register(&self, room_id: &str) {
+        self.update(room_id, |_| ());
+    }
+
+    pub fn set_account(&self, room_id: &str, account: &str) {
+        self.update(room_id, |x| x.status.account = Some(account.into()));
+    }
+
+    pub fn set_connected(&self, room_id: &str, connected: bool) {
+        self.update(room_id, |x| x.status.connected = connected);
+    }
+
+    // the window of the room's watcher, read whenever its stats are asked for
+    pub fn set_metrics(&self, room_id: &str, metrics: Arc<Mutex<MetricsEngine>>) {
+        self.update(room_id, |x| x.metrics = Some(metrics));
+    }
+
+    pub fn set_revenue(&self, room_id: &str, report: RevenueReport) {
+        self.update(room_id, |x| x.revenue = Some(report));
+    }
+
+    pub fn observe(&self, room_id: &str, session_id: Option<i64>, message: &LiveMessage) {
+        let timestamp = message.timestamp().map(|ts| ts.timestamp_millis());
+
+        self.update(room_id, |x| {
+            x.status.session_id = session_id;
+            x.status.last_message_at = timestamp.or(x.status.last_message_at);
+
+            match message {
+                LiveMessage::WatchedChange { count, .. } => x.status.watched = Some(*count),
+                LiveMessage::Danmaku { user, text, .. } => {
+                    if x.danmaku.len() == RECENT_DANMAKU {
+                        x.danmaku.pop_front();
+                    }
+
+                    x.danmaku.push_back(DanmakuEntry {
+                        timestamp: timestamp.unwrap_or_default(),
+                        uid: user.uid(),
+                        uname: user.uname().into(),
+                        text: text.clone(),
+                    });
+                }
+                _ => (),
+            }
+        });
+    }
+
+    // pushes the message to the WebSocket subscribers, if any
+    pub fn publish(&self, room_id: &str, event: &Arc<SinkEvent>) {
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+
+        let message = &event.message;
+
+        let json = match message.to_json(room_id) {
+            Ok(Some(json)) => json.to_string(),
+            Ok(None) => return,
+            Err(err) => {
+                error!("failed to encode {} message: {err}", message.kind());
+                return;
+            }
+        };
+
+        let _ = self.events.send(Arc::new(LiveEvent {
+            room_id: room_id.into(),
+            event: event.clone(),
+            json,
+        }));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<LiveEvent>> {
+        self.events.subscribe()
+    }
+
+    pub fn rooms(&self) -> Vec<RoomStatus> {
+        let rooms = self.rooms.lock().expect("failed to lock hub");
+
+        rooms.values().map(|x| x.status.clone()).collect()
+    }
+
+    pub fn status(&self, room_id: &str) -> Option<RoomStatus> {
+        let rooms = self.rooms.lock().expect("failed to lock hub");
+
+        rooms.get(room_id).map(|x| x.status.clone())
+    }
+
+    // newest first
+    pub fn recent_danmaku(&self, room_id: &str, limit: usize) -> Option<Vec<DanmakuEntry>> {
+        let rooms = self.rooms.lock().expect("failed to lock hub");
+
+        rooms
+            .get(room_id)
+            .map(|x| x.danmaku.iter().rev().take(limit).cloned().collect())
+    }
+
+    pub fn stats(&self, room_id: &str) -> Option<RoomStats> {
+        let rooms = self.rooms.lock().expect("failed to lock hub");
+
+        rooms.get(room_id).map(|x| RoomStats {
+            metrics: x
+                .metrics
+                .as_ref()
+                .map(|x| x.lock().expect("failed to lock metrics").snapshot()),
+            revenue: x.revenue.clone(),
+        })
+    }
+}
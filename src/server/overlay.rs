@@ -0,0 +1,122 @@
+use crate::config::OverlayConfig;
+use axum::Router;
+use axum::http::{StatusCode, header};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use log::error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+
+const PAGE: &str = include_str!("overlay.html");
+
+// `/overlay/<room_id>` renders the overlay of one room, add it as a browser source in OBS
+pub fn router(config: &OverlayConfig) -> Router {
+    let page: Arc<str> = PAGE
+        .replace(
+            "/*CONFIG*/null",
+            &serde_json::to_string(config).expect("failed to encode overlay config"),
+        )
+        .into();
+
+    let css = config.css.clone();
+
+    Router::new()
+        .route("/overlay/theme.css", get(move || theme(css.clone())))
+        .route("/overlay/{room_id}", get(move || page_of(page.clone())))
+}
+
+async fn page_of(page: Arc<str>) -> Html<String> {
+    Html(page.to_string())
+}
+
+// read on every request, so the theme can be tweaked with a refresh of the browser source
+async fn theme(css: Option<PathBuf>) -> Response {
+    let content = match css {
+        Some(file) => match fs::read_to_string(&file).await {
+            Ok(content) => content,
+            Err(err) => {
+                error!("failed to read overlay theme {}: {err}", file.display());
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        },
+        None => String::new(),
+    };
+
+    ([(header::CONTENT_TYPE, "text/css; charset=utf-8")], content).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::CONTENT_TYPE;
+    use tokio::net::TcpListener;
+
+    async fn serve(config: &OverlayConfig) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = router(config);
+
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn serves_the_page_with_its_config() {
+        let config = OverlayConfig {
+            show: vec!["danmaku".into()],
+            max_danmaku: 5,
+            ..OverlayConfig::default()
+        };
+        let url = serve(&config).await;
+
+        let response = reqwest::get(format!("{url}/overlay/21452505"))
+            .await
+            .unwrap();
+
+        assert!(response.status().is_success());
+        assert!(
+            response.headers()[CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+
+        let page = response.text().await.unwrap();
+        let encoded = serde_json::to_string(&config).unwrap();
+
+        assert!(!page.contains("/*CONFIG*/"));
+        assert!(page.contains(&format!("const CONFIG = {encoded};")));
+        assert!(encoded.contains("\"show\":[\"danmaku\"]"));
+        assert!(encoded.contains("\"max_danmaku\":5"));
+    }
+
+    #[tokio::test]
+    async fn serves_the_configured_theme() {
+        let dir = tempfile::tempdir().unwrap();
+        let css = dir.path().join("theme.css");
+
+        std::fs::write(&css, ".danmaku { color: red; }").unwrap();
+
+        for (config, expected) in [
+            (
+                OverlayConfig {
+                    css: Some(css),
+                    ..OverlayConfig::default()
+                },
+                ".danmaku { color: red; }",
+            ),
+            (OverlayConfig::default(), ""),
+        ] {
+            let url = serve(&config).await;
+            let response = reqwest::get(format!("{url}/overlay/theme.css"))
+                .await
+                .unwrap();
+
+            assert!(response.status().is_success());
+            assert_eq!(response.headers()[CONTENT_TYPE], "text/css; charset=utf-8");
+            assert_eq!(response.text().await.unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::filter::Filter;
+use crate::server::hub::{HUB, LiveEvent};
+use axum::extract::Query;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::http::StatusCode;
+use axum::response::Response;
+use log::{debug, warn};
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+
+// `?room=<id>&types=danmaku,gift&filter=<expr>`, all optional
+#[derive(Deserialize)]
+pub struct Subscription {
+    room: Option<String>,
+    types: Option<String>,
+    filter: Option<String>,
+}
+
+struct Filters {
+    room: Option<String>,
+    types: Option<Vec<String>>,
+    filter: Option<Filter>,
+}
+
+impl Filters {
+    fn matches(&self, event: &LiveEvent) -> bool {
+        if let Some(room) = &self.room
+            && room != &event.room_id
+        {
+            return false;
+        }
+
+        let message = &event.event.message;
+
+        if let Some(types) = &self.types
+            && !types.iter().any(|x| x == message.kind())
+        {
+            return false;
+        }
+
+        match &self.filter {
+            Some(filter) => filter.matches(&event.room_id, message),
+            None => true,
+        }
+    }
+}
+
+pub async fn subscribe(
+    ws: WebSocketUpgrade,
+    Query(subscription): Query<Subscription>,
+) -> Result<Response, (StatusCode, String)> {
+    let filter = match &subscription.filter {
+        Some(filter) => Some(
+            Filter::parse(filter)
+                .map_err(|err| (StatusCode::BAD_REQUEST, format!("invalid filter: {err:#}")))?,
+        ),
+        None => None,
+    };
+
+    let filters = Filters {
+        room: subscription.room,
+        types: subscription
+            .types
+            .map(|types| types.split(',').map(|x| x.trim().to_owned()).collect()),
+        filter,
+    };
+
+    Ok(ws.on_upgrade(move |socket| stream(socket, filters)))
+}
+
+async fn stream(mut socket: WebSocket, filter: Filters) {
+    let mut events = HUB.subscribe();
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if filter.matches(&event) => {
+                    if socket.send(Message::Text(event.json.clone().into())).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => (),
+                Err(RecvError::Lagged(count)) => {
+                    warn!("websocket subscriber lagged behind, {count} events dropped");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => (),
+            },
+        }
+    }
+
+    debug!("websocket subscriber disconnected");
+}
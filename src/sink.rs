@@ -0,0 +1,74 @@
+pub mod alert;
+pub mod dispatcher;
+pub mod log;
+pub mod sqlite;
+pub mod users;
+pub mod webhook;
+
+use crate::live::message::{LiveMessage, RawMessage};
+use anyhow::Result;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task;
+
+// A parsed message together with the raw one it came from
+#[derive(Debug)]
+pub struct SinkEvent {
+    pub session_id: Option<i64>, // 所属场次
+    pub message: LiveMessage,
+    pub raw: RawMessage,
+}
+
+impl SinkEvent {
+    // the message in the WebSocket API shape plus its session, `None` for unsupported messages
+    pub fn to_json(&self) -> Result<Option<Value>> {
+        let json = self.message.to_json(self.raw.room_id())?;
+
+        Ok(json.map(|mut json| {
+            json["session_id"] = self.session_id.into();
+            json
+        }))
+    }
+}
+
+// Receives the events of one room from its own queue, see `Dispatcher`
+pub trait Sink: Send + 'static {
+    fn name(&self) -> &'static str;
+
+    fn handle(&mut self, event: &Arc<SinkEvent>) -> impl Future<Output = Result<()>> + Send;
+
+    // messages that failed to parse, only a raw copy is available
+    fn handle_unparsed(&mut self, _raw: &RawMessage) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    fn flush(&mut self) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    // archives keep what their full queue can not take in memory instead of dropping it
+    fn lossless(&self) -> bool {
+        false
+    }
+
+    // flushed this often on top of the periodic flush of the watcher
+    fn flush_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    // called once when the room is no longer watched, flushes by default
+    fn close(&mut self) -> impl Future<Output = Result<()>> + Send {
+        self.flush()
+    }
+}
+
+// runs blocking work such as database writes off the async runtime, so it only holds up its sink
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    task::spawn_blocking(f).await?
+}
@@ -0,0 +1,396 @@
+use crate::config::{AlertAction, AlertRule};
+use crate::live::message::{LiveMessage, UserInteractType};
+use crate::sink::{Sink, SinkEvent};
+use crate::stats::revenue::{RevenueReport, value_of};
+use anyhow::{Result, bail};
+use chrono::{Local, TimeZone};
+use log::{debug, error, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tokio::{task, time};
+
+const ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+const DEDUPE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub room_id: String,
+    pub session_id: Option<i64>,
+    pub fired_at: i64, // 毫秒
+    pub summary: String,
+    pub message: Option<Value>,
+}
+
+struct RuleState {
+    rule: AlertRule,
+    fired: VecDeque<i64>,    // 窗口内的告警时间
+    seen: HashMap<u64, i64>, // 去重键 -> 最近一次告警时间
+}
+
+impl RuleState {
+    // windows are timed by the message so a replay behaves like the live stream
+    fn admit(&mut self, message: &LiveMessage, now: i64) -> bool {
+        let dedupe = self
+            .rule
+            .dedupe
+            .map(|x| (dedupe_key(message), x as i64 * 1000));
+
+        if let Some((key, window)) = dedupe
+            && self.seen.get(&key).is_some_and(|last| now - last < window)
+        {
+            debug!("alert {} deduplicated", self.rule.name);
+            return false;
+        }
+
+        if let Some(limit) = &self.rule.rate_limit {
+            let window = limit.seconds as i64 * 1000;
+
+            while self.fired.front().is_some_and(|x| now - x >= window) {
+                self.fired.pop_front();
+            }
+
+            if self.fired.len() >= limit.count {
+                debug!("alert {} rate limited", self.rule.name);
+                return false;
+            }
+
+            self.fired.push_back(now);
+        }
+
+        // only alerts that fire hold back their duplicates
+        if let Some((key, _)) = dedupe {
+            if !self.seen.contains_key(&key)
+                && self.seen.len() >= DEDUPE_CAPACITY
+                && let Some(oldest) = self.seen.iter().min_by_key(|(_, last)| **last)
+            {
+                let oldest = *oldest.0;
+                self.seen.remove(&oldest);
+            }
+
+            self.seen.insert(key, now);
+        }
+
+        true
+    }
+}
+
+// Evaluates the alert rules of a room and runs their actions in the background
+pub struct AlertSink {
+    room_id: String,
+    rules: Vec<RuleState>,
+    client: reqwest::Client,
+    pending: Vec<JoinHandle<()>>, // 未完成的 webhook / 命令
+}
+
+impl AlertSink {
+    pub fn new(room_id: &str, rules: &[AlertRule]) -> Self {
+        Self {
+            room_id: room_id.into(),
+            rules: rules
+                .iter()
+                .map(|rule| RuleState {
+                    rule: rule.clone(),
+                    fired: VecDeque::new(),
+                    seen: HashMap::new(),
+                })
+                .collect(),
+            client: reqwest::Client::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    // the alerts the event raises once dedupe and rate limits are applied, with the actions
+    // of their rules
+    pub fn evaluate(&mut self, event: &SinkEvent) -> Result<Vec<(Arc<Alert>, Vec<AlertAction>)>> {
+        let message = &event.message;
+        let now = message
+            .timestamp()
+            .unwrap_or_else(Local::now)
+            .timestamp_millis();
+
+        let mut fired = Vec::new();
+
+        for state in &mut self.rules {
+            if !state.rule.when.matches(&self.room_id, message) || !state.admit(message, now) {
+                continue;
+            }
+
+            let alert = Arc::new(Alert {
+                rule: state.rule.name.clone(),
+                room_id: self.room_id.clone(),
+                session_id: event.session_id,
+                fired_at: now,
+                summary: summary(message),
+                message: message.to_json(&self.room_id)?,
+            });
+
+            fired.push((alert, state.rule.actions.clone()));
+        }
+
+        Ok(fired)
+    }
+
+    pub fn fire(&mut self, action: &AlertAction, alert: &Arc<Alert>) {
+        match action {
+            AlertAction::Stdout => print(alert, None),
+            AlertAction::Webhook { url } => {
+                let request = self.client.post(url).timeout(ACTION_TIMEOUT).json(&**alert);
+                let (url, rule) = (url.clone(), alert.rule.clone());
+
+                self.spawn(async move {
+                    match request.send().await.and_then(|x| x.error_for_status()) {
+                        Ok(_) => debug!("alert {rule} posted to {url}"),
+                        Err(err) => error!("failed to post alert {rule} to {url}: {err}"),
+                    }
+                });
+            }
+            AlertAction::Command { program, args } => {
+                let (program, args, alert) = (program.clone(), args.clone(), alert.clone());
+
+                self.spawn(async move {
+                    if let Err(err) = run_command(&program, &args, &alert).await {
+                        error!("alert {} command {program} failed: {err:?}", alert.rule);
+                    }
+                });
+            }
+        }
+    }
+
+    fn spawn<F: Future<Output = ()> + Send + 'static>(&mut self, action: F) {
+        self.pending.retain(|x| !x.is_finished());
+        self.pending.push(task::spawn(action));
+    }
+}
+
+impl Sink for AlertSink {
+    fn name(&self) -> &'static str {
+        "alerts"
+    }
+
+    async fn handle(&mut self, event: &Arc<SinkEvent>) -> Result<()> {
+        for (alert, actions) in self.evaluate(event)? {
+            for action in &actions {
+                self.fire(action, &alert);
+            }
+        }
+
+        Ok(())
+    }
+
+    // waits for the actions still running
+    async fn close(&mut self) -> Result<()> {
+        for action in self.pending.drain(..) {
+            let _ = action.await;
+        }
+
+        Ok(())
+    }
+}
+
+// what the action would have done, printed instead of doing it
+pub fn dry_run(action: &AlertAction, alert: &Alert) {
+    match action {
+        AlertAction::Stdout => print(alert, None),
+        AlertAction::Webhook { url } => print(alert, Some(&format!("would post to {url}"))),
+        AlertAction::Command { program, args } => {
+            let command = [program]
+                .into_iter()
+                .chain(args)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            print(alert, Some(&format!("would run {command}")))
+        }
+    }
+}
+
+fn print(alert: &Alert, note: Option<&str>) {
+    let fired_at = Local
+        .timestamp_millis_opt(alert.fired_at)
+        .single()
+        .unwrap_or_else(Local::now);
+
+    let note = note.map(|x| format!(" ({x})")).unwrap_or_default();
+
+    println!(
+        "[{}] {} @ {}: {}{note}",
+        fired_at.format("%Y-%m-%d %H:%M:%S"),
+        alert.rule,
+        alert.room_id,
+        alert.summary
+    );
+}
+
+// the alert as JSON on stdin, with the rule, room and summary also in the environment
+async fn run_command(program: &str, args: &[String], alert: &Alert) -> Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .env("ALERT_RULE", &alert.rule)
+        .env("ALERT_ROOM", &alert.room_id)
+        .env("ALERT_SUMMARY", &alert.summary)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(&serde_json::to_vec(alert)?).await?;
+    }
+
+    match time::timeout(ACTION_TIMEOUT, child.wait()).await {
+        Ok(status) => match status? {
+            status if status.success() => Ok(()),
+            status => bail!("exited with {status}"),
+        },
+        Err(_) => {
+            warn!("alert command {program} timed out, killing it");
+            child.kill().await?;
+            Ok(())
+        }
+    }
+}
+
+fn dedupe_key(message: &LiveMessage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    message.kind().hash(&mut hasher);
+    message.user().map(|x| x.uid()).hash(&mut hasher);
+
+    match message {
+        LiveMessage::Danmaku { text, .. } | LiveMessage::SuperChat { text, .. } => {
+            text.hash(&mut hasher)
+        }
+        LiveMessage::Gift { gift_name, .. } => gift_name.hash(&mut hasher),
+        LiveMessage::CutOff { reason, .. } => reason.hash(&mut hasher),
+        LiveMessage::Custom { cmd, data, .. } => (cmd, data.to_string()).hash(&mut hasher),
+        _ => (),
+    }
+
+    hasher.finish()
+}
+
+// one line a person can read in a notification
+fn summary(message: &LiveMessage) -> String {
+    let uname = message.user().map(|x| x.uname()).unwrap_or_default();
+
+    match message {
+        LiveMessage::StreamStart { .. } => "stream started".into(),
+        LiveMessage::SteamEnd { .. } => "stream ended".into(),
+        LiveMessage::CutOff { reason, .. } => format!("stream cut off: {reason}"),
+        LiveMessage::Danmaku { text, .. } => format!("{uname}: {text}"),
+        LiveMessage::SuperChat { price, text, .. } => format!("{uname} ¥{price}: {text}"),
+        LiveMessage::Gift {
+            gift_name,
+            gift_count,
+            ..
+        } => format!(
+            "{uname} sent {gift_name} x{gift_count} (¥{:.2})",
+            RevenueReport::yuan(value_of(message))
+        ),
+        LiveMessage::GuardBuy {
+            guard_name, count, ..
+        } => format!("{uname} bought {guard_name} x{count}"),
+        LiveMessage::UserInteract { msg_type, .. } => match msg_type {
+            UserInteractType::JoinRoom => format!("{uname} joined"),
+            UserInteractType::Subscribe => format!("{uname} followed"),
+            UserInteractType::Share => format!("{uname} shared the room"),
+        },
+        LiveMessage::Like { .. } => format!("{uname} liked"),
+        LiveMessage::WatchedChange { count, .. } => format!("{count} watched"),
+        LiveMessage::BattleInfo { .. } => "pk battle update".into(),
+        LiveMessage::Custom { cmd, data, .. } => format!("{cmd} {uname}: {data}"),
+        LiveMessage::Unsupported(cmd) => cmd.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateLimit;
+    use crate::filter::Filter;
+    use crate::live::message::RawMessage;
+    use serde_json::json;
+
+    fn cut_off(reason: &str) -> LiveMessage {
+        LiveMessage::try_from(&RawMessage::new(
+            "1",
+            json!({"cmd": "CUT_OFF", "msg": reason}),
+        ))
+        .unwrap()
+    }
+
+    fn state(rate_limit: Option<RateLimit>, dedupe: Option<u64>) -> RuleState {
+        RuleState {
+            rule: AlertRule {
+                name: "test".into(),
+                when: Filter::parse("type == cut_off").unwrap(),
+                actions: Vec::new(),
+                rate_limit,
+                dedupe,
+            },
+            fired: VecDeque::new(),
+            seen: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn dedupes_and_rate_limits() {
+        let (a, b) = (cut_off("a"), cut_off("b"));
+
+        let mut state = self::state(None, Some(10));
+        assert!(state.admit(&a, 0));
+        assert!(!state.admit(&a, 9_999));
+        assert!(state.admit(&b, 9_999));
+        assert!(state.admit(&a, 10_000));
+
+        let mut state = self::state(
+            Some(RateLimit {
+                count: 2,
+                seconds: 60,
+            }),
+            None,
+        );
+        assert!(state.admit(&a, 0));
+        assert!(state.admit(&a, 1_000));
+        assert!(!state.admit(&b, 59_999));
+        assert!(state.admit(&b, 60_000));
+
+        // a rate limited alert did not fire, so it does not hold back the next one
+        let mut state = self::state(
+            Some(RateLimit {
+                count: 1,
+                seconds: 60,
+            }),
+            Some(600),
+        );
+        assert!(state.admit(&a, 0));
+        assert!(!state.admit(&b, 1_000));
+        assert!(state.admit(&b, 60_000));
+        assert!(!state.admit(&a, 120_000));
+    }
+
+    #[test]
+    fn evicts_the_oldest_dedupe_key_at_capacity() {
+        let mut state = self::state(None, Some(600));
+
+        for i in 0..=DEDUPE_CAPACITY {
+            assert!(state.admit(&cut_off(&i.to_string()), i as i64));
+        }
+
+        // the first key made room for the last one, the others are still held back
+        assert_eq!(state.seen.len(), DEDUPE_CAPACITY);
+        assert!(state.admit(&cut_off("0"), 10_000));
+        assert!(!state.admit(&cut_off("2"), 10_000));
+    }
+}
@@ -0,0 +1,405 @@
+use crate::config::{SinkConfig, SinkKind};
+use crate::filter::Filter;
+use crate::live::message::RawMessage;
+use crate::sink::alert::AlertSink;
+use crate::sink::log::{NormalizedLogSink, RawLogSink};
+use crate::sink::sqlite::SqliteSink;
+use crate::sink::users::UsersSink;
+use crate::sink::webhook::WebhookSink;
+use crate::sink::{Sink, SinkEvent};
+use crate::stats::exporter::EXPORTER;
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio::{task, time};
+
+enum Command {
+    Event(Arc<SinkEvent>),
+    Unparsed(Arc<RawMessage>),
+    Flush,
+}
+
+struct SinkHandle {
+    name: &'static str,
+    filter: Option<Filter>,
+    tx: mpsc::Sender<Command>,
+    task: JoinHandle<()>,
+    backlog: Option<VecDeque<Command>>, // 无损 sink 的队列满了以后暂存的事件
+    dropping: bool,                     // 队列已满，正在丢弃或暂存事件
+}
+
+// Fans the events of one room out to its sinks. Each sink runs in its own task behind a
+// bounded queue and never blocks the room. Events for a best-effort sink that can not keep
+// up are dropped, lossless sinks spill them into a backlog that is fed in as the queue frees.
+pub struct Dispatcher {
+    room_id: String,
+    sinks: Vec<SinkHandle>,
+}
+
+impl Dispatcher {
+    pub fn new(room_id: &str) -> Self {
+        Self {
+            room_id: room_id.into(),
+            sinks: Vec::new(),
+        }
+    }
+
+    pub fn from_config(room_id: &str, configs: &[SinkConfig]) -> Result<Self> {
+        let mut dispatcher = Self::new(room_id);
+
+        for config in configs {
+            let (queue, filter) = (config.queue, config.filter.clone());
+
+            match &config.kind {
+                SinkKind::RawLog => dispatcher.add(RawLogSink::new(room_id), queue, filter),
+                SinkKind::NormalizedLog => {
+                    dispatcher.add(NormalizedLogSink::new(room_id), queue, filter)
+                }
+                SinkKind::Sqlite => dispatcher.add(SqliteSink::new(room_id)?, queue, filter),
+                SinkKind::Users => dispatcher.add(UsersSink::new(room_id)?, queue, filter),
+                SinkKind::Webhook(webhook) => {
+                    dispatcher.add(WebhookSink::new(room_id, webhook), queue, filter)
+                }
+                SinkKind::Alerts { rules } => {
+                    dispatcher.add(AlertSink::new(room_id, rules), queue, filter)
+                }
+            }
+        }
+
+        Ok(dispatcher)
+    }
+
+    // a sink with a filter only receives the events it matches and no unparsed messages
+    pub fn add<S: Sink>(&mut self, sink: S, capacity: usize, filter: Option<Filter>) {
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+        let name = sink.name();
+        let backlog = sink.lossless().then(VecDeque::new);
+
+        self.sinks.push(SinkHandle {
+            name,
+            filter,
+            tx,
+            task: task::spawn(run(self.room_id.clone(), sink, rx)),
+            backlog,
+            dropping: false,
+        });
+    }
+
+    pub fn dispatch(&mut self, event: Arc<SinkEvent>) {
+        let room_id = &self.room_id;
+
+        Self::send(room_id, &mut self.sinks, |sink| match &sink.filter {
+            Some(filter) if !filter.matches(room_id, &event.message) => None,
+            _ => Some(Command::Event(event.clone())),
+        });
+    }
+
+    pub fn dispatch_unparsed(&mut self, raw: RawMessage) {
+        let raw = Arc::new(raw);
+
+        Self::send(&self.room_id, &mut self.sinks, |sink| match sink.filter {
+            Some(_) => None,
+            None => Some(Command::Unparsed(raw.clone())),
+        });
+    }
+
+    // asks every sink to flush, skipped for sinks whose queue is full
+    pub fn flush(&mut self) {
+        for sink in &mut self.sinks {
+            if sink.feed_backlog(&self.room_id) {
+                let _ = sink.tx.try_send(Command::Flush);
+            }
+        }
+    }
+
+    // waits until every sink has taken its backlog, drained its queue and closed
+    pub async fn close(self) {
+        for sink in self.sinks {
+            for command in sink.backlog.into_iter().flatten() {
+                if sink.tx.send(command).await.is_err() {
+                    EXPORTER.inc_sink_dropped(&self.room_id, sink.name);
+                }
+            }
+
+            drop(sink.tx);
+
+            if let Err(err) = sink.task.await {
+                error!(
+                    "[{}] failed to join sink {}: {err:?}",
+                    self.room_id, sink.name
+                );
+            }
+        }
+    }
+
+    fn send<F: Fn(&SinkHandle) -> Option<Command>>(
+        room_id: &str,
+        sinks: &mut [SinkHandle],
+        command: F,
+    ) {
+        for sink in sinks {
+            let Some(command) = command(sink) else {
+                continue;
+            };
+
+            // queued behind the backlog so the sink still sees events in order
+            let result = if sink.feed_backlog(room_id) {
+                sink.tx.try_send(command)
+            } else {
+                Err(TrySendError::Full(command))
+            };
+
+            match result {
+                Ok(()) => {
+                    if sink.dropping {
+                        sink.dropping = false;
+                        info!("[{room_id}] sink {} caught up", sink.name);
+                    }
+                }
+                Err(TrySendError::Full(command)) => {
+                    if !sink.dropping {
+                        sink.dropping = true;
+                        warn!(
+                            "[{room_id}] sink {} is falling behind, {} events",
+                            sink.name,
+                            match sink.backlog {
+                                Some(_) => "holding back",
+                                None => "dropping",
+                            }
+                        );
+                    }
+
+                    match &mut sink.backlog {
+                        Some(backlog) => backlog.push_back(command),
+                        None => EXPORTER.inc_sink_dropped(room_id, sink.name),
+                    }
+                }
+                Err(TrySendError::Closed(_)) => {
+                    EXPORTER.inc_sink_dropped(room_id, sink.name);
+                }
+            }
+        }
+    }
+}
+
+impl SinkHandle {
+    // moves held back events into the queue while it has room, true once the backlog is empty
+    fn feed_backlog(&mut self, room_id: &str) -> bool {
+        let Some(backlog) = &mut self.backlog else {
+            return true;
+        };
+
+        while let Some(command) = backlog.pop_front() {
+            match self.tx.try_send(command) {
+                Ok(()) => {}
+                Err(TrySendError::Full(command)) => {
+                    backlog.push_front(command);
+                    return false;
+                }
+                Err(TrySendError::Closed(_)) => EXPORTER.inc_sink_dropped(room_id, self.name),
+            }
+        }
+
+        true
+    }
+}
+
+async fn run<S: Sink>(room_id: String, mut sink: S, mut rx: mpsc::Receiver<Command>) {
+    let name = sink.name();
+
+    let mut ticker = sink
+        .flush_interval()
+        .map(|period| time::interval_at(Instant::now() + period, period));
+
+    loop {
+        let command = match &mut ticker {
+            Some(ticker) => tokio::select! {
+                command = rx.recv() => command,
+                _ = ticker.tick() => Some(Command::Flush),
+            },
+            None => rx.recv().await,
+        };
+
+        let Some(command) = command else {
+            break;
+        };
+
+        let result = match command {
+            Command::Event(event) => sink.handle(&event).await,
+            Command::Unparsed(raw) => sink.handle_unparsed(&raw).await,
+            Command::Flush => sink.flush().await,
+        };
+
+        if let Err(err) = result {
+            error!("[{room_id}] sink {name} failed: {err:?}");
+        }
+    }
+
+    if let Err(err) = sink.close().await {
+        error!("[{room_id}] failed to close sink {name}: {err:?}");
+    }
+
+    debug!("[{room_id}] sink {name} closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::message::LiveMessage;
+    use serde_json::json;
+    use std::future;
+    use std::time::Duration;
+    use tokio::sync::mpsc::UnboundedSender;
+
+    const ROOM_ID: &str = "dispatcher-test";
+
+    // takes the first event and then never finishes handling it
+    struct StuckSink;
+
+    impl Sink for StuckSink {
+        fn name(&self) -> &'static str {
+            "stuck"
+        }
+
+        async fn handle(&mut self, _event: &Arc<SinkEvent>) -> Result<()> {
+            future::pending().await
+        }
+    }
+
+    struct RecordingSink(&'static str, UnboundedSender<&'static str>);
+
+    impl Sink for RecordingSink {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        async fn handle(&mut self, event: &Arc<SinkEvent>) -> Result<()> {
+            self.1.send(event.message.kind())?;
+            Ok(())
+        }
+    }
+
+    // records what it gets like an archive, which must not lose events to a full queue
+    struct ArchiveSink(UnboundedSender<Arc<SinkEvent>>);
+
+    impl Sink for ArchiveSink {
+        fn name(&self) -> &'static str {
+            "archive"
+        }
+
+        async fn handle(&mut self, event: &Arc<SinkEvent>) -> Result<()> {
+            self.0.send(event.clone())?;
+            Ok(())
+        }
+
+        fn lossless(&self) -> bool {
+            true
+        }
+    }
+
+    fn event(data: serde_json::Value) -> Arc<SinkEvent> {
+        let raw = RawMessage::new(ROOM_ID, data);
+
+        Arc::new(SinkEvent {
+            session_id: None,
+            message: LiveMessage::try_from(&raw).unwrap(),
+            raw,
+        })
+    }
+
+    #[tokio::test]
+    async fn drops_events_for_a_stuck_sink_only() {
+        let (all_tx, mut all_rx) = mpsc::unbounded_channel();
+        let (filtered_tx, mut filtered_rx) = mpsc::unbounded_channel();
+
+        let mut dispatcher = Dispatcher::new(ROOM_ID);
+        dispatcher.add(StuckSink, 2, None);
+        dispatcher.add(RecordingSink("all", all_tx), 16, None);
+        dispatcher.add(
+            RecordingSink("filtered", filtered_tx),
+            16,
+            Some(Filter::parse("type == watched_change").unwrap()),
+        );
+
+        let events: Vec<_> = (0..10)
+            .map(|i| match i % 2 {
+                0 => event(json!({"cmd": "CUT_OFF", "msg": "", "roomid": 1})),
+                _ => event(json!({"cmd": "WATCHED_CHANGE", "data": {"num": i}})),
+            })
+            .collect();
+
+        // the sink tasks do not run until the test yields, so the stuck sink takes nothing
+        // off its queue: two events fit and the other eight are dropped
+        let start = Instant::now();
+
+        for event in events {
+            dispatcher.dispatch(event);
+        }
+
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert!(EXPORTER.render().contains(&format!(
+            "blivedm_sink_dropped_total{{room=\"{ROOM_ID}\",sink=\"stuck\"}} 8\n"
+        )));
+
+        let mut all = Vec::new();
+        let mut filtered = Vec::new();
+
+        for _ in 0..10 {
+            all.push(
+                time::timeout(Duration::from_secs(1), all_rx.recv())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        for _ in 0..5 {
+            filtered.push(
+                time::timeout(Duration::from_secs(1), filtered_rx.recv())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        assert_eq!(all.iter().filter(|x| **x == Some("cut_off")).count(), 5);
+        assert_eq!(
+            all.iter().filter(|x| **x == Some("watched_change")).count(),
+            5
+        );
+        assert!(filtered.iter().all(|x| *x == Some("watched_change")));
+        assert!(filtered_rx.try_recv().is_err());
+        assert!(!EXPORTER.render().contains("sink=\"all\""));
+    }
+
+    #[tokio::test]
+    async fn holds_back_events_for_a_lossless_sink_in_order() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let mut dispatcher = Dispatcher::new(ROOM_ID);
+        dispatcher.add(ArchiveSink(tx), 2, None);
+
+        let events: Vec<_> = (0..10)
+            .map(|i| event(json!({"cmd": "WATCHED_CHANGE", "data": {"num": i}})))
+            .collect();
+
+        // as above only two fit into the queue, the rest wait in the backlog
+        for event in &events {
+            dispatcher.dispatch(event.clone());
+        }
+
+        assert_eq!(dispatcher.sinks[0].backlog.as_ref().unwrap().len(), 8);
+
+        dispatcher.close().await;
+
+        for event in &events {
+            assert!(Arc::ptr_eq(&rx.recv().await.unwrap(), event));
+        }
+
+        assert!(rx.recv().await.is_none());
+        assert!(!EXPORTER.render().contains("sink=\"archive\""));
+    }
+}
@@ -0,0 +1,66 @@
+use crate::data::logger::MessageLogger;
+use crate::live::message::RawMessage;
+use crate::sink::{Sink, SinkEvent};
+use anyhow::Result;
+use std::sync::Arc;
+
+// Every message as received, including those that failed to parse
+pub struct RawLogSink {
+    logger: MessageLogger,
+}
+
+impl RawLogSink {
+    pub fn new(room_id: &str) -> Self {
+        Self {
+            logger: MessageLogger::new(room_id, "raw.jsonl"),
+        }
+    }
+}
+
+impl Sink for RawLogSink {
+    fn name(&self) -> &'static str {
+        "raw_log"
+    }
+
+    fn lossless(&self) -> bool {
+        true
+    }
+
+    async fn handle(&mut self, event: &Arc<SinkEvent>) -> Result<()> {
+        self.logger.write(event.raw.data())
+    }
+
+    async fn handle_unparsed(&mut self, raw: &RawMessage) -> Result<()> {
+        self.logger.write(raw.data())
+    }
+}
+
+// Parsed messages in the same shape as the WebSocket API, unsupported ones are skipped
+pub struct NormalizedLogSink {
+    logger: MessageLogger,
+}
+
+impl NormalizedLogSink {
+    pub fn new(room_id: &str) -> Self {
+        Self {
+            logger: MessageLogger::new(room_id, "normalized.jsonl"),
+        }
+    }
+}
+
+impl Sink for NormalizedLogSink {
+    fn name(&self) -> &'static str {
+        "normalized_log"
+    }
+
+    fn lossless(&self) -> bool {
+        true
+    }
+
+    async fn handle(&mut self, event: &Arc<SinkEvent>) -> Result<()> {
+        match event.to_json()? {
+            Some(json) => self.logger.write(&json),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use crate::data::database::LivePersist;
+use crate::data::search::SearchIndex;
+use crate::live::message::LiveMessage;
+use crate::sink::{Sink, SinkEvent, blocking};
+use anyhow::Result;
+use chrono::Local;
+use std::sync::{Arc, Mutex};
+
+// Stores parsed messages in the `events` table, which session reports are rebuilt from,
+// and indexes their text for search
+pub struct SqliteSink {
+    room_id: String,
+    db: Arc<Mutex<(LivePersist, SearchIndex)>>,
+}
+
+impl SqliteSink {
+    pub fn new(room_id: &str) -> Result<Self> {
+        Ok(Self {
+            room_id: room_id.into(),
+            db: Arc::new(Mutex::new((
+                LivePersist::new(&LivePersist::path())?,
+                SearchIndex::new(&LivePersist::path())?,
+            ))),
+        })
+    }
+}
+
+impl Sink for SqliteSink {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn lossless(&self) -> bool {
+        true
+    }
+
+    async fn handle(&mut self, event: &Arc<SinkEvent>) -> Result<()> {
+        if let LiveMessage::Unsupported(_) = event.message {
+            return Ok(());
+        }
+
+        let timestamp = event.message.timestamp().unwrap_or_else(Local::now);
+        let (room_id, event, db) = (self.room_id.clone(), event.clone(), self.db.clone());
+
+        blocking(move || {
+            let (persist, search) = &*db.lock().expect("failed to lock database");
+
+            let id = persist.insert_event(
+                &room_id,
+                event.session_id,
+                timestamp.timestamp_millis(),
+                event.message.kind(),
+                event.raw.data(),
+            )?;
+
+            search.index(id, &event.message)
+        })
+        .await
+    }
+}
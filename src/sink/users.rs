@@ -0,0 +1,45 @@
+use crate::data::database::LivePersist;
+use crate::data::users::UserPersist;
+use crate::sink::{Sink, SinkEvent, blocking};
+use anyhow::Result;
+use chrono::Local;
+use std::sync::{Arc, Mutex};
+
+// Merges the user of every event into the profiles in `live.db`
+pub struct UsersSink {
+    room_id: String,
+    persist: Arc<Mutex<UserPersist>>,
+}
+
+impl UsersSink {
+    pub fn new(room_id: &str) -> Result<Self> {
+        Ok(Self {
+            room_id: room_id.into(),
+            persist: Arc::new(Mutex::new(UserPersist::new(&LivePersist::path())?)),
+        })
+    }
+}
+
+impl Sink for UsersSink {
+    fn name(&self) -> &'static str {
+        "users"
+    }
+
+    fn lossless(&self) -> bool {
+        true
+    }
+
+    async fn handle(&mut self, event: &Arc<SinkEvent>) -> Result<()> {
+        let timestamp = event.message.timestamp().unwrap_or_else(Local::now);
+        let (room_id, event, persist) = (self.room_id.clone(), event.clone(), self.persist.clone());
+
+        blocking(move || {
+            persist.lock().expect("failed to lock database").observe(
+                &room_id,
+                timestamp.timestamp_millis(),
+                &event.message,
+            )
+        })
+        .await
+    }
+}
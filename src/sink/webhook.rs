@@ -0,0 +1,431 @@
+use crate::config::WebhookConfig;
+use crate::data::PROJECT_DIRS;
+use crate::live::credential::Secret;
+use crate::sink::{Sink, SinkEvent};
+use anyhow::Result;
+use chrono::Local;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use reqwest::StatusCode;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::fmt::Write as _;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+
+pub const SIGNATURE_HEADER: &str = "X-Blivedm-Signature-256";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize)]
+struct DeadLetter {
+    url: String,
+    failed_at: i64, // 毫秒
+    error: String,
+    events: Value,
+}
+
+enum Failure {
+    Retry(String), // 网络错误、5xx、429
+    Fatal(String), // 其余 4xx，重试也不会成功
+}
+
+// POSTs batches of events as a JSON array to every configured URL. A batch that still
+// fails after the retries is appended to a dead letter file, which is tried again once
+// on the next start. Retries hold up the sink, so its queue may overflow meanwhile.
+pub struct WebhookSink {
+    config: WebhookConfig,
+    client: reqwest::Client,
+    batch: Vec<Value>,
+    dead_letters: PathBuf,
+    retry_delay: Duration,
+    redeliver: bool, // 首次 flush 时重投死信
+}
+
+impl WebhookSink {
+    pub fn new(room_id: &str, config: &WebhookConfig) -> Self {
+        let dead_letters = PROJECT_DIRS
+            .data_dir()
+            .join(room_id)
+            .join("webhook-dead-letters.jsonl");
+
+        Self::with_dead_letters(config, dead_letters)
+    }
+
+    fn with_dead_letters(config: &WebhookConfig, dead_letters: PathBuf) -> Self {
+        Self {
+            config: config.clone(),
+            client: reqwest::Client::new(),
+            batch: Vec::new(),
+            dead_letters,
+            retry_delay: RETRY_DELAY,
+            redeliver: true,
+        }
+    }
+
+    async fn deliver_batch(&mut self) -> Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        let events = Value::Array(mem::take(&mut self.batch));
+        let body = events.to_string();
+
+        for url in &self.config.urls {
+            if let Err(err) = self.deliver(url, &body).await {
+                error!("webhook delivery to {url} failed, moved to dead letters: {err}");
+
+                // the other URLs still get the batch
+                if let Err(err) = self.dead_letter(url, &err, &events) {
+                    error!("failed to write a dead letter for {url}, batch lost: {err:?}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // retries with exponential backoff
+    async fn deliver(&self, url: &str, body: &str) -> Result<(), String> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+
+        loop {
+            match self.post(url, body).await {
+                Ok(()) => return Ok(()),
+                Err(Failure::Fatal(err)) => return Err(err),
+                Err(Failure::Retry(err)) if attempt >= self.config.retries => return Err(err),
+                Err(Failure::Retry(err)) => {
+                    attempt += 1;
+                    warn!("webhook delivery to {url} failed ({err}), retry {attempt} in {delay:?}");
+
+                    time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+    }
+
+    async fn post(&self, url: &str, body: &str) -> Result<(), Failure> {
+        let mut request = self
+            .client
+            .post(url)
+            .timeout(REQUEST_TIMEOUT)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_owned());
+
+        if let Some(secret) = &self.config.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, body.as_bytes()));
+        }
+
+        let status = match request.send().await {
+            Ok(response) => response.status(),
+            Err(err) => return Err(Failure::Retry(err.to_string())),
+        };
+
+        if status.is_success() {
+            Ok(())
+        } else if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            Err(Failure::Retry(status.to_string()))
+        } else {
+            Err(Failure::Fatal(status.to_string()))
+        }
+    }
+
+    fn dead_letter(&self, url: &str, error: &str, events: &Value) -> Result<()> {
+        if let Some(parent) = self.dead_letters.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let letter = DeadLetter {
+            url: url.into(),
+            failed_at: Local::now().timestamp_millis(),
+            error: error.into(),
+            events: events.clone(),
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.dead_letters)?;
+
+        writeln!(file, "{}", serde_json::to_string(&letter)?)?;
+
+        Ok(())
+    }
+
+    // tries every dead letter once, the ones that still fail are kept unless the receiver
+    // rejected them outright
+    async fn redeliver_dead_letters(&self) -> Result<()> {
+        let content = match fs::read_to_string(&self.dead_letters) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut remaining = Vec::new();
+        let mut delivered = 0;
+
+        for line in content.lines().filter(|x| !x.trim().is_empty()) {
+            let letter: DeadLetter = match serde_json::from_str(line) {
+                Ok(letter) => letter,
+                Err(err) => {
+                    warn!("keeping malformed dead letter: {err}");
+                    remaining.push(line);
+                    continue;
+                }
+            };
+
+            match self.post(&letter.url, &letter.events.to_string()).await {
+                Ok(()) => delivered += 1,
+                Err(Failure::Fatal(err)) => {
+                    error!("dropping dead letter rejected by {}: {err}", letter.url)
+                }
+                Err(Failure::Retry(_)) => remaining.push(line),
+            }
+        }
+
+        if remaining.is_empty() {
+            fs::remove_file(&self.dead_letters)?;
+        } else {
+            fs::write(&self.dead_letters, remaining.join("\n") + "\n")?;
+        }
+
+        if delivered > 0 {
+            info!(
+                "redelivered {delivered} dead letters, {} left in {}",
+                remaining.len(),
+                self.dead_letters.display()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Sink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn handle(&mut self, event: &Arc<SinkEvent>) -> Result<()> {
+        if let Some(json) = event.to_json()? {
+            self.batch.push(json);
+        }
+
+        if self.batch.len() >= self.config.batch_size {
+            self.deliver_batch().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if mem::take(&mut self.redeliver) {
+            self.redeliver_dead_letters().await?;
+        }
+
+        self.deliver_batch().await
+    }
+
+    fn flush_interval(&self) -> Option<Duration> {
+        Some(Duration::from_millis(self.config.batch_interval))
+    }
+}
+
+// `sha256=<hex>` of the request body, in the style of GitHub webhooks
+pub fn sign(secret: &Secret, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose().as_bytes())
+        .expect("hmac accepts keys of any length");
+
+    mac.update(body);
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .fold(String::from("sha256="), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::message::{LiveMessage, RawMessage};
+    use axum::Router;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use serde_json::json;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::net::TcpListener;
+
+    type Requests = Arc<Mutex<Vec<(Option<String>, String)>>>; // (签名, 请求体)
+
+    #[derive(Clone, Default)]
+    struct StandIn {
+        healthy: Arc<AtomicBool>,
+        rejecting: Arc<AtomicBool>,
+        received: Requests,
+        attempts: Arc<Mutex<usize>>,
+    }
+
+    // a local HTTP server taking the place of the webhook receiver
+    async fn stand_in() -> (String, StandIn) {
+        let state = StandIn::default();
+        let handler = state.clone();
+
+        let app = Router::new().route(
+            "/",
+            post(move |headers: HeaderMap, body: String| async move {
+                *handler.attempts.lock().unwrap() += 1;
+
+                if handler.rejecting.load(Ordering::SeqCst) {
+                    return StatusCode::BAD_REQUEST;
+                }
+
+                if !handler.healthy.load(Ordering::SeqCst) {
+                    return StatusCode::SERVICE_UNAVAILABLE;
+                }
+
+                let signature = headers
+                    .get(SIGNATURE_HEADER)
+                    .map(|x| x.to_str().unwrap().to_owned());
+
+                handler.received.lock().unwrap().push((signature, body));
+                StatusCode::OK
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (url, state)
+    }
+
+    fn event(reason: &str) -> Arc<SinkEvent> {
+        let raw = RawMessage::new("1", json!({"cmd": "CUT_OFF", "msg": reason}));
+
+        Arc::new(SinkEvent {
+            session_id: Some(7),
+            message: LiveMessage::try_from(&raw).unwrap(),
+            raw,
+        })
+    }
+
+    fn config(url: &str) -> WebhookConfig {
+        WebhookConfig {
+            urls: vec![url.into()],
+            secret: Some(Secret::new("hunter2")),
+            batch_size: 2,
+            batch_interval: 1000,
+            retries: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_batches_and_dead_letters() {
+        let (url, stand_in) = stand_in().await;
+        let dir = tempfile::tempdir().unwrap();
+        let dead_letters = dir.path().join("dead-letters.jsonl");
+
+        let mut sink = WebhookSink::with_dead_letters(&config(&url), dead_letters.clone());
+        sink.retry_delay = Duration::from_millis(10);
+
+        // receiver down: three attempts, then the batch becomes a dead letter
+        sink.handle(&event("a")).await.unwrap();
+        sink.handle(&event("b")).await.unwrap();
+
+        assert_eq!(*stand_in.attempts.lock().unwrap(), 3);
+        assert_eq!(
+            fs::read_to_string(&dead_letters).unwrap().lines().count(),
+            1
+        );
+
+        // receiver back: the dead letter goes out on the first flush, with the pending batch
+        stand_in.healthy.store(true, Ordering::SeqCst);
+        sink.handle(&event("c")).await.unwrap();
+        sink.flush().await.unwrap();
+
+        assert!(!dead_letters.exists());
+
+        let received = stand_in.received.lock().unwrap();
+        let secret = Secret::new("hunter2");
+
+        assert_eq!(received.len(), 2);
+
+        for (signature, body) in received.iter() {
+            assert_eq!(
+                signature.as_deref(),
+                Some(sign(&secret, body.as_bytes()).as_str())
+            );
+        }
+
+        let first: Value = serde_json::from_str(&received[0].1).unwrap();
+        let second: Value = serde_json::from_str(&received[1].1).unwrap();
+
+        assert_eq!(first[0]["reason"], "a");
+        assert_eq!(first[1]["reason"], "b");
+        assert_eq!(first[0]["session_id"], 7);
+        assert_eq!(second[0]["reason"], "c");
+    }
+
+    #[tokio::test]
+    async fn keeps_delivering_without_dead_letters_and_drops_rejected_ones() {
+        let (good, healthy) = stand_in().await;
+        let (bad, rejecting) = stand_in().await;
+        let dir = tempfile::tempdir().unwrap();
+
+        healthy.healthy.store(true, Ordering::SeqCst);
+        rejecting.rejecting.store(true, Ordering::SeqCst);
+
+        // the dead letter file can not be created under a regular file
+        fs::write(dir.path().join("file"), "").unwrap();
+
+        let mut config = config(&bad);
+        config.urls.push(good.clone());
+
+        let mut sink =
+            WebhookSink::with_dead_letters(&config, dir.path().join("file/dead-letters.jsonl"));
+
+        sink.handle(&event("a")).await.unwrap();
+        sink.handle(&event("b")).await.unwrap();
+
+        assert_eq!(*rejecting.attempts.lock().unwrap(), 1);
+        assert_eq!(healthy.received.lock().unwrap().len(), 1);
+
+        // a rejected dead letter is dropped instead of being tried on every start
+        let dead_letters = dir.path().join("dead-letters.jsonl");
+        let letter = DeadLetter {
+            url: bad,
+            failed_at: 0,
+            error: "400 Bad Request".into(),
+            events: json!([{"reason": "a"}]),
+        };
+
+        fs::write(
+            &dead_letters,
+            serde_json::to_string(&letter).unwrap() + "\n",
+        )
+        .unwrap();
+
+        let mut sink = WebhookSink::with_dead_letters(&config, dead_letters.clone());
+        sink.flush().await.unwrap();
+
+        assert_eq!(*rejecting.attempts.lock().unwrap(), 2);
+        assert!(!dead_letters.exists());
+    }
+}
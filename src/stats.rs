@@ -0,0 +1,3 @@
+pub mod exporter;
+pub mod metrics;
+pub mod revenue;
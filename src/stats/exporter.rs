@@ -0,0 +1,294 @@
+use crate::stats::metrics::MetricsEngine;
+use crate::stats::revenue::RevenueReport;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub static EXPORTER: Lazy<Exporter> = Lazy::new(Exporter::default);
+
+#[derive(Default)]
+struct RoomMetrics {
+    messages: BTreeMap<String, u64>,       // 按 cmd 统计的消息数
+    parse_failures: BTreeMap<String, u64>, // 按 cmd 统计的解析失败数
+    parse_warnings: BTreeMap<(String, &'static str), u64>, // 按 cmd、字段统计的降级解析数
+    reconnects: u64,                       // 重连次数
+    heartbeat_latency: f64,                // 最近一次心跳的往返耗时（秒）
+    watched: i64,                          // 看过人数
+    metrics: Option<Arc<Mutex<MetricsEngine>>>, // 算弹幕速率的滑动窗口
+    revenue: BTreeMap<&'static str, i64>,  // 按来源统计的收入（千分之一元）
+    backlog: u64,                          // 待处理事件数
+    sink_dropped: BTreeMap<&'static str, u64>, // 按 sink 统计的丢弃事件数
+}
+
+// Per-room counters and gauges, rendered in the Prometheus text exposition format
+#[derive(Default)]
+pub struct Exporter {
+    rooms: Mutex<BTreeMap<String, RoomMetrics>>,
+}
+
+impl Exporter {
+    fn update<F: FnOnce(&mut RoomMetrics)>(&self, room_id: &str, f: F) {
+        let mut rooms = self.rooms.lock().expect("failed to lock exporter");
+
+        f(rooms.entry(room_id.into()).or_default());
+    }
+
+    pub fn inc_message(&self, room_id: &str, cmd: &str) {
+        self.update(room_id, |x| *x.messages.entry(cmd.into()).or_default() += 1);
+    }
+
+    pub fn inc_parse_failure(&self, room_id: &str, cmd: &str) {
+        self.update(room_id, |x| {
+            *x.parse_failures.entry(cmd.into()).or_default() += 1
+        });
+    }
+
+    pub fn inc_parse_warning(&self, room_id: &str, cmd: &str, field: &'static str) {
+        self.update(room_id, |x| {
+            *x.parse_warnings.entry((cmd.into(), field)).or_default() += 1
+        });
+    }
+
+    pub fn inc_reconnect(&self, room_id: &str) {
+        self.update(room_id, |x| x.reconnects += 1);
+    }
+
+    pub fn set_heartbeat_latency(&self, room_id: &str, latency: Duration) {
+        self.update(room_id, |x| x.heartbeat_latency = latency.as_secs_f64());
+    }
+
+    pub fn set_watched(&self, room_id: &str, count: i64) {
+        self.update(room_id, |x| x.watched = count);
+    }
+
+    // the window of the room's watcher, read on every scrape
+    pub fn set_metrics(&self, room_id: &str, metrics: Arc<Mutex<MetricsEngine>>) {
+        self.update(room_id, |x| x.metrics = Some(metrics));
+    }
+
+    pub fn add_revenue(&self, room_id: &str, source: &'static str, amount: i64) {
+        self.update(room_id, |x| *x.revenue.entry(source).or_default() += amount);
+    }
+
+    pub fn set_backlog(&self, room_id: &str, backlog: usize) {
+        self.update(room_id, |x| x.backlog = backlog as u64);
+    }
+
+    pub fn inc_sink_dropped(&self, room_id: &str, sink: &'static str) {
+        self.update(room_id, |x| *x.sink_dropped.entry(sink).or_default() += 1);
+    }
+
+    pub fn render(&self) -> String {
+        let rooms = self.rooms.lock().expect("failed to lock exporter");
+        let mut out = String::new();
+
+        let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{name}{{{labels}}} {value}");
+            }
+        };
+
+        let room = |room_id: &str| format!("room=\"{}\"", escape(room_id));
+
+        family(
+            "blivedm_messages_total",
+            "counter",
+            "Messages received by cmd.",
+            rooms
+                .iter()
+                .flat_map(|(room_id, x)| {
+                    x.messages.iter().map(move |(cmd, count)| {
+                        (
+                            format!("{},cmd=\"{}\"", room(room_id), escape(cmd)),
+                            count.to_string(),
+                        )
+                    })
+                })
+                .collect(),
+        );
+
+        family(
+            "blivedm_parse_failures_total",
+            "counter",
+            "Messages that failed to parse by cmd.",
+            rooms
+                .iter()
+                .flat_map(|(room_id, x)| {
+                    x.parse_failures.iter().map(move |(cmd, count)| {
+                        (
+                            format!("{},cmd=\"{}\"", room(room_id), escape(cmd)),
+                            count.to_string(),
+                        )
+                    })
+                })
+                .collect(),
+        );
+
+        family(
+            "blivedm_parse_warnings_total",
+            "counter",
+            "Message fields that fell back to degraded data by cmd and field.",
+            rooms
+                .iter()
+                .flat_map(|(room_id, x)| {
+                    x.parse_warnings.iter().map(move |((cmd, field), count)| {
+                        (
+                            format!(
+                                "{},cmd=\"{}\",field=\"{}\"",
+                                room(room_id),
+                                escape(cmd),
+                                escape(field)
+                            ),
+                            count.to_string(),
+                        )
+                    })
+                })
+                .collect(),
+        );
+
+        family(
+            "blivedm_reconnects_total",
+            "counter",
+            "Websocket reconnect attempts.",
+            rooms
+                .iter()
+                .map(|(room_id, x)| (room(room_id), x.reconnects.to_string()))
+                .collect(),
+        );
+
+        family(
+            "blivedm_heartbeat_latency_seconds",
+            "gauge",
+            "Round trip time of the last heartbeat, from sending it to its reply.",
+            rooms
+                .iter()
+                .map(|(room_id, x)| (room(room_id), x.heartbeat_latency.to_string()))
+                .collect(),
+        );
+
+        family(
+            "blivedm_watched",
+            "gauge",
+            "Viewers of the current broadcast (WATCHED_CHANGE).",
+            rooms
+                .iter()
+                .map(|(room_id, x)| (room(room_id), x.watched.to_string()))
+                .collect(),
+        );
+
+        family(
+            "blivedm_danmaku_per_minute",
+            "gauge",
+            "Danmaku per minute over the sliding window.",
+            rooms
+                .iter()
+                .filter_map(|(room_id, x)| {
+                    let metrics = x.metrics.as_ref()?;
+                    let snapshot = metrics.lock().expect("failed to lock metrics").snapshot();
+
+                    Some((room(room_id), snapshot.danmaku_rate.to_string()))
+                })
+                .collect(),
+        );
+
+        family(
+            "blivedm_revenue_yuan_total",
+            "counter",
+            "Paid revenue in CNY by source.",
+            rooms
+                .iter()
+                .flat_map(|(room_id, x)| {
+                    x.revenue.iter().map(move |(source, amount)| {
+                        (
+                            format!("{},source=\"{}\"", room(room_id), escape(source)),
+                            RevenueReport::yuan(*amount).to_string(),
+                        )
+                    })
+                })
+                .collect(),
+        );
+
+        family(
+            "blivedm_event_backlog",
+            "gauge",
+            "Client events waiting to be processed.",
+            rooms
+                .iter()
+                .map(|(room_id, x)| (room(room_id), x.backlog.to_string()))
+                .collect(),
+        );
+
+        family(
+            "blivedm_sink_dropped_total",
+            "counter",
+            "Events not delivered to a sink because its queue was full or it stopped.",
+            rooms
+                .iter()
+                .flat_map(|(room_id, x)| {
+                    x.sink_dropped.iter().map(move |(sink, count)| {
+                        (
+                            format!("{},sink=\"{}\"", room(room_id), escape(sink)),
+                            count.to_string(),
+                        )
+                    })
+                })
+                .collect(),
+        );
+
+        out
+    }
+}
+
+// https://prometheus.io/docs/instrumenting/exposition_formats/#text-format-details
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for ch in value.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            ch => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_escapes_labels() {
+        let exporter = Exporter::default();
+
+        exporter.inc_message("1", "DANMU_MSG");
+        exporter.inc_message("1", "DANMU_MSG");
+        exporter.inc_parse_failure("1", "BAD\"CMD\\\n");
+        exporter.inc_parse_warning("1", "DANMU_MSG", "user");
+        exporter.add_revenue("1", "super_chat", 30_000);
+        exporter.set_backlog("1", 3);
+
+        let output = exporter.render();
+
+        assert!(output.contains("# TYPE blivedm_messages_total counter\n"));
+        assert!(output.contains("blivedm_messages_total{room=\"1\",cmd=\"DANMU_MSG\"} 2\n"));
+        assert!(
+            output
+                .contains("blivedm_parse_failures_total{room=\"1\",cmd=\"BAD\\\"CMD\\\\\\n\"} 1\n")
+        );
+        assert!(output.contains(
+            "blivedm_parse_warnings_total{room=\"1\",cmd=\"DANMU_MSG\",field=\"user\"} 1\n"
+        ));
+        assert!(
+            output.contains("blivedm_revenue_yuan_total{room=\"1\",source=\"super_chat\"} 30\n")
+        );
+        assert!(output.contains("blivedm_event_backlog{room=\"1\"} 3\n"));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::live::message::{LiveMessage, UserInteractType};
+use crate::stats::revenue;
+use chrono::Local;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+const MINUTE: i64 = 60 * 1000;
+
+enum Sample {
+    Danmaku(u64),
+    Join,
+    Follow,
+    Share,
+    Like,
+    Coin(i64),
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub timestamp: i64,       // 快照时间（毫秒）
+    pub window: i64,          // 窗口长度（毫秒）
+    pub danmaku_rate: f64,    // 弹幕数 / 分钟
+    pub unique_chatters: u64, // 窗口内发言人数
+    pub joins: u64,           // 窗口内进房人数
+    pub follows: u64,         // 窗口内关注数
+    pub shares: u64,          // 窗口内分享数
+    pub likes: u64,           // 窗口内点赞数
+    pub coin_rate: f64,       // 收入 / 分钟（金瓜子）
+}
+
+// Sliding-window engagement counters, timed by arrival so server clock skew does not matter
+pub struct MetricsEngine {
+    window: i64,
+    samples: VecDeque<(i64, Sample)>,
+}
+
+impl MetricsEngine {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: window.as_millis() as i64,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn observe(&mut self, message: &LiveMessage) {
+        self.observe_at(message, Local::now().timestamp_millis());
+    }
+
+    fn observe_at(&mut self, message: &LiveMessage, now: i64) {
+        let sample = match message {
+            LiveMessage::Danmaku { user, .. } => Sample::Danmaku(user.uid()),
+            LiveMessage::Like { .. } => Sample::Like,
+            LiveMessage::UserInteract { msg_type, .. } => match msg_type {
+                UserInteractType::JoinRoom => Sample::Join,
+                UserInteractType::Subscribe => Sample::Follow,
+                UserInteractType::Share => Sample::Share,
+            },
+            message => match revenue::value_of(message) {
+                0 => return,
+                value => Sample::Coin(value),
+            },
+        };
+
+        self.samples.push_back((now, sample));
+        self.evict(now);
+    }
+
+    pub fn snapshot(&mut self) -> MetricsSnapshot {
+        self.snapshot_at(Local::now().timestamp_millis())
+    }
+
+    fn snapshot_at(&mut self, now: i64) -> MetricsSnapshot {
+        self.evict(now);
+
+        let mut snapshot = MetricsSnapshot {
+            timestamp: now,
+            window: self.window,
+            ..Default::default()
+        };
+
+        let mut danmaku = 0;
+        let mut coins = 0;
+        let mut chatters = HashSet::new();
+
+        for (_, sample) in &self.samples {
+            match sample {
+                Sample::Danmaku(uid) => {
+                    danmaku += 1;
+                    chatters.insert(*uid);
+                }
+                Sample::Join => snapshot.joins += 1,
+                Sample::Follow => snapshot.follows += 1,
+                Sample::Share => snapshot.shares += 1,
+                Sample::Like => snapshot.likes += 1,
+                Sample::Coin(value) => coins += value,
+            }
+        }
+
+        let minutes = self.window as f64 / MINUTE as f64;
+
+        snapshot.danmaku_rate = danmaku as f64 / minutes;
+        snapshot.unique_chatters = chatters.len() as u64;
+        snapshot.coin_rate = coins as f64 / minutes;
+
+        snapshot
+    }
+
+    fn evict(&mut self, now: i64) {
+        while let Some((ts, _)) = self.samples.front() {
+            if now - ts < self.window {
+                break;
+            }
+
+            self.samples.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::message::RawMessage;
+    use serde_json::Value;
+
+    // the old client format, its sender is only in info[2]
+    fn danmaku(uid: u64) -> LiveMessage {
+        let mut data: Value = serde_json::from_str(include_str!(
+            "../live/message/fixtures/danmu_msg-old_client.json"
+        ))
+        .unwrap();
+        *data.pointer_mut("/info/2/0").unwrap() = uid.into();
+
+        LiveMessage::parse(&RawMessage::new("21452505", data))
+            .unwrap()
+            .message
+    }
+
+    fn parse(fixture: &str) -> LiveMessage {
+        let raw = RawMessage::new("21452505", serde_json::from_str(fixture).unwrap());
+        LiveMessage::parse(&raw).unwrap().message
+    }
+
+    #[test]
+    fn counts_unique_chatters_in_the_window() {
+        let mut engine = MetricsEngine::new(Duration::from_secs(120));
+
+        engine.observe_at(&danmaku(1), 0);
+        engine.observe_at(&danmaku(2), 10_000);
+        engine.observe_at(&danmaku(1), 20_000);
+        engine.observe_at(
+            &parse(include_str!("../live/message/fixtures/guard_buy.json")),
+            30_000,
+        );
+        engine.observe_at(
+            &parse(include_str!(
+                "../live/message/fixtures/interact_word_v2-join.json"
+            )),
+            30_000,
+        );
+        // not engagement, ignored
+        engine.observe_at(
+            &parse(include_str!("../live/message/fixtures/watched_change.json")),
+            30_000,
+        );
+
+        let snapshot = engine.snapshot_at(60_000);
+
+        assert_eq!(snapshot.window, 120_000);
+        assert_eq!(snapshot.unique_chatters, 2);
+        assert_eq!(snapshot.joins, 1);
+        // rates are per minute whatever the window is
+        assert_eq!(snapshot.danmaku_rate, 1.5);
+        assert_eq!(snapshot.coin_rate, 99_000.0);
+    }
+
+    #[test]
+    fn forgets_samples_older_than_the_window() {
+        let mut engine = MetricsEngine::new(Duration::from_secs(60));
+
+        engine.observe_at(&danmaku(1), 0);
+        engine.observe_at(&danmaku(2), 30_000);
+
+        assert_eq!(engine.snapshot_at(59_999).unique_chatters, 2);
+
+        // the first one is exactly a window old
+        let snapshot = engine.snapshot_at(60_000);
+
+        assert_eq!((snapshot.unique_chatters, snapshot.danmaku_rate), (1, 1.0));
+
+        // a sample arriving also evicts
+        engine.observe_at(&danmaku(3), 90_000);
+
+        assert_eq!(engine.samples.len(), 1);
+        assert_eq!(engine.snapshot_at(150_000).unique_chatters, 0);
+    }
+}
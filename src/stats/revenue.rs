@@ -0,0 +1,365 @@
+use crate::data::PROJECT_DIRS;
+use crate::data::database::LivePersist;
+use crate::live::message::{LiveMessage, RawMessage, UserInfo};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+const TOP_SPENDERS: usize = 10;
+
+// All amounts are in 1/1000 CNY, which is exactly one gold coin (金瓜子).
+// Silver coins (银瓜子) can not be bought, they have no cash value and are counted apart
+// as `uncounted_silver`, gifts paid with them show up with a total of 0.
+const MILLI_PER_YUAN: i64 = 1000;
+
+// cash value of a message, zero for anything that is not paid
+pub fn value_of(message: &LiveMessage) -> i64 {
+    paid_value(message).unwrap_or(0)
+}
+
+// `None` for the messages nobody paid for, silver gifts included
+pub fn paid_value(message: &LiveMessage) -> Option<i64> {
+    match message {
+        LiveMessage::Gift {
+            coin_type,
+            total_coin,
+            ..
+        } if coin_type == "gold" => Some(*total_coin),
+        LiveMessage::SuperChat { price, .. } => Some(price * MILLI_PER_YUAN),
+        LiveMessage::GuardBuy { count, price, .. } => Some(price * count),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct UserSpend {
+    pub uid: u64,
+    pub uname: String,
+    pub total: i64,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct GiftSpend {
+    pub name: String,
+    pub count: i64,
+    pub total: i64,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RevenueReport {
+    pub room_id: String,
+    pub session_id: Option<i64>,
+    pub total: i64,            // 总收入
+    pub gift: i64,             // 礼物收入
+    pub super_chat: i64,       // 醒目留言收入
+    pub guard: i64,            // 上舰收入
+    pub uncounted_silver: i64, // 银瓜子数量，没有现金价值，不计入收入
+    pub top_spenders: Vec<UserSpend>,
+    pub users: Vec<UserSpend>,
+    pub gifts: Vec<GiftSpend>,
+}
+
+impl RevenueReport {
+    pub fn yuan(amount: i64) -> f64 {
+        amount as f64 / MILLI_PER_YUAN as f64
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    gift: i64,
+    super_chat: i64,
+    guard: i64,
+    uncounted_silver: i64,
+    users: HashMap<u64, UserSpend>,
+    gifts: HashMap<String, GiftSpend>,
+}
+
+impl Totals {
+    // senders that could not be parsed all share uid 0, they count towards the totals only
+    fn add_user(&mut self, user: &UserInfo, amount: i64) {
+        if user.is_anonymous() {
+            return;
+        }
+
+        let spend = self.users.entry(user.uid()).or_default();
+
+        spend.uid = user.uid();
+        spend.uname = user.uname().into();
+        spend.total += amount;
+    }
+
+    fn add_gift(&mut self, name: &str, count: i64, amount: i64) {
+        let spend = self.gifts.entry(name.into()).or_default();
+
+        spend.name = name.into();
+        spend.count += count;
+        spend.total += amount;
+    }
+}
+
+// Totals the revenue of the current broadcast session of one room
+pub struct RevenueAggregator {
+    room_id: String,
+    session_id: Option<i64>,
+    totals: Totals,
+}
+
+impl RevenueAggregator {
+    pub fn new(room_id: &str) -> Self {
+        Self {
+            room_id: room_id.into(),
+            session_id: None,
+            totals: Totals::default(),
+        }
+    }
+
+    // rebuilds the totals of a session already in progress from the stored events
+    pub fn resume(room_id: &str, session_id: i64, persist: &LivePersist) -> Result<Self> {
+        let mut aggregator = Self::new(room_id);
+
+        for event in persist.session_events(session_id)? {
+            let raw = RawMessage::new(room_id, event.data);
+
+            if let Ok(message) = LiveMessage::try_from(&raw) {
+                let _ = aggregator.observe(Some(session_id), &message);
+            }
+        }
+
+        Ok(aggregator)
+    }
+
+    // returns the report of the previous session when the message belongs to another one,
+    // revenue outside of a session is not attributed to any report
+    pub fn observe(
+        &mut self,
+        session_id: Option<i64>,
+        message: &LiveMessage,
+    ) -> Option<RevenueReport> {
+        let finished = if session_id != self.session_id {
+            self.finish()
+        } else {
+            None
+        };
+
+        self.session_id = session_id;
+
+        if session_id.is_none() {
+            return finished;
+        }
+
+        let totals = &mut self.totals;
+
+        match message {
+            LiveMessage::Gift {
+                user,
+                gift_name,
+                gift_count,
+                coin_type,
+                total_coin,
+                ..
+            } => {
+                if coin_type == "gold" {
+                    totals.gift += total_coin;
+                    totals.add_user(user, *total_coin);
+                    totals.add_gift(gift_name, *gift_count, *total_coin);
+                } else {
+                    totals.uncounted_silver += total_coin;
+                    totals.add_gift(gift_name, *gift_count, 0);
+                }
+            }
+            LiveMessage::SuperChat { user, .. } => {
+                let amount = value_of(message);
+
+                totals.super_chat += amount;
+                totals.add_user(user, amount);
+            }
+            LiveMessage::GuardBuy {
+                user,
+                guard_name,
+                count,
+                ..
+            } => {
+                let amount = value_of(message);
+
+                totals.guard += amount;
+                totals.add_user(user, amount);
+                totals.add_gift(guard_name, *count, amount);
+            }
+            _ => (),
+        }
+
+        finished
+    }
+
+    pub fn report(&self) -> RevenueReport {
+        let totals = &self.totals;
+
+        let mut users: Vec<_> = totals.users.values().cloned().collect();
+        users.sort_by(|a, b| b.total.cmp(&a.total).then(a.uid.cmp(&b.uid)));
+
+        let mut gifts: Vec<_> = totals.gifts.values().cloned().collect();
+        gifts.sort_by(|a, b| b.total.cmp(&a.total).then(a.name.cmp(&b.name)));
+
+        RevenueReport {
+            room_id: self.room_id.clone(),
+            session_id: self.session_id,
+            total: totals.gift + totals.super_chat + totals.guard,
+            gift: totals.gift,
+            super_chat: totals.super_chat,
+            guard: totals.guard,
+            uncounted_silver: totals.uncounted_silver,
+            top_spenders: users.iter().take(TOP_SPENDERS).cloned().collect(),
+            users,
+            gifts,
+        }
+    }
+
+    // ends the current session and returns its report
+    pub fn finish(&mut self) -> Option<RevenueReport> {
+        self.session_id?;
+
+        let report = self.report();
+
+        self.session_id = None;
+        self.totals = Totals::default();
+
+        Some(report)
+    }
+}
+
+pub fn write_report(report: &RevenueReport) -> Result<PathBuf> {
+    let dir = PROJECT_DIRS
+        .data_dir()
+        .join(&report.room_id)
+        .join("reports");
+
+    fs::create_dir_all(&dir)?;
+
+    let file = dir.join(format!(
+        "revenue-{}.json",
+        report.session_id.unwrap_or_default()
+    ));
+
+    fs::write(&file, serde_json::to_string_pretty(report)?)?;
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::message::{Timestamp, UserInfo};
+
+    fn parse(fixture: &str) -> LiveMessage {
+        let raw = RawMessage::new("21452505", serde_json::from_str(fixture).unwrap());
+        LiveMessage::parse(&raw).unwrap().message
+    }
+
+    fn gift(uid: u64, total_coin: i64) -> LiveMessage {
+        LiveMessage::Gift {
+            timestamp: Timestamp::new_server(1700000000),
+            user: UserInfo::new(
+                Some(uid),
+                Some(format!("user{uid}")),
+                None::<&str>,
+                None,
+                None,
+                None,
+            )
+            .unwrap(),
+            gift_name: "小花花".into(),
+            gift_count: total_coin / 100,
+            coin_type: "gold".into(),
+            total_coin,
+            img_basic: None,
+            img_webp: None,
+        }
+    }
+
+    #[test]
+    fn breaks_revenue_down_per_session() {
+        let mut aggregator = RevenueAggregator::new("21452505");
+
+        // outside of a session nothing is attributed
+        assert!(aggregator.observe(None, &gift(1, 100)).is_none());
+
+        for fixture in [
+            include_str!("../live/message/fixtures/send_gift.json"),
+            include_str!("../live/message/fixtures/send_gift-legacy.json"),
+            include_str!("../live/message/fixtures/super_chat_message.json"),
+            include_str!("../live/message/fixtures/guard_buy.json"),
+        ] {
+            assert!(aggregator.observe(Some(1), &parse(fixture)).is_none());
+        }
+
+        let report = aggregator.observe(Some(2), &gift(1, 100)).unwrap();
+
+        assert_eq!(report.session_id, Some(1));
+        assert_eq!(
+            (report.total, report.gift, report.super_chat, report.guard),
+            (228_200, 200, 30_000, 198_000)
+        );
+        assert_eq!(report.uncounted_silver, 1000);
+
+        let users: Vec<_> = report.users.iter().map(|x| (x.uid, x.total)).collect();
+        assert_eq!(users, [(1732050807, 198_000), (3141592653, 30_200)]);
+
+        let gifts: Vec<_> = report
+            .gifts
+            .iter()
+            .map(|x| (x.name.as_str(), x.count, x.total))
+            .collect();
+        assert_eq!(
+            gifts,
+            [("舰长", 1, 198_000), ("小花花", 2, 200), ("辣条", 10, 0)]
+        );
+
+        // the next session starts from scratch
+        let report = aggregator.finish().unwrap();
+
+        assert_eq!((report.session_id, report.total), (Some(2), 100));
+        assert!(aggregator.finish().is_none());
+    }
+
+    #[test]
+    fn ranks_top_spenders() {
+        let mut aggregator = RevenueAggregator::new("21452505");
+
+        for uid in 1..=12 {
+            aggregator.observe(Some(1), &gift(uid, (uid as i64 % 6 + 1) * 100));
+        }
+
+        aggregator.observe(Some(1), &gift(7, 1000));
+
+        // an unparsed sender is part of the total but not ranked
+        aggregator.observe(Some(1), &gift(0, 100_000));
+
+        let report = aggregator.report();
+        let top: Vec<_> = report
+            .top_spenders
+            .iter()
+            .map(|x| (x.uid, x.total))
+            .collect();
+
+        assert_eq!(report.total, 105_200);
+        assert_eq!(report.users.len(), 12);
+        assert_eq!(
+            top,
+            [
+                (7, 1200),
+                (5, 600),
+                (11, 600),
+                (4, 500),
+                (10, 500),
+                (3, 400),
+                (9, 400),
+                (2, 300),
+                (8, 300),
+                (1, 200)
+            ]
+        );
+    }
+}
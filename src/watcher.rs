@@ -0,0 +1,238 @@
+use crate::config::SinkConfig;
+use crate::data::database::LivePersist;
+use crate::data::quarantine::Quarantine;
+use crate::live::message::{LiveMessage, Parsed, RawMessage};
+use crate::live::session::SessionTracker;
+use crate::server::hub::HUB;
+use crate::sink::SinkEvent;
+use crate::sink::dispatcher::Dispatcher;
+use crate::stats::exporter::EXPORTER;
+use crate::stats::metrics::MetricsEngine;
+use crate::stats::revenue;
+use crate::stats::revenue::{RevenueAggregator, RevenueReport};
+use anyhow::Result;
+use log::{debug, error, info, trace, warn};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Per-room state fed by every message the client receives
+pub struct RoomWatcher {
+    room_id: String,
+    sinks: Dispatcher,
+    persist: LivePersist,
+    quarantine: Quarantine,
+    sessions: SessionTracker,
+    revenue: RevenueAggregator,
+    metrics: Arc<Mutex<MetricsEngine>>, // 与 HUB、EXPORTER 共享，请求时再计算
+}
+
+impl RoomWatcher {
+    pub fn new(room_id: &str, sinks: &[SinkConfig], metrics_window: Duration) -> Result<Self> {
+        let persist = LivePersist::new(&LivePersist::path())?;
+        let sessions = SessionTracker::new(room_id, &persist)?;
+
+        let revenue = match sessions.current() {
+            Some(session_id) => RevenueAggregator::resume(room_id, session_id, &persist)?,
+            None => RevenueAggregator::new(room_id),
+        };
+
+        let metrics = Arc::new(Mutex::new(MetricsEngine::new(metrics_window)));
+
+        HUB.register(room_id);
+        HUB.set_metrics(room_id, metrics.clone());
+        EXPORTER.set_metrics(room_id, metrics.clone());
+
+        if sessions.current().is_some() {
+            HUB.set_revenue(room_id, revenue.report());
+        }
+
+        Ok(Self {
+            room_id: room_id.into(),
+            sinks: Dispatcher::from_config(room_id, sinks)?,
+            persist,
+            quarantine: Quarantine::new(&Quarantine::path()),
+            sessions,
+            revenue,
+            metrics,
+        })
+    }
+
+    pub fn handle_message(&mut self, raw: RawMessage) {
+        // nothing can be done with a packet without a cmd but keeping it for later
+        let msg_type = match raw.msg_type() {
+            Ok(msg_type) => msg_type.to_owned(),
+            Err(err) => {
+                warn!("quarantined undecodable packet: {err}");
+                EXPORTER.inc_parse_failure(&self.room_id, "");
+
+                if let Err(err) = self.quarantine.put(&raw, &err) {
+                    error!("failed to quarantine packet: {err:?}");
+                }
+
+                return;
+            }
+        };
+
+        EXPORTER.inc_message(&self.room_id, &msg_type);
+
+        match LiveMessage::parse(&raw) {
+            Ok(Parsed { message, warnings }) => {
+                for warning in &warnings {
+                    EXPORTER.inc_parse_warning(&self.room_id, &msg_type, warning.field);
+                }
+
+                if !warnings.is_empty() {
+                    let warnings: Vec<_> = warnings.iter().map(|x| x.to_string()).collect();
+                    warn!("degraded {msg_type}: {}", warnings.join(", "));
+                }
+
+                let session_id = match &message {
+                    LiveMessage::Unsupported(msg_type) => {
+                        trace!("unsupported message type: {}", msg_type);
+                        self.sessions.current()
+                    }
+                    message => {
+                        debug!("{message:?}");
+                        self.process(message)
+                    }
+                };
+
+                let event = Arc::new(SinkEvent {
+                    session_id,
+                    message,
+                    raw,
+                });
+
+                HUB.publish(&self.room_id, &event);
+                self.sinks.dispatch(event);
+            }
+            Err(msg) => {
+                error!("failed to parse message: {:?}", msg);
+                EXPORTER.inc_parse_failure(&self.room_id, &msg_type);
+
+                if let Err(err) = self.quarantine.put(&raw, &msg) {
+                    error!("failed to quarantine message: {err:?}");
+                }
+
+                self.sinks.dispatch_unparsed(raw);
+            }
+        }
+    }
+
+    // a message body that is not JSON, nothing but the quarantine can take it
+    pub fn handle_undecodable(&mut self, body: &[u8], err: serde_json::Error) {
+        warn!("quarantined message that is not JSON: {err}");
+        EXPORTER.inc_parse_failure(&self.room_id, "");
+
+        if let Err(err) = self.quarantine.put_body(&self.room_id, body, &err.into()) {
+            error!("failed to quarantine message: {err:?}");
+        }
+    }
+
+    // updates the room state and returns the session the message belongs to
+    fn process(&mut self, message: &LiveMessage) -> Option<i64> {
+        let session_id = match self.sessions.observe(&self.persist, message) {
+            Ok(session_id) => session_id,
+            Err(err) => {
+                error!("failed to track session: {err:?}");
+                self.sessions.current()
+            }
+        };
+
+        HUB.observe(&self.room_id, session_id, message);
+
+        if let Some(report) = self.revenue.observe(session_id, message) {
+            report_revenue(&report);
+        }
+
+        if self.sessions.current().is_none()
+            && let Some(report) = self.revenue.finish()
+        {
+            report_revenue(&report);
+        }
+
+        self.metrics
+            .lock()
+            .expect("failed to lock metrics")
+            .observe(message);
+
+        match message {
+            LiveMessage::WatchedChange { count, .. } => EXPORTER.set_watched(&self.room_id, *count),
+            message => match revenue::value_of(message) {
+                0 => (),
+                value => EXPORTER.add_revenue(&self.room_id, message.kind(), value),
+            },
+        }
+
+        session_id
+    }
+
+    // the live values are read from the window on request, this only keeps a history.
+    // The revenue report ranks every spender, so the hub gets a fresh one here as well
+    // rather than on every paid message.
+    pub fn snapshot_metrics(&mut self) {
+        if self.sessions.current().is_some() {
+            HUB.set_revenue(&self.room_id, self.revenue.report());
+        }
+
+        let snapshot = self
+            .metrics
+            .lock()
+            .expect("failed to lock metrics")
+            .snapshot();
+
+        debug!("[{}] {snapshot:?}", self.room_id);
+
+        if let Err(err) =
+            self.persist
+                .insert_metrics(&self.room_id, self.sessions.current(), &snapshot)
+        {
+            error!("failed to persist metrics: {err:?}");
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.sinks.flush();
+    }
+
+    // finishes the events still queued for the sinks
+    pub async fn close(self) {
+        self.sinks.close().await;
+    }
+}
+
+fn report_revenue(report: &RevenueReport) {
+    HUB.set_revenue(&report.room_id, report.clone());
+
+    info!(
+        "[{}] session {:?} revenue: {:.2} CNY (gift {:.2}, super chat {:.2}, guard {:.2})",
+        report.room_id,
+        report.session_id,
+        RevenueReport::yuan(report.total),
+        RevenueReport::yuan(report.gift),
+        RevenueReport::yuan(report.super_chat),
+        RevenueReport::yuan(report.guard),
+    );
+
+    if report.uncounted_silver > 0 {
+        info!(
+            "  {} silver coins not counted, they have no cash value",
+            report.uncounted_silver
+        );
+    }
+
+    for (rank, spender) in report.top_spenders.iter().enumerate() {
+        info!(
+            "  #{} {} ({}): {:.2} CNY",
+            rank + 1,
+            spender.uname,
+            spender.uid,
+            RevenueReport::yuan(spender.total)
+        );
+    }
+
+    match revenue::write_report(report) {
+        Ok(file) => info!("revenue report written to {}", file.display()),
+        Err(err) => error!("failed to write revenue report: {err:?}"),
+    }
+}
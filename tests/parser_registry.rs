@@ -0,0 +1,64 @@
+use anyhow::Result;
+use blivedm_rs::filter::Filter;
+use blivedm_rs::live::message::registry::{PARSERS, ParserInfo};
+use blivedm_rs::live::message::{Fallbacks, LiveMessage, RawMessage, Timestamp, UserInfo};
+use serde_json::json;
+
+// a cmd the crate does not parse, handled by a parser registered from outside it
+fn dm_interaction(message: &RawMessage, fallbacks: &mut Fallbacks) -> Result<LiveMessage> {
+    Ok(LiveMessage::Custom {
+        timestamp: Timestamp::new_local(),
+        cmd: "DM_INTERACTION".into(),
+        user: Some(fallbacks.user(&[&|| {
+            UserInfo::new(
+                message["data"]["uid"].as_u64(),
+                message["data"]["uname"].as_str(),
+                None::<&str>,
+                None,
+                None,
+                None,
+            )
+        }])),
+        data: message["data"].clone(),
+    })
+}
+
+#[test]
+fn registers_a_parser_for_an_unknown_cmd() {
+    let raw = RawMessage::new(
+        "21452505",
+        json!({"cmd": "DM_INTERACTION", "data": {"uid": 7, "uname": "alice"}}),
+    );
+
+    assert!(matches!(
+        LiveMessage::try_from(&raw).unwrap(),
+        LiveMessage::Unsupported(_)
+    ));
+
+    PARSERS.register(
+        ParserInfo {
+            cmd: "DM_INTERACTION",
+            version: 1,
+            samples: &[],
+        },
+        dm_interaction,
+    );
+
+    let parsed = LiveMessage::parse(&raw).unwrap();
+
+    assert_eq!(parsed.message.kind(), "custom");
+    assert_eq!(parsed.message.user().map(|x| x.uid()), Some(7));
+    assert!(
+        Filter::parse("type == custom && cmd == DM_INTERACTION && uid == 7")
+            .unwrap()
+            .matches("21452505", &parsed.message)
+    );
+
+    let json = parsed.message.to_json("21452505").unwrap().unwrap();
+
+    assert_eq!(json["type"], "custom");
+    assert_eq!(json["cmd"], "DM_INTERACTION");
+    assert_eq!(json["data"]["uname"], "alice");
+    assert_eq!(parsed.warnings, []);
+    assert!(PARSERS.parsers().iter().any(|x| x.cmd == "DM_INTERACTION"));
+}